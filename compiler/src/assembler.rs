@@ -0,0 +1,702 @@
+//! Instructions of the compiled code, encoded in natural byte order from their operands written
+//! in the order of assembly: `lw(A0, 4, SP)` for `lw a0, 4(sp)`. Everything is `const` so the
+//! instruction sequences of the primitives and routines are assembled at build time.
+
+use crate::disassembler::{Condition, Instruction, Operation, Width};
+
+pub const ZERO: u32 = 0;
+pub const RA: u32 = 1;
+pub const SP: u32 = 2;
+pub const T0: u32 = 5;
+pub const T1: u32 = 6;
+pub const T2: u32 = 7;
+/// Points at the free cell on top of the return stack.
+pub const FP: u32 = 8;
+pub const S1: u32 = 9;
+pub const A0: u32 = 10;
+pub const A1: u32 = 11;
+pub const A2: u32 = 12;
+pub const A3: u32 = 13;
+pub const A4: u32 = 14;
+pub const A5: u32 = 15;
+
+pub const BEQ: u32 = 0b000;
+pub const BNE: u32 = 0b001;
+pub const BLT: u32 = 0b100;
+pub const BGE: u32 = 0b101;
+pub const BLTU: u32 = 0b110;
+pub const BGEU: u32 = 0b111;
+
+const LOAD: u32 = 0b0000011;
+const STORE: u32 = 0b0100011;
+const OP_IMM: u32 = 0b0010011;
+const OP: u32 = 0b0110011;
+const LUI: u32 = 0b0110111;
+const AUIPC: u32 = 0b0010111;
+const BRANCH: u32 = 0b1100011;
+const JAL: u32 = 0b1101111;
+const JALR: u32 = 0b1100111;
+
+const MISC_MEM: u32 = 0b0001111;
+const SYSTEM: u32 = 0b1110011;
+
+const ALTERNATE: u32 = 0b0100000;
+const MULDIV: u32 = 0b0000001;
+
+const fn r_type(funct7: u32, funct3: u32, rd: u32, rs1: u32, rs2: u32) -> u32 {
+    funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | OP
+}
+
+const fn i_type(op: u32, funct3: u32, rd: u32, rs1: u32, imm: i32) -> u32 {
+    (imm as u32 & 0xfff) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | op
+}
+
+const fn s_type(funct3: u32, rs2: u32, imm: i32, rs1: u32) -> u32 {
+    let imm = imm as u32;
+    (imm >> 5 & 0x7f) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | (imm & 0x1f) << 7 | STORE
+}
+
+/// Unwraps the encoding of a branch or jump whose offset is known to fit, failing the build
+/// when used in a constant otherwise.
+const fn fits(instruction: Option<u32>) -> u32 {
+    match instruction {
+        Some(instruction) => instruction,
+        None => panic!("offset out of range"),
+    }
+}
+
+/// Encodes `instruction` as decoded by [`Instruction::decode`], `None` if it has no encoding
+/// or its offset doesn't fit in the immediate. Fences order all accesses, whatever their decoded
+/// form ordered.
+pub const fn encode(instruction: Instruction) -> Option<u32> {
+    let encoded = match instruction {
+        Instruction::Lui { rd, imm } => lui(rd, imm),
+        Instruction::Auipc { rd, imm } => auipc(rd, imm),
+        Instruction::Jal { rd, offset } => return jal(rd, offset),
+        Instruction::Jalr { rd, rs1, offset } => jalr(rd, offset, rs1),
+        Instruction::Branch {
+            condition,
+            rs1,
+            rs2,
+            offset,
+        } => {
+            let funct3 = match condition {
+                Condition::Eq => BEQ,
+                Condition::Ne => BNE,
+                Condition::Lt => BLT,
+                Condition::Ge => BGE,
+                Condition::Ltu => BLTU,
+                Condition::Geu => BGEU,
+            };
+            return branch(funct3, rs1, rs2, offset);
+        }
+        Instruction::Load {
+            width,
+            rd,
+            rs1,
+            offset,
+        } => {
+            let funct3 = match width {
+                Width::Byte => 0b000,
+                Width::Half => 0b001,
+                Width::Word => 0b010,
+                Width::ByteUnsigned => 0b100,
+                Width::HalfUnsigned => 0b101,
+            };
+            i_type(LOAD, funct3, rd, rs1, offset)
+        }
+        Instruction::Store {
+            width,
+            rs1,
+            rs2,
+            offset,
+        } => {
+            let funct3 = match width {
+                Width::Byte => 0b000,
+                Width::Half => 0b001,
+                Width::Word => 0b010,
+                Width::ByteUnsigned | Width::HalfUnsigned => return None,
+            };
+            s_type(funct3, rs2, offset, rs1)
+        }
+        Instruction::Immediate {
+            operation,
+            rd,
+            rs1,
+            imm,
+        } => {
+            let funct3 = match operation {
+                Operation::Sll => return Some(slli(rd, rs1, imm as u32)),
+                Operation::Srl => return Some(srli(rd, rs1, imm as u32)),
+                Operation::Sra => return Some(srai(rd, rs1, imm as u32)),
+                Operation::Add => 0b000,
+                Operation::Slt => 0b010,
+                Operation::Sltu => 0b011,
+                Operation::Xor => 0b100,
+                Operation::Or => 0b110,
+                Operation::And => 0b111,
+                _ => return None,
+            };
+            i_type(OP_IMM, funct3, rd, rs1, imm)
+        }
+        Instruction::Register {
+            operation,
+            rd,
+            rs1,
+            rs2,
+        } => {
+            let (funct7, funct3) = match operation {
+                Operation::Add => (0, 0b000),
+                Operation::Sub => (ALTERNATE, 0b000),
+                Operation::Sll => (0, 0b001),
+                Operation::Slt => (0, 0b010),
+                Operation::Sltu => (0, 0b011),
+                Operation::Xor => (0, 0b100),
+                Operation::Srl => (0, 0b101),
+                Operation::Sra => (ALTERNATE, 0b101),
+                Operation::Or => (0, 0b110),
+                Operation::And => (0, 0b111),
+                Operation::Mul => (MULDIV, 0b000),
+                Operation::Mulh => (MULDIV, 0b001),
+                Operation::Mulhsu => (MULDIV, 0b010),
+                Operation::Mulhu => (MULDIV, 0b011),
+                Operation::Div => (MULDIV, 0b100),
+                Operation::Divu => (MULDIV, 0b101),
+                Operation::Rem => (MULDIV, 0b110),
+                Operation::Remu => (MULDIV, 0b111),
+            };
+            r_type(funct7, funct3, rd, rs1, rs2)
+        }
+        Instruction::Fence => 0x0ff0_0000 | MISC_MEM,
+        Instruction::Ecall => SYSTEM,
+        Instruction::Ebreak => 1 << 20 | SYSTEM,
+    };
+    Some(encoded)
+}
+
+/// `lui rd, imm`, loading `imm << 12`.
+pub const fn lui(rd: u32, imm: u32) -> u32 {
+    imm << 12 | rd << 7 | LUI
+}
+
+pub const fn auipc(rd: u32, imm: u32) -> u32 {
+    imm << 12 | rd << 7 | AUIPC
+}
+
+/// `jal rd, offset`, `None` if the offset doesn't fit in the immediate.
+pub const fn jal(rd: u32, offset: i32) -> Option<u32> {
+    if offset < -(1 << 20) || offset >= 1 << 20 {
+        return None;
+    }
+    let imm = offset as u32;
+    Some(
+        (imm >> 20 & 0x1) << 31
+            | (imm >> 1 & 0x3ff) << 21
+            | (imm >> 11 & 0x1) << 20
+            | (imm >> 12 & 0xff) << 12
+            | rd << 7
+            | JAL,
+    )
+}
+
+/// `jalr rd, offset(rs1)`
+pub const fn jalr(rd: u32, offset: i32, rs1: u32) -> u32 {
+    i_type(JALR, 0b000, rd, rs1, offset)
+}
+
+/// The conditional branch `funct3` `offset` bytes away, `None` if the offset doesn't fit in
+/// the immediate.
+pub const fn branch(funct3: u32, rs1: u32, rs2: u32, offset: i32) -> Option<u32> {
+    if offset < -(1 << 12) || offset >= 1 << 12 {
+        return None;
+    }
+    let imm = offset as u32;
+    Some(
+        (imm >> 12 & 0x1) << 31
+            | (imm >> 5 & 0x3f) << 25
+            | rs2 << 20
+            | rs1 << 15
+            | funct3 << 12
+            | (imm >> 1 & 0xf) << 8
+            | (imm >> 11 & 0x1) << 7
+            | BRANCH,
+    )
+}
+
+pub const fn beq(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    fits(branch(BEQ, rs1, rs2, offset))
+}
+
+pub const fn bne(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    fits(branch(BNE, rs1, rs2, offset))
+}
+
+pub const fn blt(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    fits(branch(BLT, rs1, rs2, offset))
+}
+
+pub const fn bltu(rs1: u32, rs2: u32, offset: i32) -> u32 {
+    fits(branch(BLTU, rs1, rs2, offset))
+}
+
+/// `lw rd, offset(rs1)`
+pub const fn lw(rd: u32, offset: i32, rs1: u32) -> u32 {
+    i_type(LOAD, 0b010, rd, rs1, offset)
+}
+
+/// `lbu rd, offset(rs1)`
+pub const fn lbu(rd: u32, offset: i32, rs1: u32) -> u32 {
+    i_type(LOAD, 0b100, rd, rs1, offset)
+}
+
+/// `sw rs2, offset(rs1)`
+pub const fn sw(rs2: u32, offset: i32, rs1: u32) -> u32 {
+    s_type(0b010, rs2, offset, rs1)
+}
+
+pub const fn addi(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(OP_IMM, 0b000, rd, rs1, imm)
+}
+
+pub const fn sltiu(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(OP_IMM, 0b011, rd, rs1, imm)
+}
+
+pub const fn ori(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(OP_IMM, 0b110, rd, rs1, imm)
+}
+
+pub const fn andi(rd: u32, rs1: u32, imm: i32) -> u32 {
+    i_type(OP_IMM, 0b111, rd, rs1, imm)
+}
+
+pub const fn slli(rd: u32, rs1: u32, shamt: u32) -> u32 {
+    i_type(OP_IMM, 0b001, rd, rs1, (shamt & 0x1f) as i32)
+}
+
+pub const fn srli(rd: u32, rs1: u32, shamt: u32) -> u32 {
+    i_type(OP_IMM, 0b101, rd, rs1, (shamt & 0x1f) as i32)
+}
+
+pub const fn srai(rd: u32, rs1: u32, shamt: u32) -> u32 {
+    i_type(
+        OP_IMM,
+        0b101,
+        rd,
+        rs1,
+        (ALTERNATE << 5 | shamt & 0x1f) as i32,
+    )
+}
+
+pub const fn add(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0, 0b000, rd, rs1, rs2)
+}
+
+pub const fn sub(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(ALTERNATE, 0b000, rd, rs1, rs2)
+}
+
+pub const fn sll(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0, 0b001, rd, rs1, rs2)
+}
+
+pub const fn slt(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0, 0b010, rd, rs1, rs2)
+}
+
+pub const fn sltu(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0, 0b011, rd, rs1, rs2)
+}
+
+pub const fn xor(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0, 0b100, rd, rs1, rs2)
+}
+
+pub const fn srl(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0, 0b101, rd, rs1, rs2)
+}
+
+pub const fn or(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0, 0b110, rd, rs1, rs2)
+}
+
+pub const fn and(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(0, 0b111, rd, rs1, rs2)
+}
+
+pub const fn mul(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(MULDIV, 0b000, rd, rs1, rs2)
+}
+
+pub const fn mulhu(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(MULDIV, 0b011, rd, rs1, rs2)
+}
+
+pub const fn div(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(MULDIV, 0b100, rd, rs1, rs2)
+}
+
+pub const fn rem(rd: u32, rs1: u32, rs2: u32) -> u32 {
+    r_type(MULDIV, 0b110, rd, rs1, rs2)
+}
+
+/// `addi rd, zero, imm`
+pub const fn li(rd: u32, imm: i32) -> u32 {
+    addi(rd, ZERO, imm)
+}
+
+/// `addi rd, rs, 0`
+pub const fn mv(rd: u32, rs: u32) -> u32 {
+    addi(rd, rs, 0)
+}
+
+/// `sub rd, zero, rs`
+pub const fn neg(rd: u32, rs: u32) -> u32 {
+    sub(rd, ZERO, rs)
+}
+
+/// `sltiu rd, rs, 1`
+pub const fn seqz(rd: u32, rs: u32) -> u32 {
+    sltiu(rd, rs, 1)
+}
+
+/// `jal zero, offset`
+pub const fn j(offset: i32) -> u32 {
+    fits(jal(ZERO, offset))
+}
+
+/// `jalr zero, 0(rs)`
+pub const fn jr(rs: u32) -> u32 {
+    jalr(ZERO, 0, rs)
+}
+
+/// `jalr zero, 0(ra)`
+pub const fn ret() -> u32 {
+    jr(RA)
+}
+
+// The 16 bit forms of the C extension, for the registers and immediates they reach: x8 to x15
+// where the assembly names a `'` register, and immediates the width of their fields.
+
+/// The CI format, with a sign extended 6 bit immediate.
+const fn ci(funct3: u32, rd: u32, imm: i32, op: u32) -> u16 {
+    let imm = imm as u32;
+    (funct3 << 13 | (imm >> 5 & 1) << 12 | rd << 7 | (imm & 0x1f) << 2 | op) as u16
+}
+
+/// The CB format of the shifts and `c.andi`.
+const fn cb(funct2: u32, rd: u32, imm: i32) -> u16 {
+    ci(0b100, funct2 << 3 | (rd - 8), imm, 0b01)
+}
+
+const fn ca(funct2: u32, rd: u32, rs2: u32) -> u16 {
+    (0b100011 << 10 | (rd - 8) << 7 | funct2 << 5 | (rs2 - 8) << 2 | 0b01) as u16
+}
+
+const fn cr(funct4: u32, rd: u32, rs2: u32) -> u16 {
+    (funct4 << 12 | rd << 7 | rs2 << 2 | 0b10) as u16
+}
+
+/// The word offset of `c.lw` and `c.sw`, scattered over the CL and CS formats.
+const fn word_offset(offset: i32) -> u32 {
+    let offset = offset as u32;
+    (offset >> 3 & 7) << 10 | (offset >> 2 & 1) << 6 | (offset >> 6 & 1) << 5
+}
+
+/// `c.lwsp rd, offset(sp)`
+pub const fn c_lwsp(rd: u32, offset: i32) -> u16 {
+    let offset = offset as u32;
+    let imm = (offset >> 5 & 1) << 12 | (offset >> 2 & 7) << 4 | (offset >> 6 & 3) << 2;
+    (0b010 << 13 | imm | rd << 7 | 0b10) as u16
+}
+
+/// `c.swsp rs2, offset(sp)`
+pub const fn c_swsp(rs2: u32, offset: i32) -> u16 {
+    let offset = offset as u32;
+    (0b110 << 13 | (offset >> 2 & 0xf) << 9 | (offset >> 6 & 3) << 7 | rs2 << 2 | 0b10) as u16
+}
+
+/// `c.lw rd', offset(rs1')`
+pub const fn c_lw(rd: u32, offset: i32, rs1: u32) -> u16 {
+    (0b010 << 13 | word_offset(offset) | (rs1 - 8) << 7 | (rd - 8) << 2) as u16
+}
+
+/// `c.sw rs2', offset(rs1')`
+pub const fn c_sw(rs2: u32, offset: i32, rs1: u32) -> u16 {
+    (0b110 << 13 | word_offset(offset) | (rs1 - 8) << 7 | (rs2 - 8) << 2) as u16
+}
+
+pub const fn c_li(rd: u32, imm: i32) -> u16 {
+    ci(0b010, rd, imm, 0b01)
+}
+
+/// `c.lui rd, imm`, `imm` being the upper 20 bits as for `lui`.
+pub const fn c_lui(rd: u32, imm: u32) -> u16 {
+    ci(0b011, rd, imm as i32, 0b01)
+}
+
+/// `c.addi rd, imm`, adding to `rd`.
+pub const fn c_addi(rd: u32, imm: i32) -> u16 {
+    ci(0b000, rd, imm, 0b01)
+}
+
+pub const fn c_slli(rd: u32, shamt: u32) -> u16 {
+    ci(0b000, rd, shamt as i32, 0b10)
+}
+
+pub const fn c_srli(rd: u32, shamt: u32) -> u16 {
+    cb(0b00, rd, shamt as i32)
+}
+
+pub const fn c_srai(rd: u32, shamt: u32) -> u16 {
+    cb(0b01, rd, shamt as i32)
+}
+
+pub const fn c_andi(rd: u32, imm: i32) -> u16 {
+    cb(0b10, rd, imm)
+}
+
+pub const fn c_mv(rd: u32, rs2: u32) -> u16 {
+    cr(0b1000, rd, rs2)
+}
+
+/// `c.add rd, rs2`, adding to `rd`.
+pub const fn c_add(rd: u32, rs2: u32) -> u16 {
+    cr(0b1001, rd, rs2)
+}
+
+pub const fn c_sub(rd: u32, rs2: u32) -> u16 {
+    ca(0b00, rd, rs2)
+}
+
+pub const fn c_xor(rd: u32, rs2: u32) -> u16 {
+    ca(0b01, rd, rs2)
+}
+
+pub const fn c_or(rd: u32, rs2: u32) -> u16 {
+    ca(0b10, rd, rs2)
+}
+
+pub const fn c_and(rd: u32, rs2: u32) -> u16 {
+    ca(0b11, rd, rs2)
+}
+
+pub const fn c_jr(rs1: u32) -> u16 {
+    cr(0b1000, rs1, ZERO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn immediate(operation: Operation, rd: u32, rs1: u32, imm: i32) -> Instruction {
+        Instruction::Immediate {
+            operation,
+            rd,
+            rs1,
+            imm,
+        }
+    }
+
+    fn register(operation: Operation, rd: u32, rs1: u32, rs2: u32) -> Instruction {
+        Instruction::Register {
+            operation,
+            rd,
+            rs1,
+            rs2,
+        }
+    }
+
+    #[test]
+    fn decodes_to_the_operands_assembled() {
+        use Operation::*;
+        let assembled = [
+            (
+                lui(A2, 0x10013),
+                Instruction::Lui {
+                    rd: A2,
+                    imm: 0x10013,
+                },
+            ),
+            (
+                auipc(RA, 0xfffff),
+                Instruction::Auipc {
+                    rd: RA,
+                    imm: 0xfffff,
+                },
+            ),
+            (
+                jalr(RA, -2048, A0),
+                Instruction::Jalr {
+                    rd: RA,
+                    rs1: A0,
+                    offset: -2048,
+                },
+            ),
+            (
+                lw(A0, 2047, SP),
+                Instruction::Load {
+                    width: Width::Word,
+                    rd: A0,
+                    rs1: SP,
+                    offset: 2047,
+                },
+            ),
+            (
+                lbu(A4, -1, A0),
+                Instruction::Load {
+                    width: Width::ByteUnsigned,
+                    rd: A4,
+                    rs1: A0,
+                    offset: -1,
+                },
+            ),
+            (
+                sw(RA, -2048, FP),
+                Instruction::Store {
+                    width: Width::Word,
+                    rs1: FP,
+                    rs2: RA,
+                    offset: -2048,
+                },
+            ),
+            (
+                sw(A1, 36, SP),
+                Instruction::Store {
+                    width: Width::Word,
+                    rs1: SP,
+                    rs2: A1,
+                    offset: 36,
+                },
+            ),
+            (addi(SP, SP, -4), immediate(Add, SP, SP, -4)),
+            (sltiu(A3, A0, 1), immediate(Sltu, A3, A0, 1)),
+            (ori(A0, A0, 1), immediate(Or, A0, A0, 1)),
+            (andi(A5, A1, -1), immediate(And, A5, A1, -1)),
+            (slli(A0, A0, 31), immediate(Sll, A0, A0, 31)),
+            (srli(A5, A2, 31), immediate(Srl, A5, A2, 31)),
+            (srai(T1, A1, 31), immediate(Sra, T1, A1, 31)),
+            (li(A3, 32), immediate(Add, A3, ZERO, 32)),
+            (mv(A0, S1), immediate(Add, A0, S1, 0)),
+            (seqz(A0, A0), immediate(Sltu, A0, A0, 1)),
+            (add(A4, A4, A5), register(Add, A4, A4, A5)),
+            (sub(A2, A2, T2), register(Sub, A2, A2, T2)),
+            (sll(S1, A1, S1), register(Sll, S1, A1, S1)),
+            (slt(A0, A1, A0), register(Slt, A0, A1, A0)),
+            (sltu(A5, A0, A2), register(Sltu, A5, A0, A2)),
+            (xor(A3, T1, T2), register(Xor, A3, T1, T2)),
+            (srl(S1, A1, S1), register(Srl, S1, A1, S1)),
+            (or(A3, A3, A5), register(Or, A3, A3, A5)),
+            (and(A3, A3, T1), register(And, A3, A3, T1)),
+            (mul(A2, A1, A0), register(Mul, A2, A1, A0)),
+            (mulhu(S1, A1, S1), register(Mulhu, S1, A1, S1)),
+            (div(A0, A1, A0), register(Div, A0, A1, A0)),
+            (rem(A2, A1, S1), register(Rem, A2, A1, S1)),
+            (neg(S1, S1), register(Sub, S1, ZERO, S1)),
+            (
+                jr(T0),
+                Instruction::Jalr {
+                    rd: ZERO,
+                    rs1: T0,
+                    offset: 0,
+                },
+            ),
+            (
+                ret(),
+                Instruction::Jalr {
+                    rd: ZERO,
+                    rs1: RA,
+                    offset: 0,
+                },
+            ),
+        ];
+        for (instruction, expected) in assembled {
+            assert_eq!(
+                Instruction::decode(instruction),
+                Some(expected),
+                "{expected}"
+            );
+            assert_eq!(encode(expected), Some(instruction), "{expected}");
+        }
+    }
+
+    #[test]
+    fn decodes_branches_and_jumps_at_every_offset_assembled() {
+        let conditions = [
+            (BEQ, Condition::Eq),
+            (BNE, Condition::Ne),
+            (BLT, Condition::Lt),
+            (BGE, Condition::Ge),
+            (BLTU, Condition::Ltu),
+            (BGEU, Condition::Geu),
+        ];
+        for (funct3, condition) in conditions {
+            for offset in (-4096..4096).step_by(2) {
+                let expected = Instruction::Branch {
+                    condition,
+                    rs1: A0,
+                    rs2: A1,
+                    offset,
+                };
+                let branch = branch(funct3, A0, A1, offset).unwrap();
+                assert_eq!(Instruction::decode(branch), Some(expected));
+                assert_eq!(encode(expected), Some(branch));
+            }
+        }
+        assert_eq!(branch(BEQ, A0, ZERO, 4096), None);
+        assert_eq!(branch(BEQ, A0, ZERO, -4098), None);
+
+        for offset in [-(1 << 20), -4098, -2, 0, 2, 2048, 4096, (1 << 20) - 2] {
+            let expected = Instruction::Jal { rd: T0, offset };
+            assert_eq!(
+                Instruction::decode(jal(T0, offset).unwrap()),
+                Some(expected)
+            );
+        }
+        assert_eq!(
+            encode(Instruction::Jal {
+                rd: RA,
+                offset: 1 << 20
+            }),
+            None
+        );
+        assert_eq!(jal(RA, 1 << 20), None);
+        assert_eq!(jal(RA, -(1 << 20) - 2), None);
+    }
+
+    #[test]
+    fn compressed_forms_decode_to_the_operands_assembled() {
+        let assembled = [
+            (c_lwsp(A0, 252), lw(A0, 252, SP)),
+            (c_lwsp(RA, 64), lw(RA, 64, SP)),
+            (c_swsp(A5, 252), sw(A5, 252, SP)),
+            (c_swsp(ZERO, 4), sw(ZERO, 4, SP)),
+            (c_lw(A0, 124, FP), lw(A0, 124, FP)),
+            (c_sw(A5, 64, S1), sw(A5, 64, S1)),
+            (c_li(T0, -32), li(T0, -32)),
+            (c_li(A0, 31), li(A0, 31)),
+            (c_lui(A1, 0xfffe0), lui(A1, 0xfffe0)),
+            (c_lui(A1, 0x1f), lui(A1, 0x1f)),
+            (c_addi(SP, -4), addi(SP, SP, -4)),
+            (c_addi(T2, 31), addi(T2, T2, 31)),
+            (c_slli(A0, 31), slli(A0, A0, 31)),
+            (c_srli(A5, 1), srli(A5, A5, 1)),
+            (c_srai(FP, 31), srai(FP, FP, 31)),
+            (c_andi(A0, -32), andi(A0, A0, -32)),
+            (c_mv(T0, A5), add(T0, ZERO, A5)),
+            (c_add(A0, RA), add(A0, A0, RA)),
+            (c_sub(A0, A1), sub(A0, A0, A1)),
+            (c_xor(S1, FP), xor(S1, S1, FP)),
+            (c_or(A5, A4), or(A5, A5, A4)),
+            (c_and(FP, A5), and(FP, FP, A5)),
+            (c_jr(RA), ret()),
+        ];
+        for (compressed, instruction) in assembled {
+            assert_eq!(
+                Instruction::decode_compressed(compressed),
+                Instruction::decode(instruction),
+                "{compressed:#06x}"
+            );
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Command line front end of the compiler.
+//!
+//! Compiles each source file in turn, later files being able to use the words of earlier ones,
+//! and writes them out as a flat binary, an Intel HEX file or an ELF relocatable object.
+
+use forth_compiler::{ForthCompiler, Target, TopOfStack};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str =
+    "usage: forthc [-f bin|hex|elf] [-t TARGET] [-s STACK] [-O0|-O1] [-a ADDRESS] [-d ADDRESS]
+              [-l] [-o OUTPUT] FILE...
+
+  -f FORMAT   output format, bin by default
+  -t TARGET   instruction set, rv32i (default), rv32im or rv32imc
+  -s STACK    where the top of the data stack lives, memory (default) or register
+  -O0, -O1    leave the code of each word as it is (default), or optimize it
+  -a ADDRESS  address the code is loaded at, 0 by default (bin and hex only)
+  -d ADDRESS  address of the data segment, 0xc800 by default (bin and hex only)
+  -l          print the source and instructions of every word defined
+  -o OUTPUT   output file, the first source file with the extension of the format by default";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Bin,
+    Hex,
+    Elf,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Bin => "bin",
+            Format::Hex => "hex",
+            Format::Elf => "o",
+        }
+    }
+}
+
+struct Options {
+    format: Format,
+    target: Target,
+    top_of_stack: TopOfStack,
+    optimize: bool,
+    address: u32,
+    data_address: Option<u32>,
+    listing: bool,
+    output: Option<PathBuf>,
+    sources: Vec<PathBuf>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        format: Format::Bin,
+        target: Target::RV32I,
+        top_of_stack: TopOfStack::Memory,
+        optimize: false,
+        address: 0,
+        data_address: None,
+        listing: false,
+        output: None,
+        sources: Vec::new(),
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-f" => {
+                options.format = match args.next().as_deref() {
+                    Some("bin") => Format::Bin,
+                    Some("hex") => Format::Hex,
+                    Some("elf") => Format::Elf,
+                    _ => return Err("-f expects bin, hex or elf".into()),
+                }
+            }
+            "-t" => {
+                options.target = match args.next().as_deref() {
+                    Some("rv32i") => Target::RV32I,
+                    Some("rv32im") => Target::RV32IM,
+                    Some("rv32imc") => Target::RV32IMC,
+                    _ => return Err("-t expects rv32i, rv32im or rv32imc".into()),
+                }
+            }
+            "-s" => {
+                options.top_of_stack = match args.next().as_deref() {
+                    Some("memory") => TopOfStack::Memory,
+                    Some("register") => TopOfStack::Register,
+                    _ => return Err("-s expects memory or register".into()),
+                }
+            }
+            "-O0" => options.optimize = false,
+            "-O1" => options.optimize = true,
+            "-a" => {
+                let address = args.next().ok_or("-a expects an address")?;
+                options.address = parse_address(&address)
+                    .ok_or_else(|| format!("invalid address {}", address))?;
+            }
+            "-d" => {
+                let address = args.next().ok_or("-d expects an address")?;
+                let address = parse_address(&address)
+                    .ok_or_else(|| format!("invalid address {}", address))?;
+                options.data_address = Some(address);
+            }
+            "-l" => options.listing = true,
+            "-o" => options.output = Some(args.next().ok_or("-o expects a file")?.into()),
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ => options.sources.push(arg.into()),
+        }
+    }
+    if options.sources.is_empty() {
+        return Err(USAGE.into());
+    }
+    if options.format == Format::Elf && options.sources.len() > 1 {
+        return Err("elf output takes a single source file".into());
+    }
+    Ok(options)
+}
+
+fn parse_address(address: &str) -> Option<u32> {
+    match address.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None => address.parse().ok(),
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let mut builder = ForthCompiler::builder()
+        .target(options.target)
+        .top_of_stack(options.top_of_stack)
+        .optimize(options.optimize);
+    if let Some(address) = options.data_address {
+        builder = builder.data_address(address);
+    }
+    let mut compiler = builder.build();
+    let mut image = Vec::new();
+    for (compilation, path) in options.sources.iter().enumerate() {
+        let source =
+            std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        // Words refer to their source for as long as the dictionary lives
+        let source: &'static str = Box::leak(source.into_boxed_str());
+
+        let mut code = vec![0; (source.len() + 1) * 64];
+        let len = compiler
+            .compile(source, &mut code)
+            .map_err(|diagnostic| format!("{}:{}", path.display(), diagnostic))?;
+        let code = &mut code[..len];
+        if options.listing {
+            print_listing(&compiler, compilation);
+        }
+
+        if options.format == Format::Elf {
+            let data_len = compiler.data_segment().len;
+            image.resize(object_size(code.len(), data_len, source.len()), 0);
+            let len = compiler
+                .write_object(code, &mut image)
+                .map_err(|err| format!("{}: error: {}", path.display(), err))?;
+            image.truncate(len);
+        } else {
+            let address = load_address(options.address, image.len(), code.len())
+                .ok_or_else(|| format!("{}: error: code past the end of memory", path.display()))?;
+            compiler
+                .relocate(code, address)
+                .map_err(|err| format!("{}: error: {}", path.display(), err))?;
+            image.extend_from_slice(code);
+        }
+    }
+
+    let output = match &options.output {
+        Some(output) => output.clone(),
+        None => options.sources[0].with_extension(options.format.extension()),
+    };
+    let contents = match options.format {
+        Format::Hex => intel_hex(&image, options.address).into_bytes(),
+        Format::Bin | Format::Elf => image,
+    };
+    std::fs::write(&output, contents).map_err(|err| format!("{}: {}", output.display(), err))
+}
+
+/// Address of the code `offset` bytes into an image loaded at `start`, as long as all of its
+/// `len` bytes are below 4 GiB.
+fn load_address(start: u32, offset: usize, len: usize) -> Option<u32> {
+    let address = start.checked_add(u32::try_from(offset).ok()?)?;
+    let end = u64::from(address) + len as u64;
+    (end <= 1 << 32).then_some(address)
+}
+
+/// Prints the words compiled from the source of `compilation` in the order they were defined.
+fn print_listing(compiler: &ForthCompiler, compilation: usize) {
+    let mut words: Vec<_> = compiler
+        .dictionary()
+        .words()
+        .iter()
+        .filter(|word| word.compilation == compilation)
+        .collect();
+    words.sort_by_key(|word| word.addr);
+    for word in words {
+        if let Some(listing) = compiler.see(word.name) {
+            println!("{}", listing);
+        }
+    }
+}
+
+/// Upper bound of the size of the object holding `code_len` bytes of code and `data_len` bytes of
+/// data compiled from `source_len` bytes, whose names, numbered at most, are all the symbols can
+/// refer to. Each 8 byte relocation takes at most two entries and a label symbol.
+fn object_size(code_len: usize, data_len: usize, source_len: usize) -> usize {
+    4096 + code_len * 8 + data_len + source_len * 16
+}
+
+/// Encodes `data` loaded at `address` as Intel HEX records.
+fn intel_hex(data: &[u8], address: u32) -> String {
+    let mut hex = String::new();
+    let mut upper = None;
+    for (idx, chunk) in data.chunks(16).enumerate() {
+        let address = address + idx as u32 * 16;
+        if upper != Some(address >> 16) {
+            upper = Some(address >> 16);
+            hex_record(&mut hex, 0, 0x04, &((address >> 16) as u16).to_be_bytes());
+        }
+        hex_record(&mut hex, address as u16, 0x00, chunk);
+    }
+    hex_record(&mut hex, 0, 0x01, &[]);
+    hex
+}
+
+fn hex_record(hex: &mut String, address: u16, kind: u8, data: &[u8]) {
+    let mut checksum = data.len() as u8;
+    checksum = checksum.wrapping_add((address >> 8) as u8);
+    checksum = checksum.wrapping_add(address as u8);
+    checksum = checksum.wrapping_add(kind);
+    hex.push_str(&format!(":{:02X}{:04X}{:02X}", data.len(), address, kind));
+    for byte in data {
+        hex.push_str(&format!("{:02X}", byte));
+        checksum = checksum.wrapping_add(*byte);
+    }
+    hex.push_str(&format!("{:02X}\n", checksum.wrapping_neg()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_records_carry_their_checksum() {
+        let data = [
+            0x21, 0x46, 0x01, 0x36, 0x01, 0x21, 0x47, 0x01, 0x36, 0x00, 0x7e, 0xfe, 0x09, 0xd2,
+            0x19, 0x01,
+        ];
+        let hex = intel_hex(&data, 0x100);
+        assert_eq!(
+            hex,
+            ":020000040000FA\n:10010000214601360121470136007EFE09D2190140\n:00000001FF\n"
+        );
+        for record in hex.lines() {
+            let bytes = (1..record.len())
+                .step_by(2)
+                .map(|at| u8::from_str_radix(&record[at..at + 2], 16).unwrap());
+            assert_eq!(bytes.fold(0u8, u8::wrapping_add), 0, "{record}");
+        }
+    }
+
+    #[test]
+    fn hex_records_switch_segments_at_64_kib() {
+        let hex = intel_hex(&[0xaa; 20], 0x1_fff8);
+        let records: Vec<_> = hex.lines().collect();
+        assert_eq!(
+            records,
+            [
+                ":020000040001F9",
+                ":10FFF800AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA59",
+                ":020000040002F8",
+                ":04000800AAAAAAAA4C",
+                ":00000001FF",
+            ]
+        );
+    }
+
+    #[test]
+    fn code_past_the_end_of_memory_has_no_address() {
+        assert_eq!(load_address(0x8000_0000, 0x100, 0x100), Some(0x8000_0100));
+        assert_eq!(load_address(0xffff_ff00, 0xf0, 0x10), Some(0xffff_fff0));
+        assert_eq!(load_address(0xffff_ff00, 0xf0, 0x11), None);
+        assert_eq!(load_address(0xffff_ff00, 0x100, 0), None);
+    }
+}
@@ -0,0 +1,141 @@
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+enum Storage<'a, T> {
+    Borrowed(&'a mut [T]),
+    #[cfg(feature = "alloc")]
+    Owned(Vec<T>),
+}
+
+/// A length tracked view over the storage backing the compiler tables.
+///
+/// The storage is either a slice provided by the caller, which gives a fixed capacity, or
+/// (with the `alloc` feature) a vector owned by the buffer that grows on demand.
+pub struct Buffer<'a, T> {
+    len: usize,
+    storage: Storage<'a, T>,
+}
+
+impl<'a, T> Buffer<'a, T> {
+    /// Wraps caller provided storage, of which the first `len` elements are already in use.
+    pub fn borrowed(len: usize, storage: &'a mut [T]) -> Self {
+        Buffer {
+            len,
+            storage: Storage::Borrowed(storage),
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn owned() -> Self {
+        Buffer {
+            len: 0,
+            storage: Storage::Owned(Vec::new()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of values the caller provided storage holds, `None` for owned storage.
+    pub fn capacity(&self) -> Option<usize> {
+        match &self.storage {
+            Storage::Borrowed(storage) => Some(storage.len()),
+            #[cfg(feature = "alloc")]
+            Storage::Owned(_) => None,
+        }
+    }
+
+    /// Whether `count` more values fit in the storage.
+    pub fn has_room(&self, count: usize) -> bool {
+        self.capacity()
+            .is_none_or(|capacity| self.len + count <= capacity)
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        match &self.storage {
+            Storage::Borrowed(storage) => &storage[..self.len],
+            #[cfg(feature = "alloc")]
+            Storage::Owned(storage) => storage,
+        }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        match &mut self.storage {
+            Storage::Borrowed(storage) => &mut storage[..self.len],
+            #[cfg(feature = "alloc")]
+            Storage::Owned(storage) => storage,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        #[cfg(feature = "alloc")]
+        if let Storage::Owned(storage) = &mut self.storage {
+            storage.clear();
+        }
+        self.len = 0;
+    }
+
+    /// Shortens the buffer to its first `len` values.
+    pub fn truncate(&mut self, len: usize) {
+        #[cfg(feature = "alloc")]
+        if let Storage::Owned(storage) = &mut self.storage {
+            storage.truncate(len);
+        }
+        self.len = self.len.min(len);
+    }
+
+    /// Appends a value, handing it back if the caller provided storage is exhausted.
+    pub fn try_push(&mut self, value: T) -> Result<(), T> {
+        if !self.has_room(1) {
+            return Err(value);
+        }
+        self.push(value);
+        Ok(())
+    }
+
+    /// Appends a value the storage has room for.
+    fn push(&mut self, value: T) {
+        match &mut self.storage {
+            Storage::Borrowed(storage) => storage[self.len] = value,
+            #[cfg(feature = "alloc")]
+            Storage::Owned(storage) => storage.push(value),
+        }
+        self.len += 1;
+    }
+
+    /// Inserts a value at `index`, shifting those after it, handing it back if the caller
+    /// provided storage is exhausted.
+    pub fn try_insert(&mut self, index: usize, value: T) -> Result<(), T> {
+        if !self.has_room(1) {
+            return Err(value);
+        }
+        match &mut self.storage {
+            Storage::Borrowed(storage) => {
+                storage[self.len] = value;
+                storage[index..=self.len].rotate_right(1);
+            }
+            #[cfg(feature = "alloc")]
+            Storage::Owned(storage) => storage.insert(index, value),
+        }
+        self.len += 1;
+        Ok(())
+    }
+}
+
+impl<'a, T: Copy> Buffer<'a, T> {
+    /// Appends all of `values`, or none of them if the caller provided storage can't hold them.
+    pub fn try_extend_from_slice(&mut self, values: &[T]) -> Result<(), ()> {
+        if !self.has_room(values.len()) {
+            return Err(());
+        }
+        for value in values {
+            self.push(*value);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+use crate::assembler::{self, A0, A1, BEQ, BGE, BNE, RA, ZERO};
+use crate::output;
+use crate::CompilerError;
+
+/// The open end of a control structure.
+#[derive(Clone, Copy)]
+pub enum Control {
+    /// Branch taken by `IF` or `WHILE` when the flag is false, waiting for its target.
+    If(usize),
+    /// Jump over the false part taken at `ELSE`, waiting for its target.
+    Else(usize),
+    /// Destination left by `BEGIN` for the branches closing the loop.
+    Begin(usize),
+    /// Start of the body of a counted loop, which `LOOP` and `+LOOP` branch back to. Also marks
+    /// the loop the `LEAVE`s pushed after it belong to.
+    Do(usize),
+    /// Jump out of a counted loop taken at `LEAVE`, or at `?DO` when the loop is empty.
+    Leave(usize),
+}
+
+impl Control {
+    /// Points the branch at the instruction `target`, both being byte offsets in `code`.
+    pub fn resolve(self, code: &mut [u8], target: usize) -> Result<(), CompilerError> {
+        let (at, branch) = match self {
+            Control::If(at) => (at, Branch::IfZero),
+            Control::Else(at) | Control::Leave(at) => (at, Branch::Always),
+            Control::Begin(_) | Control::Do(_) => return Err(CompilerError::UnbalancedControlFlow),
+        };
+        output::patch(code, at, branch.encode(at, target)?);
+        Ok(())
+    }
+
+    /// The instruction a loop started by `BEGIN` jumps back to.
+    pub fn destination(self) -> Result<usize, CompilerError> {
+        match self {
+            Control::Begin(destination) => Ok(destination),
+            _ => Err(CompilerError::UnbalancedControlFlow),
+        }
+    }
+
+    /// The first instruction of the body of a loop started by `DO` or `?DO`.
+    pub fn loop_body(self) -> Result<usize, CompilerError> {
+        match self {
+            Control::Do(body) => Ok(body),
+            _ => Err(CompilerError::UnbalancedControlFlow),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Branch {
+    /// Taken when the flag popped into a0 is zero.
+    IfZero,
+    /// Taken when a0 and a1 differ.
+    IfNotEqual,
+    /// Taken when a0 is positive or zero.
+    IfPositive,
+    Always,
+    /// Calls the word at the target, linking the return point in ra.
+    Call,
+}
+
+impl Branch {
+    /// Encodes the branch from the instruction at byte offset `from` to the one at `to`.
+    pub fn encode(self, from: usize, to: usize) -> Result<u32, CompilerError> {
+        let offset = to as i32 - from as i32;
+        let instruction = match self {
+            Branch::IfZero => assembler::branch(BEQ, A0, ZERO, offset),
+            Branch::IfNotEqual => assembler::branch(BNE, A0, A1, offset),
+            Branch::IfPositive => assembler::branch(BGE, A0, ZERO, offset),
+            Branch::Always => assembler::jal(ZERO, offset),
+            Branch::Call => assembler::jal(RA, offset),
+        };
+        instruction.ok_or(CompilerError::BranchOutOfRange)
+    }
+}
+
+/// Control structures currently open, innermost last.
+pub struct ControlStack {
+    entries: [Control; Self::SIZE],
+    len: usize,
+}
+
+impl ControlStack {
+    const SIZE: usize = 32;
+
+    pub fn new() -> Self {
+        ControlStack {
+            entries: [Control::If(0); Self::SIZE],
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push(&mut self, control: Control) -> Result<(), CompilerError> {
+        if self.len >= Self::SIZE {
+            return Err(CompilerError::BufferOverflow);
+        }
+        self.entries[self.len] = control;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Resolves the `LEAVE`s of the innermost counted loop to `exit`, dropping its marker.
+    pub fn resolve_leaves(&mut self, code: &mut [u8], exit: usize) -> Result<(), CompilerError> {
+        loop {
+            match self.pop()? {
+                Control::Do(_) => return Ok(()),
+                leave => leave.resolve(code, exit)?,
+            }
+        }
+    }
+
+    /// Pops the innermost structure, failing when none is open.
+    pub fn pop(&mut self) -> Result<Control, CompilerError> {
+        if self.len == 0 {
+            return Err(CompilerError::UnbalancedControlFlow);
+        }
+        self.len -= 1;
+        Ok(self.entries[self.len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::assembler::{self, jal};
+    use crate::primitives::{self, Primitive};
+    use crate::{CompiledWord, ForthCompiler};
+    use std::string::String;
+
+    fn if_branch(offset: i32) -> u32 {
+        assembler::branch(BEQ, A0, ZERO, offset).unwrap()
+    }
+
+    /// The 32 bit instructions of `code`, as compiled for the default target, and their number.
+    fn instructions(code: &[u8]) -> ([u32; 64], usize) {
+        let mut instructions = [0; 64];
+        for (instruction, bytes) in instructions.iter_mut().zip(code.chunks(4)) {
+            *instruction = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        (instructions, code.len() / 4)
+    }
+
+    /// Length of the code pushing a small number.
+    fn push_len() -> usize {
+        Primitive::Push(1).get_instructions().0
+    }
+
+    #[test]
+    fn if_branches_past_else_which_jumps_past_then() {
+        let mut keys = [(); 2].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .build();
+        let mut output = [0; 1024];
+        compiler
+            .compile(": f IF 1 ELSE 2 THEN ; : g IF 1 THEN ;", &mut output)
+            .unwrap();
+        let dictionary = compiler.dictionary();
+        // Both parts push a number, which takes as much code
+        let part = push_len();
+        let (code, len) = instructions(dictionary.lookup("f").unwrap().1);
+        assert_eq!(code[..2], primitives::POP_A0);
+        assert_eq!(code[2], if_branch((part as i32 + 2) * 4));
+        assert_eq!(code[part + 3], jal(ZERO, (part as i32 + 1) * 4).unwrap());
+        assert_eq!(len, 2 * part + 4);
+
+        let (code, len) = instructions(dictionary.lookup("g").unwrap().1);
+        assert_eq!(code[2], if_branch((part as i32 + 1) * 4));
+        assert_eq!(len, part + 3);
+    }
+
+    #[test]
+    fn loops_branch_back_to_begin() {
+        let mut keys = [(); 4].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .build();
+        let mut output = [0; 1024];
+        let source = ": f BEGIN 1 UNTIL ; : g BEGIN 1 AGAIN ; : h BEGIN 1 WHILE 2 REPEAT ;";
+        compiler.compile(source, &mut output).unwrap();
+        let dictionary = compiler.dictionary();
+        let part = push_len();
+
+        let (code, len) = instructions(dictionary.lookup("f").unwrap().1);
+        let code = &code[..len];
+        assert_eq!(code[part..part + 2], primitives::POP_A0);
+        assert_eq!(code[part + 2..], [if_branch(-(part as i32 + 2) * 4)]);
+
+        let (code, len) = instructions(dictionary.lookup("g").unwrap().1);
+        let code = &code[..len];
+        assert_eq!(code[part..], [jal(ZERO, -(part as i32) * 4).unwrap()]);
+
+        let (code, len) = instructions(dictionary.lookup("h").unwrap().1);
+        let code = &code[..len];
+        assert_eq!(code[part + 2], if_branch((part as i32 + 2) * 4));
+        let jump = 2 * part + 3;
+        assert_eq!(code[jump..], [jal(ZERO, -(jump as i32) * 4).unwrap()]);
+    }
+
+    #[test]
+    fn counted_loops_branch_back_to_their_body_and_leave_past_unloop() {
+        let mut keys = [(); 4].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .build();
+        let mut output = [0; 1024];
+        let source = ": f DO 2 +LOOP ; : g ?DO LEAVE LOOP ; : h DO 0 IF LEAVE THEN LOOP ;";
+        compiler.compile(source, &mut output).unwrap();
+        let dictionary = compiler.dictionary();
+        let params = primitives::LOOP_PARAMS.len();
+        let unloop = Primitive::Unloop.get_instructions().1[0];
+        let part = push_len();
+
+        let (code, len) = instructions(dictionary.lookup("f").unwrap().1);
+        let code = &code[..len];
+        let branch = params + part + primitives::PLUS_LOOP_STEP.len();
+        let back = assembler::branch(BGE, A0, ZERO, (params as i32 - branch as i32) * 4);
+        assert_eq!(code[branch..], [back.unwrap(), unloop]);
+
+        // `?DO` jumps over its own leave unless the loop is empty
+        let (code, len) = instructions(dictionary.lookup("g").unwrap().1);
+        let code = &code[..len];
+        let skip = assembler::branch(BNE, A0, A1, 8).unwrap();
+        assert_eq!(
+            code[params..params + 3],
+            [skip, jal(ZERO, 28).unwrap(), jal(ZERO, 24).unwrap()]
+        );
+        let branch = params + 3 + primitives::LOOP_STEP.len();
+        let back = assembler::branch(BNE, A0, A1, -20).unwrap();
+        assert_eq!(code[branch..], [back, unloop]);
+
+        let (code, len) = instructions(dictionary.lookup("h").unwrap().1);
+        let code = &code[..len];
+        let leave = params + part + 3;
+        let exit = leave + 2 + primitives::LOOP_STEP.len();
+        assert_eq!(code[leave], jal(ZERO, (exit - leave) as i32 * 4).unwrap());
+        assert_eq!(code[exit..], [unloop]);
+    }
+
+    #[test]
+    fn structures_must_be_balanced() {
+        let mut compiler = ForthCompiler::builder().build();
+        let mut output = [0; 1024];
+        for source in [
+            "0 IF 1",
+            "THEN",
+            "0 ELSE 1 THEN",
+            "0 IF 1 ELSE 2 ELSE 3 THEN",
+            "UNTIL",
+            "AGAIN",
+            "REPEAT",
+            "BEGIN 1",
+            "BEGIN 1 WHILE",
+            "BEGIN THEN",
+            "1 IF AGAIN",
+            "1 IF 1 UNTIL",
+            "1 IF BEGIN THEN",
+            "LOOP",
+            "1 +LOOP",
+            "LEAVE",
+            "1 0 DO",
+            "1 0 DO AGAIN",
+            "0 IF 1 0 DO THEN LOOP",
+            "BEGIN 1 0 DO UNTIL",
+        ] {
+            let error = compiler
+                .compile(source, &mut output)
+                .map_err(|diagnostic| diagnostic.error);
+            assert_eq!(error, Err(CompilerError::UnbalancedControlFlow), "{source}");
+        }
+        let len = compiler.compile("0 IF 1 THEN", &mut output).unwrap();
+        assert_eq!(len, (push_len() + 3 + push_len()) * 4);
+    }
+
+    #[test]
+    fn branches_reach_as_far_as_their_offsets_do() {
+        let mut code = [0; 8];
+        Control::If(0).resolve(&mut code, 4092).unwrap();
+        assert_eq!(instructions(&code).0[0], if_branch(4092));
+        let error = Control::If(4).resolve(&mut code, 4100);
+        assert_eq!(error, Err(CompilerError::BranchOutOfRange));
+        Control::Else(0).resolve(&mut code, (1 << 20) - 4).unwrap();
+        let error = Control::Else(0).resolve(&mut code, 1 << 20);
+        assert_eq!(error, Err(CompilerError::BranchOutOfRange));
+    }
+
+    #[test]
+    fn if_fails_when_then_is_out_of_its_reach() {
+        let mut output = [0; 16384];
+        let mut reached = 0;
+        for count in 100.. {
+            let mut source = String::from("0 IF");
+            (0..count).for_each(|_| source.push_str(" 1 +"));
+            source.push_str(" THEN");
+            match ForthCompiler::builder()
+                .build()
+                .compile(&source, &mut output)
+            {
+                Ok(len) => {
+                    let branch = (push_len() + 2) * 4;
+                    reached = (len - branch) as i32;
+                    let (code, _) = instructions(&output[branch..branch + 4]);
+                    assert_eq!(code[0], if_branch(reached));
+                }
+                Err(diagnostic) => {
+                    assert_eq!(diagnostic.error, CompilerError::BranchOutOfRange);
+                    break;
+                }
+            }
+        }
+        // The last code that fit left no room for another `1 +`
+        assert!(reached > 4000 && reached < 4096, "{reached}");
+    }
+}
@@ -0,0 +1,74 @@
+use crate::control::Control;
+use crate::output::{self, Output};
+use crate::relocation::RelocationKind;
+use crate::CompilerError;
+
+const LITERALS: usize = 64;
+
+/// The string literals of the code being compiled, laid out behind it once it is complete.
+///
+/// Each literal is referred to by an `auipc a0, hi; addi a0, a0, lo` pair loading its address,
+/// which is patched once the data section is written, and copied over to the dictionary from
+/// the offsets the pairs are at.
+pub struct DataSection<'a> {
+    /// Offset of the instruction pair, the string and whether it is preceded by its length.
+    literals: [(usize, &'a str, bool); LITERALS],
+    len: usize,
+}
+
+impl<'a> DataSection<'a> {
+    pub fn new() -> Self {
+        DataSection {
+            literals: [(0, "", false); LITERALS],
+            len: 0,
+        }
+    }
+
+    /// Emits the instructions loading the address of `string` into a0, preceded by a byte
+    /// holding its length when `counted`.
+    pub fn load(
+        &mut self,
+        string: &'a str,
+        counted: bool,
+        output: &mut Output,
+    ) -> Result<(), CompilerError> {
+        if self.len >= LITERALS {
+            return Err(CompilerError::BufferOverflow);
+        }
+        if counted && string.len() > u8::MAX as usize {
+            return Err(CompilerError::MalformedCompilation);
+        }
+        self.literals[self.len] = (output.len(), string, counted);
+        self.len += 1;
+        output.emit_fixed(&RelocationKind::Address.encode(0))
+    }
+
+    /// Offsets of the instruction pairs loading the address of each literal.
+    pub fn offsets(&self) -> impl Iterator<Item = usize> + '_ {
+        self.literals[..self.len].iter().map(|(at, _, _)| *at)
+    }
+
+    /// Writes the literals at the end of `output` behind a jump over them, padded so that code
+    /// can follow.
+    pub fn write(&self, output: &mut Output) -> Result<(), CompilerError> {
+        if self.len == 0 {
+            return Ok(());
+        }
+        let skip = output.len();
+        output.emit_fixed(&[0])?;
+        for (at, string, counted) in &self.literals[..self.len] {
+            let [hi, lo] = RelocationKind::Address.encode((output.len() - at) as i32);
+            output::patch(output.as_mut_slice(), *at, hi);
+            output::patch(output.as_mut_slice(), at + 4, lo);
+            if *counted {
+                output.bytes(&[string.len() as u8])?;
+            }
+            output.bytes(string.as_bytes())?;
+        }
+        while !output.len().is_multiple_of(4) {
+            output.bytes(&[0])?;
+        }
+        let len = output.len();
+        Control::Else(skip).resolve(output.as_mut_slice(), len)
+    }
+}
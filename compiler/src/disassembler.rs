@@ -0,0 +1,856 @@
+use core::fmt;
+
+use crate::SourceMark;
+
+/// ABI names of the registers, `x8` being the return stack pointer.
+const REGISTERS: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// Arithmetic and logic of the instructions combining two registers, or a register and an
+/// immediate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+}
+
+impl Operation {
+    fn mnemonic(self) -> &'static str {
+        use Operation::*;
+        match self {
+            Add => "add",
+            Sub => "sub",
+            Sll => "sll",
+            Slt => "slt",
+            Sltu => "sltu",
+            Xor => "xor",
+            Srl => "srl",
+            Sra => "sra",
+            Or => "or",
+            And => "and",
+            Mul => "mul",
+            Mulh => "mulh",
+            Mulhsu => "mulhsu",
+            Mulhu => "mulhu",
+            Div => "div",
+            Divu => "divu",
+            Rem => "rem",
+            Remu => "remu",
+        }
+    }
+}
+
+/// Comparison of a conditional branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Ltu,
+    Geu,
+}
+
+impl Condition {
+    fn mnemonic(self) -> &'static str {
+        use Condition::*;
+        match self {
+            Eq => "beq",
+            Ne => "bne",
+            Lt => "blt",
+            Ge => "bge",
+            Ltu => "bltu",
+            Geu => "bgeu",
+        }
+    }
+}
+
+/// Size of the value moved by a load or a store, and whether a load extends its sign.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Half,
+    Word,
+    ByteUnsigned,
+    HalfUnsigned,
+}
+
+/// An RV32IM instruction, which compressed instructions decode to as well. Registers are
+/// numbered as in [`crate::assembler`] and offsets are in bytes from the instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Lui {
+        rd: u32,
+        /// The upper 20 bits of the value.
+        imm: u32,
+    },
+    Auipc {
+        rd: u32,
+        imm: u32,
+    },
+    Jal {
+        rd: u32,
+        offset: i32,
+    },
+    Jalr {
+        rd: u32,
+        rs1: u32,
+        offset: i32,
+    },
+    Branch {
+        condition: Condition,
+        rs1: u32,
+        rs2: u32,
+        offset: i32,
+    },
+    Load {
+        width: Width,
+        rd: u32,
+        rs1: u32,
+        offset: i32,
+    },
+    Store {
+        width: Width,
+        rs1: u32,
+        rs2: u32,
+        offset: i32,
+    },
+    /// `addi` and its siblings, the immediate of shifts being the amount.
+    Immediate {
+        operation: Operation,
+        rd: u32,
+        rs1: u32,
+        imm: i32,
+    },
+    Register {
+        operation: Operation,
+        rd: u32,
+        rs1: u32,
+        rs2: u32,
+    },
+    Fence,
+    Ecall,
+    Ebreak,
+}
+
+impl Instruction {
+    /// Decodes a 32 bit instruction in natural byte order, `None` when it isn't one of RV32IM.
+    pub fn decode(instruction: u32) -> Option<Self> {
+        use Instruction::*;
+        let op = instruction & 0x7f;
+        let rd = instruction >> 7 & 0x1f;
+        let funct3 = instruction >> 12 & 0x7;
+        let rs1 = instruction >> 15 & 0x1f;
+        let rs2 = instruction >> 20 & 0x1f;
+        let funct7 = instruction >> 25;
+        let imm = instruction as i32 >> 20;
+
+        let decoded = match op {
+            0b0110111 => Lui {
+                rd,
+                imm: instruction >> 12,
+            },
+            0b0010111 => Auipc {
+                rd,
+                imm: instruction >> 12,
+            },
+            0b1101111 => {
+                let offset = (instruction & 0x8000_0000) as i32 >> 11
+                    | (instruction & 0xff000) as i32
+                    | (instruction >> 9 & 0x800) as i32
+                    | (instruction >> 20 & 0x7fe) as i32;
+                Jal { rd, offset }
+            }
+            0b1100111 if funct3 == 0 => Jalr {
+                rd,
+                rs1,
+                offset: imm,
+            },
+            0b1100011 => {
+                let condition = match funct3 {
+                    0b000 => Condition::Eq,
+                    0b001 => Condition::Ne,
+                    0b100 => Condition::Lt,
+                    0b101 => Condition::Ge,
+                    0b110 => Condition::Ltu,
+                    0b111 => Condition::Geu,
+                    _ => return None,
+                };
+                let offset = (instruction & 0x8000_0000) as i32 >> 19
+                    | (instruction << 4 & 0x800) as i32
+                    | (instruction >> 20 & 0x7e0) as i32
+                    | (instruction >> 7 & 0x1e) as i32;
+                Branch {
+                    condition,
+                    rs1,
+                    rs2,
+                    offset,
+                }
+            }
+            0b0000011 => {
+                let width = match funct3 {
+                    0b000 => Width::Byte,
+                    0b001 => Width::Half,
+                    0b010 => Width::Word,
+                    0b100 => Width::ByteUnsigned,
+                    0b101 => Width::HalfUnsigned,
+                    _ => return None,
+                };
+                Load {
+                    width,
+                    rd,
+                    rs1,
+                    offset: imm,
+                }
+            }
+            0b0100011 => {
+                let width = match funct3 {
+                    0b000 => Width::Byte,
+                    0b001 => Width::Half,
+                    0b010 => Width::Word,
+                    _ => return None,
+                };
+                Store {
+                    width,
+                    rs1,
+                    rs2,
+                    offset: (instruction as i32 >> 25) << 5 | rd as i32,
+                }
+            }
+            0b0010011 => {
+                let (operation, imm) = match (funct3, funct7) {
+                    (0b000, _) => (Operation::Add, imm),
+                    (0b010, _) => (Operation::Slt, imm),
+                    (0b011, _) => (Operation::Sltu, imm),
+                    (0b100, _) => (Operation::Xor, imm),
+                    (0b110, _) => (Operation::Or, imm),
+                    (0b111, _) => (Operation::And, imm),
+                    (0b001, 0) => (Operation::Sll, rs2 as i32),
+                    (0b101, 0) => (Operation::Srl, rs2 as i32),
+                    (0b101, 0x20) => (Operation::Sra, rs2 as i32),
+                    _ => return None,
+                };
+                Immediate {
+                    operation,
+                    rd,
+                    rs1,
+                    imm,
+                }
+            }
+            0b0110011 => {
+                let operation = match (funct7, funct3) {
+                    (0, 0b000) => Operation::Add,
+                    (0x20, 0b000) => Operation::Sub,
+                    (0, 0b001) => Operation::Sll,
+                    (0, 0b010) => Operation::Slt,
+                    (0, 0b011) => Operation::Sltu,
+                    (0, 0b100) => Operation::Xor,
+                    (0, 0b101) => Operation::Srl,
+                    (0x20, 0b101) => Operation::Sra,
+                    (0, 0b110) => Operation::Or,
+                    (0, 0b111) => Operation::And,
+                    (1, 0b000) => Operation::Mul,
+                    (1, 0b001) => Operation::Mulh,
+                    (1, 0b010) => Operation::Mulhsu,
+                    (1, 0b011) => Operation::Mulhu,
+                    (1, 0b100) => Operation::Div,
+                    (1, 0b101) => Operation::Divu,
+                    (1, 0b110) => Operation::Rem,
+                    (1, 0b111) => Operation::Remu,
+                    _ => return None,
+                };
+                Register {
+                    operation,
+                    rd,
+                    rs1,
+                    rs2,
+                }
+            }
+            0b0001111 if funct3 == 0 => Fence,
+            0b1110011 if instruction == 0x0000_0073 => Ecall,
+            0b1110011 if instruction == 0x0010_0073 => Ebreak,
+            _ => return None,
+        };
+        Some(decoded)
+    }
+
+    /// Decodes a 16 bit instruction in natural byte order into the one it stands for, `None`
+    /// when it isn't one of RV32C.
+    pub fn decode_compressed(instruction: u16) -> Option<Self> {
+        use Instruction::*;
+        let instruction = instruction as u32;
+        let bits = |high: u32, low: u32| instruction >> low & ((1 << (high - low + 1)) - 1);
+        let rd = bits(11, 7);
+        let rs2 = bits(6, 2);
+        // x8 to x15 in the 3 bit register fields
+        let rd_prime = bits(4, 2) + 8;
+        let rs1_prime = bits(9, 7) + 8;
+        // The sign extended 6 bit immediate of the CI format
+        let small = ((bits(12, 12) << 5 | bits(6, 2)) as i32) << 26 >> 26;
+        let word_offset = (bits(12, 10) << 3 | bits(6, 6) << 2 | bits(5, 5) << 6) as i32;
+        let jump_offset = ((bits(12, 12) << 11
+            | bits(11, 11) << 4
+            | bits(10, 9) << 8
+            | bits(8, 8) << 10
+            | bits(7, 7) << 6
+            | bits(6, 6) << 7
+            | bits(5, 3) << 1
+            | bits(2, 2) << 5) as i32)
+            << 20
+            >> 20;
+        let branch_offset = ((bits(12, 12) << 8
+            | bits(11, 10) << 3
+            | bits(6, 5) << 6
+            | bits(4, 3) << 1
+            | bits(2, 2) << 5) as i32)
+            << 23
+            >> 23;
+        let immediate = |operation, rd, rs1, imm| Immediate {
+            operation,
+            rd,
+            rs1,
+            imm,
+        };
+        let register = |operation, rd, rs1, rs2| Register {
+            operation,
+            rd,
+            rs1,
+            rs2,
+        };
+
+        let decoded = match (bits(1, 0), bits(15, 13)) {
+            // c.addi4spn
+            (0b00, 0b000) => {
+                let imm = bits(12, 11) << 4 | bits(10, 7) << 6 | bits(6, 6) << 2 | bits(5, 5) << 3;
+                if imm == 0 {
+                    return None;
+                }
+                immediate(Operation::Add, rd_prime, 2, imm as i32)
+            }
+            // c.lw
+            (0b00, 0b010) => Load {
+                width: Width::Word,
+                rd: rd_prime,
+                rs1: rs1_prime,
+                offset: word_offset,
+            },
+            // c.sw
+            (0b00, 0b110) => Store {
+                width: Width::Word,
+                rs1: rs1_prime,
+                rs2: rd_prime,
+                offset: word_offset,
+            },
+            // c.addi and c.nop
+            (0b01, 0b000) => immediate(Operation::Add, rd, rd, small),
+            // c.jal
+            (0b01, 0b001) => Jal {
+                rd: 1,
+                offset: jump_offset,
+            },
+            // c.li
+            (0b01, 0b010) => immediate(Operation::Add, rd, 0, small),
+            // c.addi16sp
+            (0b01, 0b011) if rd == 2 => {
+                let imm = bits(12, 12) << 9
+                    | bits(6, 6) << 4
+                    | bits(5, 5) << 6
+                    | bits(4, 3) << 7
+                    | bits(2, 2) << 5;
+                immediate(Operation::Add, 2, 2, (imm as i32) << 22 >> 22)
+            }
+            // c.lui
+            (0b01, 0b011) => Lui {
+                rd,
+                imm: small as u32 & 0xfffff,
+            },
+            (0b01, 0b100) => {
+                let shamt = bits(12, 12) << 5 | bits(6, 2);
+                match (bits(11, 10), bits(12, 12), bits(6, 5)) {
+                    (0b00, _, _) => immediate(Operation::Srl, rs1_prime, rs1_prime, shamt as i32),
+                    (0b01, _, _) => immediate(Operation::Sra, rs1_prime, rs1_prime, shamt as i32),
+                    (0b10, _, _) => immediate(Operation::And, rs1_prime, rs1_prime, small),
+                    (0b11, 0, funct2) => {
+                        let operation = match funct2 {
+                            0b00 => Operation::Sub,
+                            0b01 => Operation::Xor,
+                            0b10 => Operation::Or,
+                            _ => Operation::And,
+                        };
+                        register(operation, rs1_prime, rs1_prime, rd_prime)
+                    }
+                    _ => return None,
+                }
+            }
+            // c.j
+            (0b01, 0b101) => Jal {
+                rd: 0,
+                offset: jump_offset,
+            },
+            // c.beqz and c.bnez
+            (0b01, 0b110 | 0b111) => Branch {
+                condition: if bits(13, 13) == 0 {
+                    Condition::Eq
+                } else {
+                    Condition::Ne
+                },
+                rs1: rs1_prime,
+                rs2: 0,
+                offset: branch_offset,
+            },
+            // c.slli
+            (0b10, 0b000) => immediate(Operation::Sll, rd, rd, bits(6, 2) as i32),
+            // c.lwsp
+            (0b10, 0b010) if rd != 0 => Load {
+                width: Width::Word,
+                rd,
+                rs1: 2,
+                offset: (bits(12, 12) << 5 | bits(6, 4) << 2 | bits(3, 2) << 6) as i32,
+            },
+            (0b10, 0b100) => match (bits(12, 12), rd, rs2) {
+                (_, 0, 0) if bits(12, 12) == 1 => Ebreak,
+                // c.jr
+                (0, rs1, 0) if rs1 != 0 => Jalr {
+                    rd: 0,
+                    rs1,
+                    offset: 0,
+                },
+                // c.mv
+                (0, rd, rs2) if rd != 0 => register(Operation::Add, rd, 0, rs2),
+                // c.jalr
+                (1, rs1, 0) => Jalr {
+                    rd: 1,
+                    rs1,
+                    offset: 0,
+                },
+                // c.add
+                (1, rd, rs2) if rd != 0 => register(Operation::Add, rd, rd, rs2),
+                _ => return None,
+            },
+            // c.swsp
+            (0b10, 0b110) => Store {
+                width: Width::Word,
+                rs1: 2,
+                rs2,
+                offset: (bits(12, 9) << 2 | bits(8, 7) << 6) as i32,
+            },
+            _ => return None,
+        };
+        Some(decoded)
+    }
+}
+
+/// Writes the instruction as an assembler would read it, using the usual pseudo instructions
+/// such as `mv`, `li` or `ret`. Offsets of jumps and branches are relative to the instruction.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+        let name = |register: &u32| REGISTERS[*register as usize & 0x1f];
+        match self {
+            Lui { rd, imm } => write!(f, "lui {}, {:#x}", name(rd), imm),
+            Auipc { rd, imm } => write!(f, "auipc {}, {:#x}", name(rd), imm),
+            Jal { rd: 0, offset } => write!(f, "j {}", offset),
+            Jal { rd, offset } => write!(f, "jal {}, {}", name(rd), offset),
+            Jalr {
+                rd: 0,
+                rs1: 1,
+                offset: 0,
+            } => write!(f, "ret"),
+            Jalr { rd, rs1, offset } => {
+                write!(f, "jalr {}, {}({})", name(rd), offset, name(rs1))
+            }
+            Branch {
+                condition: condition @ (Condition::Eq | Condition::Ne),
+                rs1,
+                rs2: 0,
+                offset,
+            } => write!(f, "{}z {}, {}", condition.mnemonic(), name(rs1), offset),
+            Branch {
+                condition,
+                rs1,
+                rs2,
+                offset,
+            } => write!(
+                f,
+                "{} {}, {}, {}",
+                condition.mnemonic(),
+                name(rs1),
+                name(rs2),
+                offset
+            ),
+            Load {
+                width,
+                rd,
+                rs1,
+                offset,
+            } => {
+                let mnemonic = match width {
+                    Width::Byte => "lb",
+                    Width::Half => "lh",
+                    Width::Word => "lw",
+                    Width::ByteUnsigned => "lbu",
+                    Width::HalfUnsigned => "lhu",
+                };
+                write!(f, "{} {}, {}({})", mnemonic, name(rd), offset, name(rs1))
+            }
+            Store {
+                width,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                let mnemonic = match width {
+                    Width::Byte | Width::ByteUnsigned => "sb",
+                    Width::Half | Width::HalfUnsigned => "sh",
+                    Width::Word => "sw",
+                };
+                write!(f, "{} {}, {}({})", mnemonic, name(rs2), offset, name(rs1))
+            }
+            Immediate {
+                operation: Operation::Add,
+                rd: 0,
+                rs1: 0,
+                imm: 0,
+            } => write!(f, "nop"),
+            Immediate {
+                operation: Operation::Add,
+                rd,
+                rs1: 0,
+                imm,
+            } => write!(f, "li {}, {}", name(rd), imm),
+            Immediate {
+                operation: Operation::Add,
+                rd,
+                rs1,
+                imm: 0,
+            } => write!(f, "mv {}, {}", name(rd), name(rs1)),
+            Immediate {
+                operation: Operation::Sltu,
+                rd,
+                rs1,
+                imm: 1,
+            } => write!(f, "seqz {}, {}", name(rd), name(rs1)),
+            Immediate {
+                operation,
+                rd,
+                rs1,
+                imm,
+            } => write!(
+                f,
+                "{}i {}, {}, {}",
+                operation.mnemonic(),
+                name(rd),
+                name(rs1),
+                imm
+            ),
+            Register {
+                operation: Operation::Add,
+                rd,
+                rs1: 0,
+                rs2,
+            } => write!(f, "mv {}, {}", name(rd), name(rs2)),
+            Register {
+                operation: Operation::Sub,
+                rd,
+                rs1: 0,
+                rs2,
+            } => write!(f, "neg {}, {}", name(rd), name(rs2)),
+            Register {
+                operation,
+                rd,
+                rs1,
+                rs2,
+            } => write!(
+                f,
+                "{} {}, {}, {}",
+                operation.mnemonic(),
+                name(rd),
+                name(rs1),
+                name(rs2)
+            ),
+            Fence => write!(f, "fence"),
+            Ecall => write!(f, "ecall"),
+            Ebreak => write!(f, "ebreak"),
+        }
+    }
+}
+
+/// An instruction read from code, or the bytes at its place when they don't decode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decoded<'c> {
+    /// Offset of the instruction in the code.
+    pub offset: usize,
+    /// The 2 or 4 bytes of the instruction, fewer when the code ends in the middle of one.
+    pub bytes: &'c [u8],
+    pub instruction: Option<Instruction>,
+}
+
+/// Writes the offset, the bytes in memory order, as the instructions in [`crate::primitives`]
+/// are written, and the instruction, along with where jumps and branches lead to.
+impl fmt::Display for Decoded<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:6x}:  ", self.offset)?;
+        for byte in self.bytes {
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, "{:width$}", "", width = 2 + 2 * (4 - self.bytes.len()))?;
+        match self.instruction {
+            Some(
+                instruction
+                @ (Instruction::Jal { offset, .. } | Instruction::Branch { offset, .. }),
+            ) => {
+                // Calls may lead before the start of the code
+                let target = self.offset as isize + offset as isize;
+                let sign = if target < 0 { "-" } else { "" };
+                write!(f, "{} # {}{:x}", instruction, sign, target.unsigned_abs())
+            }
+            Some(instruction) => write!(f, "{}", instruction),
+            None => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Decodes the instructions of compiled code one after the other, compressed or not.
+pub struct Disassembly<'c> {
+    code: &'c [u8],
+    offset: usize,
+}
+
+impl<'c> Disassembly<'c> {
+    pub fn new(code: &'c [u8]) -> Self {
+        Disassembly { code, offset: 0 }
+    }
+}
+
+impl<'c> Iterator for Disassembly<'c> {
+    type Item = Decoded<'c>;
+
+    fn next(&mut self) -> Option<Decoded<'c>> {
+        let rest = &self.code[self.offset..];
+        let first = *rest.first()?;
+        // The lowest two bits of a 32 bit instruction are set
+        let len = if first & 0b11 == 0b11 { 4 } else { 2 };
+        let bytes = &rest[..len.min(rest.len())];
+        let instruction = match *bytes {
+            [a, b, c, d] => Instruction::decode(u32::from_le_bytes([a, b, c, d])),
+            [a, b] => Instruction::decode_compressed(u16::from_le_bytes([a, b])),
+            _ => None,
+        };
+        let decoded = Decoded {
+            offset: self.offset,
+            bytes,
+            instruction,
+        };
+        self.offset += bytes.len();
+        Some(decoded)
+    }
+}
+
+/// The code of a word alongside its source, as `SEE` shows it: each part of
+/// [`crate::CompiledWord::original`] is followed by the instructions compiled from it.
+///
+/// Without marks for the word, the whole source comes first. With the optimizer, code pulled
+/// from one token into the next shows under the source of either.
+pub struct Listing<'l> {
+    original: &'l str,
+    code: &'l [u8],
+    marks: &'l [SourceMark],
+}
+
+impl<'l> Listing<'l> {
+    pub fn new(original: &'l str, code: &'l [u8], marks: &'l [SourceMark]) -> Self {
+        Listing {
+            original,
+            code,
+            marks,
+        }
+    }
+
+    /// Writes the source from `from` up to the token whose code starts after `offset`, and
+    /// returns where it stopped.
+    fn source(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        from: usize,
+        offset: usize,
+    ) -> Result<usize, fmt::Error> {
+        let to = self
+            .marks
+            .iter()
+            .find(|mark| mark.offset > offset && mark.source >= from)
+            .map_or(self.original.len(), |mark| mark.source);
+        for line in self.original[from..to].lines() {
+            if !line.trim().is_empty() {
+                writeln!(f, "{}", line.trim())?;
+            }
+        }
+        Ok(to)
+    }
+}
+
+impl fmt::Display for Listing<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut from = 0;
+        for decoded in Disassembly::new(self.code) {
+            from = self.source(f, from, decoded.offset)?;
+            writeln!(f, "{}", decoded)?;
+        }
+        self.source(f, from, usize::MAX)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::primitives::{self, Primitive};
+    use crate::target::Target;
+    use std::string::ToString;
+
+    fn decode(instruction: u32) -> Instruction {
+        Instruction::decode(instruction).unwrap()
+    }
+
+    fn primitives() -> [Primitive; 19] {
+        use Primitive::*;
+        [
+            Load,
+            Fetch,
+            Push(0x1234_5678),
+            LShift,
+            RShift,
+            Add,
+            Sub,
+            And,
+            Xor,
+            Or,
+            Eq,
+            Gt,
+            Lt,
+            Branch,
+            RFrom,
+            RTo,
+            I,
+            J,
+            Unloop,
+        ]
+    }
+
+    #[test]
+    fn displays_instructions_in_assembly_syntax() {
+        let listed = [
+            (0x00412503, "lw a0, 4(sp)"),
+            (0x00a12223, "sw a0, 4(sp)"),
+            (0xffc10113, "addi sp, sp, -4"),
+            (0x40a58533, "sub a0, a1, a0"),
+            (0x00153513, "seqz a0, a0"),
+            (0x41f55513, "srai a0, a0, 31"),
+            (0x409004b3, "neg s1, s1"),
+            (0x00048513, "mv a0, s1"),
+            (0x00008067, "ret"),
+            (0x000500e7, "jalr ra, 0(a0)"),
+        ];
+        for (instruction, text) in listed {
+            assert_eq!(decode(instruction).to_string(), text);
+        }
+    }
+
+    #[test]
+    fn compressed_instructions_decode_as_their_full_forms() {
+        let sequences: [&[u32]; 10] = [
+            &primitives::POP_A0,
+            &primitives::ENTER,
+            &primitives::EXIT,
+            &primitives::PUSH_A0,
+            &primitives::LOOP_PARAMS,
+            &primitives::LOOP_STEP,
+            &primitives::PLUS_LOOP_STEP,
+            &primitives::CACHED_POP_A0,
+            &primitives::CACHED_PUSH_A0,
+            &primitives::CACHED_PLUS_LOOP_STEP,
+        ];
+        let check = |instruction: u32| {
+            let full = decode(instruction);
+            if let Some(compressed) = Target::RV32IMC.compress(instruction) {
+                let decoded = Instruction::decode_compressed(compressed);
+                let decoded = decoded.map(normalize);
+                assert_eq!(decoded, Some(normalize(full)), "{instruction:08x}");
+            }
+        };
+        for sequence in sequences {
+            sequence.iter().copied().for_each(check);
+        }
+        for primitive in primitives() {
+            let (len, instructions) = primitive.get_instructions();
+            instructions[..len].iter().copied().for_each(check);
+            let (len, instructions) = primitive.get_cached_instructions();
+            instructions[..len].iter().copied().for_each(check);
+        }
+    }
+
+    /// The form of moves and additions the compressed encodings stand for, `c.mv` being an
+    /// `add` and `c.add` taking its operands in either order.
+    fn normalize(instruction: Instruction) -> Instruction {
+        match instruction {
+            Instruction::Immediate {
+                operation: Operation::Add,
+                rd,
+                rs1,
+                imm: 0,
+            } => normalize(Instruction::Register {
+                operation: Operation::Add,
+                rd,
+                rs1: 0,
+                rs2: rs1,
+            }),
+            Instruction::Register {
+                operation: Operation::Add,
+                rd,
+                rs1,
+                rs2,
+            } => Instruction::Register {
+                operation: Operation::Add,
+                rd,
+                rs1: rs1.min(rs2),
+                rs2: rs1.max(rs2),
+            },
+            _ => instruction,
+        }
+    }
+
+    #[test]
+    fn comparisons_leave_their_result_on_top() {
+        for primitive in [Primitive::Lt, Primitive::Gt, Primitive::Eq] {
+            let (len, instructions) = primitive.get_instructions();
+            let last = decode(instructions[len - 1]);
+            let store = Instruction::Store {
+                width: Width::Word,
+                rs1: 2,
+                rs2: crate::assembler::A0,
+                offset: 4,
+            };
+            assert_eq!(last, store);
+        }
+    }
+}
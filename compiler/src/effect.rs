@@ -0,0 +1,316 @@
+use crate::CompilerError;
+
+/// Number of cells a word takes from the data stack and leaves on it, as in `( a b -- c )`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackEffect {
+    pub inputs: usize,
+    pub outputs: usize,
+}
+
+impl StackEffect {
+    pub const fn new(inputs: usize, outputs: usize) -> Self {
+        StackEffect { inputs, outputs }
+    }
+
+    /// Reads a stack comment from the words between its parentheses.
+    pub fn parse<'c>(words: impl Iterator<Item = &'c str>) -> Result<Self, CompilerError> {
+        let mut inputs = 0;
+        let mut outputs = None;
+        for word in words {
+            match (word, &mut outputs) {
+                ("--", None) => outputs = Some(0),
+                ("--", Some(_)) => return Err(CompilerError::MalformedCompilation),
+                (_, Some(outputs)) => *outputs += 1,
+                (_, None) => inputs += 1,
+            }
+        }
+        let outputs = outputs.ok_or(CompilerError::MalformedCompilation)?;
+        Ok(StackEffect::new(inputs, outputs))
+    }
+}
+
+/// Follows the depth of the data stack through the definition being compiled, relative to its
+/// depth when the definition starts, to work out the effect of the definition or check it
+/// against its stack comment.
+///
+/// The depth is lost once the code calls a word whose effect isn't known, after which the
+/// definition has the effect of its stack comment, if it has one. Control flow merging with
+/// different depths loses it too, unless there is a stack comment to check, in which case it is
+/// an error.
+pub struct Effects {
+    declared: Option<StackEffect>,
+    depth: isize,
+    lowest: isize,
+    known: bool,
+    /// Depth where each open control structure started, and whether it is a counted loop.
+    open: [(isize, bool); Self::SIZE],
+    len: usize,
+}
+
+impl Effects {
+    const SIZE: usize = 32;
+
+    pub fn new() -> Self {
+        Effects {
+            declared: None,
+            depth: 0,
+            lowest: 0,
+            known: true,
+            open: [(0, false); Self::SIZE],
+            len: 0,
+        }
+    }
+
+    /// Starts following a definition, checked against `declared` when it has a stack comment.
+    pub fn start(&mut self, declared: Option<StackEffect>) {
+        *self = Effects {
+            declared,
+            ..Effects::new()
+        };
+    }
+
+    /// Effect of the definition once it ends, the declared one if it has a stack comment.
+    pub fn finish(&mut self) -> Result<Option<StackEffect>, CompilerError> {
+        let computed = self.known.then(|| {
+            let inputs = self.lowest.unsigned_abs();
+            StackEffect::new(inputs, (self.depth - self.lowest) as usize)
+        });
+        let effect = match (self.declared, computed) {
+            (Some(declared), Some(_)) => {
+                if self.depth != declared.outputs as isize - declared.inputs as isize {
+                    return Err(CompilerError::StackEffectMismatch);
+                }
+                Some(declared)
+            }
+            (declared, computed) => declared.or(computed),
+        };
+        self.start(None);
+        Ok(effect)
+    }
+
+    /// Follows code with the given effect, failing when it takes more cells than the
+    /// definition was declared to.
+    pub fn apply(&mut self, effect: StackEffect) -> Result<(), CompilerError> {
+        self.depth -= effect.inputs as isize;
+        self.lowest = self.lowest.min(self.depth);
+        if let Some(declared) = self.declared {
+            if self.known && self.lowest < -(declared.inputs as isize) {
+                return Err(CompilerError::StackEffectMismatch);
+            }
+        }
+        self.depth += effect.outputs as isize;
+        Ok(())
+    }
+
+    /// Follows code whose effect isn't known.
+    pub fn unknown(&mut self) {
+        self.known = false;
+    }
+
+    /// Opens a control structure at the current depth, failing when they are nested deeper
+    /// than the depths kept.
+    pub fn open(&mut self, counted: bool) -> Result<(), CompilerError> {
+        if self.len == Self::SIZE {
+            return Err(CompilerError::StackOverflow);
+        }
+        self.open[self.len] = (self.depth, counted);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Starts the false part of an `IF` at the depth the true part started from, keeping the
+    /// depth the true part ends with for `THEN`.
+    pub fn otherwise(&mut self) {
+        if let Some((depth, _)) = self.open[..self.len].last_mut() {
+            core::mem::swap(depth, &mut self.depth);
+        }
+    }
+
+    /// Closes the innermost control structure, which must end at the depth it started from.
+    pub fn close(&mut self) -> Result<(), CompilerError> {
+        let Some(len) = self.len.checked_sub(1) else {
+            return Ok(());
+        };
+        self.len = len;
+        self.merge(self.open[len].0)
+    }
+
+    /// Keeps the current depth under the `BEGIN` of the loop for the exit taken at `WHILE`.
+    pub fn fork(&mut self) -> Result<(), CompilerError> {
+        if self.len == 0 {
+            return Ok(());
+        }
+        if self.len == Self::SIZE {
+            return Err(CompilerError::StackOverflow);
+        }
+        let begin = self.open[self.len - 1];
+        self.open[self.len - 1] = (self.depth, false);
+        self.open[self.len] = begin;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Continues from the exit taken at `WHILE`, once its loop is closed.
+    pub fn join(&mut self) {
+        if let Some(len) = self.len.checked_sub(1) {
+            self.len = len;
+            self.depth = self.open[len].0;
+        }
+    }
+
+    /// Jumps out of the innermost counted loop, which must be left at the depth it started from.
+    pub fn leave(&mut self) -> Result<(), CompilerError> {
+        let counted = self.open[..self.len]
+            .iter()
+            .rev()
+            .find(|(_, counted)| *counted);
+        match counted {
+            Some((depth, _)) => self.merge(*depth),
+            None => Ok(()),
+        }
+    }
+
+    /// Control flow reaching the current point from another one at `depth`.
+    fn merge(&mut self, depth: isize) -> Result<(), CompilerError> {
+        if !self.known || depth == self.depth {
+            return Ok(());
+        }
+        if self.declared.is_some() {
+            return Err(CompilerError::StackEffectMismatch);
+        }
+        self.unknown();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::{CompiledWord, ForthCompiler};
+    use std::string::String;
+
+    /// The effect of the word `w` defined by `source`, or the error compiling it.
+    fn effect(source: &str) -> Result<Option<StackEffect>, CompilerError> {
+        let mut keys = [(); 8].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 4096];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .build();
+        let mut output = [0; 4096];
+        compiler
+            .compile(source, &mut output)
+            .map_err(|diagnostic| diagnostic.error)?;
+        let (word, _) = compiler.dictionary().lookup("w").unwrap();
+        Ok(word.effect)
+    }
+
+    #[test]
+    fn if_without_else_skips_its_body_at_the_depth_it_started_from() {
+        assert_eq!(
+            effect(": w IF 1 + THEN ;"),
+            Ok(Some(StackEffect::new(2, 1)))
+        );
+        assert_eq!(effect(": w IF 1 THEN ;"), Ok(None));
+        assert_eq!(
+            effect(": w ( f -- x ) IF 1 THEN ;"),
+            Err(CompilerError::StackEffectMismatch)
+        );
+    }
+
+    #[test]
+    fn branches_must_leave_the_same_depth() {
+        assert_eq!(
+            effect(": w IF 1 ELSE 2 THEN ;"),
+            Ok(Some(StackEffect::new(1, 1)))
+        );
+        assert_eq!(effect(": w IF 1 ELSE 1 2 THEN ;"), Ok(None));
+        assert_eq!(
+            effect(": w ( f -- x ) IF 1 ELSE 1 2 THEN ;"),
+            Err(CompilerError::StackEffectMismatch)
+        );
+    }
+
+    #[test]
+    fn while_exits_at_the_depth_it_tests_at() {
+        assert_eq!(
+            effect(": w BEGIN 1 WHILE 1 + REPEAT ;"),
+            Ok(Some(StackEffect::new(1, 1)))
+        );
+        // The loop takes a cell each time round, but the exit leaves one more than the body
+        assert_eq!(
+            effect(": w BEGIN 1 1 WHILE + REPEAT ;"),
+            Ok(Some(StackEffect::new(1, 2)))
+        );
+        assert_eq!(effect(": w BEGIN 1 WHILE 1 REPEAT ;"), Ok(None));
+        assert_eq!(
+            effect(": w ( -- ) BEGIN 1 WHILE 1 REPEAT ;"),
+            Err(CompilerError::StackEffectMismatch)
+        );
+    }
+
+    #[test]
+    fn leave_must_leave_the_depth_its_loop_started_from() {
+        assert_eq!(
+            effect(": w 10 0 DO 1 + 0 IF LEAVE THEN LOOP ;"),
+            Ok(Some(StackEffect::new(1, 1)))
+        );
+        assert_eq!(effect(": w 10 0 DO I LEAVE LOOP ;"), Ok(None));
+        assert_eq!(
+            effect(": w ( -- ) 10 0 DO I LEAVE LOOP ;"),
+            Err(CompilerError::StackEffectMismatch)
+        );
+    }
+
+    #[test]
+    fn effects_are_inferred_through_calls() {
+        assert_eq!(
+            effect(": a + + ; : w 1 2 a ;"),
+            Ok(Some(StackEffect::new(1, 1)))
+        );
+        assert_eq!(
+            effect(": a ( x y -- z z ) + 1 ; : w 1 a ;"),
+            Ok(Some(StackEffect::new(1, 2)))
+        );
+        // Words whose effect isn't known lose it, unless there is a stack comment to rely on
+        assert_eq!(effect(": a BRANCH ; : w 1 a ;"), Ok(None));
+        assert_eq!(
+            effect(": a BRANCH ; : w ( -- x ) 1 a ;"),
+            Ok(Some(StackEffect::new(0, 1)))
+        );
+        assert_eq!(
+            effect(": a + ; : w ( x -- y ) a ;"),
+            Err(CompilerError::StackEffectMismatch)
+        );
+    }
+
+    #[test]
+    fn structures_nested_too_deep_fail() {
+        let nested = |depth| {
+            let mut source = String::from(": w");
+            for _ in 0..depth {
+                source.push_str(" 0 IF");
+            }
+            for _ in 0..depth {
+                source.push_str(" THEN");
+            }
+            source + " ;"
+        };
+        assert_eq!(
+            effect(&nested(Effects::SIZE)),
+            Ok(Some(StackEffect::new(0, 0)))
+        );
+        assert_eq!(
+            effect(&nested(Effects::SIZE + 1)),
+            Err(CompilerError::StackOverflow)
+        );
+
+        let mut source = String::from(": w");
+        for _ in 0..Effects::SIZE {
+            source.push_str(" BEGIN");
+        }
+        source.push_str(" 0 WHILE");
+        assert_eq!(effect(&source), Err(CompilerError::StackOverflow));
+    }
+}
@@ -0,0 +1,698 @@
+use crate::relocation::{Relocation, RelocationKind};
+use crate::target::Target;
+use crate::{primitives, CompiledWord, CompilerError};
+
+const HEADER_SIZE: usize = 52;
+const SECTION_HEADER_SIZE: usize = 40;
+const SYMBOL_SIZE: usize = 16;
+const RELA_SIZE: usize = 12;
+
+const EM_RISCV: u16 = 243;
+const ET_REL: u16 = 1;
+const EF_RISCV_RVC: u32 = 0x1;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHF_WRITE: u32 = 0x1;
+const SHF_ALLOC: u32 = 0x2;
+const SHF_EXECINSTR: u32 = 0x4;
+const SHF_INFO_LINK: u32 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+
+const R_RISCV_CALL_PLT: u32 = 19;
+const R_RISCV_PCREL_HI20: u32 = 23;
+const R_RISCV_PCREL_LO12_I: u32 = 24;
+const R_RISCV_HI20: u32 = 26;
+const R_RISCV_LO12_I: u32 = 27;
+
+// Section indexes, in the order their headers are written.
+const TEXT: u16 = 1;
+const SYMTAB: u32 = 3;
+const STRTAB: u32 = 4;
+const SHSTRTAB: u16 = 5;
+const DATA: u16 = 6;
+const SECTIONS: usize = 7;
+
+// Matched by the `*(.text.*)` pattern of link.x so the code lands in ROM.
+const TEXT_NAME: &str = ".text.forth";
+const RELA_TEXT_NAME: &str = ".rela.text.forth";
+const SYMTAB_NAME: &str = ".symtab";
+const STRTAB_NAME: &str = ".strtab";
+const SHSTRTAB_NAME: &str = ".shstrtab";
+// Matched by the `*(.data.*)` pattern of link.x so the data lands in RAM.
+const DATA_NAME: &str = ".data.forth";
+// Of the code outside of definitions, at the start of the section and followed by the number of
+// the compilation.
+const ENTRY_NAME: &str = "forth_init_";
+// Label of the `auipc` a `R_RISCV_PCREL_LO12_I` refers to.
+const PCREL_LABEL: &str = ".Lpcrel_hi";
+
+/// Writes an ELF32 RISC-V relocatable object into `output` holding `code` in a `.text.forth`
+/// section, returning the length of the object.
+///
+/// The code outside of definitions, which runs from the start of `code` to its end, gets a
+/// global `forth_init_<compilation>` symbol. Each of the `words` compiled into `code` by
+/// `compilation` gets a global symbol too, and the `relocations` refer to undefined symbols named
+/// after the words they reach. Words redefining one of an earlier compilation have `.` and their
+/// compilation after their name, so that both keep a symbol of their own. The `data_len` bytes
+/// the compilation reserved, `data_start` bytes into the data segment, go in a `.data.forth`
+/// section holding the values the cells start with. The object is flagged as holding compressed
+/// instructions when `target` has them.
+#[allow(clippy::too_many_arguments)]
+pub fn write_object(
+    code: &[u8],
+    target: Target,
+    words: &[CompiledWord],
+    compilation: usize,
+    relocations: &[Relocation],
+    data_start: u32,
+    data_len: usize,
+    output: &mut [u8],
+) -> Result<usize, CompilerError> {
+    let all_words = words;
+    let words = || {
+        words
+            .iter()
+            .filter(move |word| word.compilation == compilation)
+    };
+    let entry = Name {
+        name: ENTRY_NAME,
+        number: Some(compilation),
+        separator: "",
+    };
+    let symbols = || {
+        unique_symbols(relocations)
+            .map(|relocation| Name::of(relocation.symbol, relocation.compilation, all_words))
+    };
+    let count = |kind| {
+        relocations
+            .iter()
+            .filter(|relocation| relocation.kind == kind)
+            .count()
+    };
+    let labels = count(RelocationKind::Address);
+
+    // Null symbol, section symbols, labels, then the globals: the entry, defined words and
+    // undefined ones.
+    let first_global = 3 + labels;
+    let first_undefined = first_global + 1 + words().count();
+    let symbol_count = first_undefined + symbols().count();
+    let rela_count = relocations.len() + labels + count(RelocationKind::Data);
+
+    let strtab_len = 1
+        + PCREL_LABEL.len()
+        + 1
+        + entry.len()
+        + 1
+        + words()
+            .map(|word| Name::of(word.name, word.compilation, all_words).len() + 1)
+            .sum::<usize>()
+        + symbols().map(|symbol| symbol.len() + 1).sum::<usize>();
+    let shstrtab_len = 1 + [
+        TEXT_NAME,
+        RELA_TEXT_NAME,
+        SYMTAB_NAME,
+        STRTAB_NAME,
+        SHSTRTAB_NAME,
+        DATA_NAME,
+    ]
+    .iter()
+    .map(|name| name.len() + 1)
+    .sum::<usize>();
+
+    let text_offset = HEADER_SIZE;
+    let text_len = code.len();
+    let data_offset = align(text_offset + text_len, 4);
+    let symtab_offset = data_offset + data_len;
+    let symtab_len = symbol_count * SYMBOL_SIZE;
+    let rela_offset = symtab_offset + symtab_len;
+    let rela_len = rela_count * RELA_SIZE;
+    let strtab_offset = rela_offset + rela_len;
+    let shstrtab_offset = strtab_offset + strtab_len;
+    let section_headers_offset = align(shstrtab_offset + shstrtab_len, 4);
+    let len = section_headers_offset + SECTIONS * SECTION_HEADER_SIZE;
+
+    let flags = if target.compressed() { EF_RISCV_RVC } else { 0 };
+
+    let mut out = Writer::new(output);
+    out.check(len)?;
+
+    // ELF header
+    out.bytes(&[0x7f, b'E', b'L', b'F', 1, 1, 1, 0]);
+    out.bytes(&[0; 8]);
+    out.u16(ET_REL);
+    out.u16(EM_RISCV);
+    out.u32(1); // version
+    out.u32(0); // entry
+    out.u32(0); // program headers
+    out.u32(section_headers_offset as u32);
+    out.u32(flags); // soft float ABI
+    out.u16(HEADER_SIZE as u16);
+    out.u16(0); // program header size
+    out.u16(0); // program header count
+    out.u16(SECTION_HEADER_SIZE as u16);
+    out.u16(SECTIONS as u16);
+    out.u16(SHSTRTAB);
+
+    // .text.forth
+    out.bytes(code);
+
+    // .data.forth, zero but for the cells set outside of definitions
+    while out.len < data_offset + data_len {
+        out.bytes(&[0]);
+    }
+    for relocation in relocations {
+        if let Some(value) = relocation.initial {
+            let at = data_offset + relocation.addend.wrapping_sub(data_start) as usize;
+            out.output[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    // .symtab
+    out.symbol(0, 0, 0, 0, 0);
+    out.symbol(0, 0, 0, STB_LOCAL << 4 | STT_SECTION, TEXT);
+    out.symbol(0, 0, 0, STB_LOCAL << 4 | STT_SECTION, DATA);
+    let label_name = 1;
+    for relocation in relocations {
+        if relocation.kind == RelocationKind::Address {
+            let value = relocation.offset as u32;
+            out.symbol(label_name, value, 0, STB_LOCAL << 4 | STT_NOTYPE, TEXT);
+        }
+    }
+    let mut at = label_name + PCREL_LABEL.len() as u32 + 1;
+    let size = text_len as u32;
+    out.symbol(at, 0, size, STB_GLOBAL << 4 | STT_FUNC, TEXT);
+    at += entry.len() as u32 + 1;
+    let frame = target.size(&primitives::ENTER) + target.size(&primitives::EXIT);
+    for word in words() {
+        let size = (frame + word.len) as u32;
+        out.symbol(at, word.addr as u32, size, STB_GLOBAL << 4 | STT_FUNC, TEXT);
+        at += Name::of(word.name, word.compilation, all_words).len() as u32 + 1;
+    }
+    for symbol in symbols() {
+        out.symbol(at, 0, 0, STB_GLOBAL << 4 | STT_NOTYPE, 0);
+        at += symbol.len() as u32 + 1;
+    }
+
+    // .rela.text.forth
+    let mut label = 3;
+    for (idx, relocation) in relocations.iter().enumerate() {
+        let offset = relocation.offset as u32;
+        let symbol = (first_undefined + symbol_index(relocations, idx)) as u32;
+        match relocation.kind {
+            RelocationKind::Call => out.rela(offset, symbol, R_RISCV_CALL_PLT, 0),
+            RelocationKind::Address => {
+                out.rela(offset, symbol, R_RISCV_PCREL_HI20, 0);
+                out.rela(offset + 4, label, R_RISCV_PCREL_LO12_I, 0);
+                label += 1;
+            }
+            RelocationKind::Data => {
+                // Relative to the section symbol of .data.forth
+                let addend = relocation.addend.wrapping_sub(data_start);
+                out.rela(offset, 2, R_RISCV_HI20, addend);
+                out.rela(offset + 4, 2, R_RISCV_LO12_I, addend);
+            }
+        }
+    }
+
+    // .strtab
+    out.bytes(&[0]);
+    out.string(PCREL_LABEL);
+    out.name(entry);
+    for word in words() {
+        out.name(Name::of(word.name, word.compilation, all_words));
+    }
+    for symbol in symbols() {
+        out.name(symbol);
+    }
+
+    // .shstrtab
+    out.bytes(&[0]);
+    for name in [
+        TEXT_NAME,
+        RELA_TEXT_NAME,
+        SYMTAB_NAME,
+        STRTAB_NAME,
+        SHSTRTAB_NAME,
+        DATA_NAME,
+    ] {
+        out.string(name);
+    }
+    while out.len < section_headers_offset {
+        out.bytes(&[0]);
+    }
+
+    // Section headers
+    let text_name = 1;
+    let rela_text_name = text_name + TEXT_NAME.len() + 1;
+    let symtab_name = rela_text_name + RELA_TEXT_NAME.len() + 1;
+    let strtab_name = symtab_name + SYMTAB_NAME.len() + 1;
+    let shstrtab_name = strtab_name + STRTAB_NAME.len() + 1;
+    let data_name = shstrtab_name + SHSTRTAB_NAME.len() + 1;
+    out.bytes(&[0; SECTION_HEADER_SIZE]);
+    out.section(SectionHeader {
+        name: text_name,
+        kind: SHT_PROGBITS,
+        flags: SHF_ALLOC | SHF_EXECINSTR,
+        offset: text_offset,
+        size: text_len,
+        link: 0,
+        info: 0,
+        align: 4,
+        entry_size: 0,
+    });
+    out.section(SectionHeader {
+        name: rela_text_name,
+        kind: SHT_RELA,
+        flags: SHF_INFO_LINK,
+        offset: rela_offset,
+        size: rela_len,
+        link: SYMTAB,
+        info: TEXT as u32,
+        align: 4,
+        entry_size: RELA_SIZE,
+    });
+    out.section(SectionHeader {
+        name: symtab_name,
+        kind: SHT_SYMTAB,
+        flags: 0,
+        offset: symtab_offset,
+        size: symtab_len,
+        link: STRTAB,
+        info: first_global as u32,
+        align: 4,
+        entry_size: SYMBOL_SIZE,
+    });
+    out.section(SectionHeader {
+        name: strtab_name,
+        kind: SHT_STRTAB,
+        flags: 0,
+        offset: strtab_offset,
+        size: strtab_len,
+        link: 0,
+        info: 0,
+        align: 1,
+        entry_size: 0,
+    });
+    out.section(SectionHeader {
+        name: shstrtab_name,
+        kind: SHT_STRTAB,
+        flags: 0,
+        offset: shstrtab_offset,
+        size: shstrtab_len,
+        link: 0,
+        info: 0,
+        align: 1,
+        entry_size: 0,
+    });
+    out.section(SectionHeader {
+        name: data_name,
+        kind: SHT_PROGBITS,
+        flags: SHF_WRITE | SHF_ALLOC,
+        offset: data_offset,
+        size: data_len,
+        link: 0,
+        info: 0,
+        align: 4,
+        entry_size: 0,
+    });
+
+    Ok(out.len)
+}
+
+/// The first relocation reaching each word referred to by `relocations`, leaving out the data
+/// segment.
+fn unique_symbols<'r, 'a>(
+    relocations: &'r [Relocation<'a>],
+) -> impl Iterator<Item = &'r Relocation<'a>> + 'r {
+    relocations
+        .iter()
+        .enumerate()
+        .filter(|(idx, relocation)| {
+            relocation.kind != RelocationKind::Data
+                && !relocations[..*idx]
+                    .iter()
+                    .any(|previous| same_word(previous, relocation))
+        })
+        .map(|(_, relocation)| relocation)
+}
+
+/// Position of the symbol of the relocation at `idx` within [`unique_symbols`].
+fn symbol_index(relocations: &[Relocation], idx: usize) -> usize {
+    let first = relocations
+        .iter()
+        .position(|relocation| same_word(relocation, &relocations[idx]))
+        .unwrap_or(idx);
+    unique_symbols(&relocations[..first]).count()
+}
+
+fn same_word(relocation: &Relocation, other: &Relocation) -> bool {
+    (relocation.compilation, relocation.addr) == (other.compilation, other.addr)
+}
+
+/// Name of a symbol, followed by `separator` and `number` when it has one.
+#[derive(Clone, Copy)]
+struct Name<'a> {
+    name: &'a str,
+    separator: &'static str,
+    number: Option<usize>,
+}
+
+impl<'a> Name<'a> {
+    /// Name of the symbol of the word `name` defined by `compilation`, numbered when it
+    /// redefines one of the `words` defined by an earlier compilation.
+    fn of(name: &'a str, compilation: usize, words: &[CompiledWord]) -> Self {
+        let redefines = words
+            .iter()
+            .any(|word| word.name == name && word.compilation < compilation);
+        Name {
+            name,
+            separator: ".",
+            number: redefines.then_some(compilation),
+        }
+    }
+
+    fn len(self) -> usize {
+        let number = self.number.map_or(0, digits);
+        self.name.len() + self.separator.len() * self.number.is_some() as usize + number
+    }
+}
+
+/// Number of decimal digits of `number`.
+fn digits(number: usize) -> usize {
+    number.checked_ilog10().unwrap_or(0) as usize + 1
+}
+
+fn align(value: usize, to: usize) -> usize {
+    value.div_ceil(to) * to
+}
+
+struct SectionHeader {
+    name: usize,
+    kind: u32,
+    flags: u32,
+    offset: usize,
+    size: usize,
+    link: u32,
+    info: u32,
+    align: u32,
+    entry_size: usize,
+}
+
+/// Little endian writer over a buffer checked to be large enough up front.
+struct Writer<'o> {
+    output: &'o mut [u8],
+    len: usize,
+}
+
+impl<'o> Writer<'o> {
+    fn new(output: &'o mut [u8]) -> Self {
+        Writer { output, len: 0 }
+    }
+
+    fn check(&self, len: usize) -> Result<(), CompilerError> {
+        if len > self.output.len() {
+            return Err(CompilerError::ObjectOutOfBounds);
+        }
+        Ok(())
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.output[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+        self.bytes(&[0]);
+    }
+
+    fn name(&mut self, name: Name) {
+        self.bytes(name.name.as_bytes());
+        if let Some(number) = name.number {
+            self.bytes(name.separator.as_bytes());
+            let len = digits(number);
+            for (idx, byte) in self.output[self.len..self.len + len].iter_mut().enumerate() {
+                *byte = b'0' + (number / 10usize.pow((len - 1 - idx) as u32) % 10) as u8;
+            }
+            self.len += len;
+        }
+        self.bytes(&[0]);
+    }
+
+    fn symbol(&mut self, name: u32, value: u32, size: u32, info: u8, section: u16) {
+        self.u32(name);
+        self.u32(value);
+        self.u32(size);
+        self.bytes(&[info, 0]);
+        self.u16(section);
+    }
+
+    fn rela(&mut self, offset: u32, symbol: u32, kind: u32, addend: u32) {
+        self.u32(offset);
+        self.u32(symbol << 8 | kind);
+        self.u32(addend);
+    }
+
+    fn section(&mut self, header: SectionHeader) {
+        self.u32(header.name as u32);
+        self.u32(header.kind);
+        self.u32(header.flags);
+        self.u32(0); // address
+        self.u32(header.offset as u32);
+        self.u32(header.size as u32);
+        self.u32(header.link);
+        self.u32(header.info);
+        self.u32(header.align);
+        self.u32(header.entry_size as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Action, ForthCompiler};
+
+    fn u16_at(object: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(object[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32_at(object: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(object[at..at + 4].try_into().unwrap())
+    }
+
+    fn string_at(object: &[u8], at: usize) -> &str {
+        let len = object[at..].iter().position(|&byte| byte == 0).unwrap();
+        core::str::from_utf8(&object[at..at + len]).unwrap()
+    }
+
+    /// The header of a section: its name, type, offset, size, link and info.
+    struct Section<'o> {
+        name: &'o str,
+        kind: u32,
+        offset: usize,
+        size: usize,
+        link: u32,
+        info: u32,
+    }
+
+    fn sections(object: &[u8]) -> impl Iterator<Item = Section<'_>> {
+        let headers = u32_at(object, 32) as usize;
+        let header = move |idx: usize| headers + idx * SECTION_HEADER_SIZE;
+        let names = u32_at(object, header(u16_at(object, 50) as usize) + 16) as usize;
+        (0..u16_at(object, 48) as usize).map(move |idx| Section {
+            name: string_at(object, names + u32_at(object, header(idx)) as usize),
+            kind: u32_at(object, header(idx) + 4),
+            offset: u32_at(object, header(idx) + 16) as usize,
+            size: u32_at(object, header(idx) + 20) as usize,
+            link: u32_at(object, header(idx) + 24),
+            info: u32_at(object, header(idx) + 28),
+        })
+    }
+
+    fn section<'o>(object: &'o [u8], name: &str) -> Section<'o> {
+        sections(object)
+            .find(|section| section.name == name)
+            .unwrap()
+    }
+
+    /// The name, value, binding and section index of the symbol at `idx`.
+    fn symbol(object: &[u8], idx: u32) -> (&str, u32, u8, u16) {
+        let symtab = section(object, SYMTAB_NAME);
+        let strtab = sections(object).nth(symtab.link as usize).unwrap();
+        let at = symtab.offset + idx as usize * SYMBOL_SIZE;
+        let name = string_at(object, strtab.offset + u32_at(object, at) as usize);
+        (
+            name,
+            u32_at(object, at + 4),
+            object[at + 12] >> 4,
+            u16_at(object, at + 14),
+        )
+    }
+
+    #[test]
+    fn object_holds_the_code_its_symbols_and_relocations() {
+        let mut keys = [(); 4].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 256];
+        let mut relocations = [Relocation::default(); 8];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .relocations(&mut relocations)
+            .build();
+        let mut code = [0; 256];
+        compiler.compile(": double 1 << ;", &mut code).unwrap();
+        let source = "VARIABLE v : quad double double ; ' double v";
+        let len = compiler.compile(source, &mut code).unwrap();
+        let mut object = [0; 1024];
+        let object_len = compiler.write_object(&code[..len], &mut object).unwrap();
+        let object = &object[..object_len];
+
+        assert_eq!(object[..8], [0x7f, b'E', b'L', b'F', 1, 1, 1, 0]);
+        assert_eq!((u16_at(object, 16), u16_at(object, 18)), (ET_REL, EM_RISCV));
+        assert_eq!(u32_at(object, 36), 0);
+        assert_eq!(u16_at(object, 48) as usize, SECTIONS);
+        let names: [_; SECTIONS] =
+            core::array::from_fn(|idx| sections(object).nth(idx).unwrap().name);
+        assert_eq!(
+            names,
+            [
+                "",
+                TEXT_NAME,
+                RELA_TEXT_NAME,
+                SYMTAB_NAME,
+                STRTAB_NAME,
+                SHSTRTAB_NAME,
+                DATA_NAME
+            ]
+        );
+        let text = section(object, TEXT_NAME);
+        assert_eq!(text.kind, SHT_PROGBITS);
+        assert_eq!(object[text.offset..text.offset + text.size], code[..len]);
+        let data = section(object, DATA_NAME);
+        assert_eq!((data.kind, data.size), (SHT_PROGBITS, 4));
+        assert_eq!(object[data.offset..data.offset + data.size], [0; 4]);
+
+        // Locals come first, the words defined next and the words referred to last
+        let symtab = section(object, SYMTAB_NAME);
+        let count = (symtab.size / SYMBOL_SIZE) as u32;
+        let globals = (symtab.info..count).map(|idx| symbol(object, idx));
+        let dictionary = compiler.dictionary();
+        let addr = |name| dictionary.lookup(name).unwrap().0.addr as u32;
+        let expected = [
+            ("forth_init_1", 0, STB_GLOBAL, TEXT),
+            ("v", addr("v"), STB_GLOBAL, TEXT),
+            ("quad", addr("quad"), STB_GLOBAL, TEXT),
+            ("double", 0, STB_GLOBAL, 0),
+        ];
+        assert!(globals.eq(expected));
+        assert!((1..symtab.info).all(|idx| symbol(object, idx).2 == STB_LOCAL));
+
+        let rela = section(object, RELA_TEXT_NAME);
+        assert_eq!((rela.link, rela.info), (SYMTAB, TEXT as u32));
+        let entries = (rela.offset..rela.offset + rela.size)
+            .step_by(RELA_SIZE)
+            .map(|at| {
+                let info = u32_at(object, at + 4);
+                let (name, value, _, _) = symbol(object, info >> 8);
+                (u32_at(object, at), name, value, info & 0xff)
+            });
+        let offset = |idx: usize| compiler.relocations()[idx].offset as u32;
+        let (data, first, second, address) = (offset(0), offset(1), offset(2), offset(3));
+        let expected = [
+            (data, "", 0, R_RISCV_HI20),
+            (data + 4, "", 0, R_RISCV_LO12_I),
+            (first, "double", 0, R_RISCV_CALL_PLT),
+            (second, "double", 0, R_RISCV_CALL_PLT),
+            (address, "double", 0, R_RISCV_PCREL_HI20),
+            (address + 4, PCREL_LABEL, address, R_RISCV_PCREL_LO12_I),
+        ];
+        assert!(entries.eq(expected));
+    }
+
+    #[test]
+    fn redefinitions_get_symbols_of_their_own() {
+        let mut keys = [(); 8].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 256];
+        let mut relocations = [Relocation::default(); 8];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .relocations(&mut relocations)
+            .build();
+        let mut code = [0; 256];
+        compiler.compile(": w 1 2 + ;", &mut code).unwrap();
+        let len = compiler
+            .compile(": x w ; : w 3 4 + ; x", &mut code)
+            .unwrap();
+        let mut object = [0; 1024];
+        let object_len = compiler.write_object(&code[..len], &mut object).unwrap();
+        let object = &object[..object_len];
+
+        let symtab = section(object, SYMTAB_NAME);
+        let count = (symtab.size / SYMBOL_SIZE) as u32;
+        let words = compiler.dictionary().words();
+        let addr = |name, compilation| {
+            let word = words
+                .iter()
+                .find(|word| (word.name, word.compilation) == (name, compilation));
+            word.unwrap().addr as u32
+        };
+        let expected = [
+            ("forth_init_1", 0, STB_GLOBAL, TEXT),
+            ("x", addr("x", 1), STB_GLOBAL, TEXT),
+            // The earlier definition keeps the plain name, which `x` still refers to
+            ("w.1", addr("w", 1), STB_GLOBAL, TEXT),
+            ("w", 0, STB_GLOBAL, 0),
+        ];
+        // The words defined come in the order of the dictionary, sorted by hash
+        assert_eq!(count - symtab.info, expected.len() as u32);
+        for expected in expected {
+            assert!(
+                (symtab.info..count).any(|idx| symbol(object, idx) == expected),
+                "{expected:?}"
+            );
+        }
+        // The entry runs over all of the code
+        let entry = symtab.offset + symtab.info as usize * SYMBOL_SIZE;
+        assert_eq!(u32_at(object, entry + 8) as usize, len);
+    }
+
+    #[test]
+    fn initialised_cells_hold_their_values_in_the_data() {
+        let mut keys = [(); 8].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut relocations = [Relocation::default(); 8];
+        let mut actions = [Action::Cell(0); 4];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .relocations(&mut relocations)
+            .actions(&mut actions)
+            .build();
+        let mut code = [0; 512];
+        let source = "7 VALUE v CREATE t 1 , 2 , VARIABLE u";
+        let len = compiler.compile(source, &mut code).unwrap();
+        let mut object = [0; 2048];
+        let object_len = compiler.write_object(&code[..len], &mut object).unwrap();
+        let object = &object[..object_len];
+
+        let data = section(object, DATA_NAME);
+        let cells = object[data.offset..data.offset + data.size]
+            .chunks(4)
+            .map(|cell| u32::from_le_bytes(cell.try_into().unwrap()));
+        assert!(cells.eq([7, 1, 2, 0]));
+    }
+}
@@ -0,0 +1,562 @@
+use core::fmt;
+
+use crate::assembler::{FP, RA, SP};
+use crate::buffer::Buffer;
+use crate::disassembler::{Condition, Instruction, Operation, Width};
+use crate::primitives::TOS;
+use crate::stack::TopOfStack;
+
+/// Instructions run at most by [`Emulator::run`], beyond which the code is taken to loop forever.
+const STEPS: usize = 1 << 20;
+
+/// Address of the txdata register of the UART [`crate::runtime`] writes to.
+const UART: u32 = 0x1001_3000;
+
+/// Why the emulator stopped before reaching the end of the code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The code at `pc` doesn't decode to an instruction of RV32IMC.
+    IllegalInstruction { pc: u32 },
+    /// An instruction reads or writes outside of the memory, or jumps there.
+    MemoryOutOfBounds { address: u32 },
+    /// An `ecall` or `ebreak` at `pc`, which compiled code never makes.
+    EnvironmentCall { pc: u32 },
+    /// The code ran for more instructions than it is given.
+    StepLimit,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::IllegalInstruction { pc } => write!(f, "illegal instruction at {:#x}", pc),
+            Fault::MemoryOutOfBounds { address } => {
+                write!(f, "memory access out of bounds at {:#x}", address)
+            }
+            Fault::EnvironmentCall { pc } => write!(f, "environment call at {:#x}", pc),
+            Fault::StepLimit => f.write_str("step limit exceeded"),
+        }
+    }
+}
+
+/// Runs compiled code on the host, an RV32IMC hart whose memory is a slice starting at address
+/// 0, so that it can be tested without the hardware.
+///
+/// The data stack starts at the last cell of the memory and the return stack a quarter of the
+/// memory below it, both growing down. Bytes stored to the txdata register of the UART are kept
+/// for [`Emulator::uart`], and reading it back tells the FIFO is never full. Registers and
+/// memory are left as they are from one run to the next, as they are when a REPL runs one
+/// output after another.
+pub struct Emulator<'m> {
+    memory: &'m mut [u8],
+    registers: [u32; 32],
+    top_of_stack: TopOfStack,
+    data_stack: u32,
+    return_stack: u32,
+    uart: Buffer<'m, u8>,
+}
+
+impl<'m> Emulator<'m> {
+    /// An emulator running code compiled with `top_of_stack`. With the `alloc` feature it keeps
+    /// whatever is written to the UART, and without it nothing until given storage with
+    /// [`Emulator::uart_storage`].
+    pub fn new(memory: &'m mut [u8], top_of_stack: TopOfStack) -> Self {
+        let data_stack = (memory.len() as u32 - 4) & !3;
+        let return_stack = data_stack - ((memory.len() as u32 / 4) & !3);
+        let mut registers = [0; 32];
+        registers[SP as usize] = data_stack;
+        registers[FP as usize] = return_stack;
+        #[cfg(feature = "alloc")]
+        let uart = Buffer::owned();
+        #[cfg(not(feature = "alloc"))]
+        let uart = Buffer::borrowed(0, &mut []);
+        Emulator {
+            memory,
+            registers,
+            top_of_stack,
+            data_stack,
+            return_stack,
+            uart,
+        }
+    }
+
+    /// Keeps the bytes written to the UART in `storage`, dropping those that don't fit.
+    pub fn uart_storage(mut self, storage: &'m mut [u8]) -> Self {
+        self.uart = Buffer::borrowed(0, storage);
+        self
+    }
+
+    /// Copies `code` into the memory at `address`, where it was relocated to run from.
+    pub fn load(&mut self, address: u32, code: &[u8]) -> Result<(), Fault> {
+        let start = address as usize;
+        let memory = self
+            .memory
+            .get_mut(start..start + code.len())
+            .ok_or(Fault::MemoryOutOfBounds { address })?;
+        memory.copy_from_slice(code);
+        Ok(())
+    }
+
+    /// Runs the `len` bytes of code loaded at `address` until it reaches their end, or returns
+    /// to `ra`, which points there.
+    pub fn run(&mut self, address: u32, len: usize) -> Result<(), Fault> {
+        let end = address + len as u32;
+        self.registers[RA as usize] = end;
+        let mut pc = address;
+        for _ in 0..STEPS {
+            if pc == end {
+                return Ok(());
+            }
+            pc = self.step(pc)?;
+        }
+        Err(Fault::StepLimit)
+    }
+
+    /// The cells on the data stack, from the bottom up.
+    pub fn data_stack(&self) -> impl Iterator<Item = u32> + '_ {
+        let depth = self.data_stack.saturating_sub(self.registers[SP as usize]) / 4;
+        let cells = (0..depth).map(move |cell| self.cell(self.data_stack - cell * 4));
+        // With the top in s1 the bottom cell in memory holds what s1 held before the first push
+        let cached = self.top_of_stack == TopOfStack::Register;
+        cells
+            .skip(cached as usize)
+            .chain((cached && depth > 0).then_some(self.registers[TOS as usize]))
+    }
+
+    /// The cells on the return stack, from the bottom up.
+    pub fn return_stack(&self) -> impl Iterator<Item = u32> + '_ {
+        let depth = self
+            .return_stack
+            .saturating_sub(self.registers[FP as usize])
+            / 4;
+        (0..depth).map(move |cell| self.cell(self.return_stack - cell * 4))
+    }
+
+    /// The bytes written to the UART so far.
+    pub fn uart(&self) -> &[u8] {
+        self.uart.as_slice()
+    }
+
+    /// The value held by `register`, `x8` being `fp`.
+    pub fn register(&self, register: u32) -> u32 {
+        self.registers[register as usize]
+    }
+
+    fn cell(&self, address: u32) -> u32 {
+        let address = address as usize;
+        let bytes = &self.memory[address..address + 4];
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    /// Runs the instruction at `pc`, returning the address of the next one.
+    fn step(&mut self, pc: u32) -> Result<u32, Fault> {
+        let low = self.read(pc, 2)? as u16;
+        let (instruction, len) = if low & 0b11 == 0b11 {
+            (Instruction::decode(self.read(pc, 4)?), 4)
+        } else {
+            (Instruction::decode_compressed(low), 2)
+        };
+        let instruction = instruction.ok_or(Fault::IllegalInstruction { pc })?;
+        let next = pc.wrapping_add(len);
+        let x = |register: u32| self.registers[register as usize];
+
+        let (rd, value, next) = match instruction {
+            Instruction::Lui { rd, imm } => (rd, imm << 12, next),
+            Instruction::Auipc { rd, imm } => (rd, pc.wrapping_add(imm << 12), next),
+            Instruction::Jal { rd, offset } => (rd, next, pc.wrapping_add(offset as u32)),
+            Instruction::Jalr { rd, rs1, offset } => {
+                (rd, next, x(rs1).wrapping_add(offset as u32) & !1)
+            }
+            Instruction::Branch {
+                condition,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                let (a, b) = (x(rs1), x(rs2));
+                let taken = match condition {
+                    Condition::Eq => a == b,
+                    Condition::Ne => a != b,
+                    Condition::Lt => (a as i32) < b as i32,
+                    Condition::Ge => a as i32 >= b as i32,
+                    Condition::Ltu => a < b,
+                    Condition::Geu => a >= b,
+                };
+                let next = if taken {
+                    pc.wrapping_add(offset as u32)
+                } else {
+                    next
+                };
+                (0, 0, next)
+            }
+            Instruction::Load {
+                width,
+                rd,
+                rs1,
+                offset,
+            } => {
+                let address = x(rs1).wrapping_add(offset as u32);
+                let value = match width {
+                    Width::Byte => self.read(address, 1)? as i8 as u32,
+                    Width::Half => self.read(address, 2)? as i16 as u32,
+                    Width::Word => self.read(address, 4)?,
+                    Width::ByteUnsigned => self.read(address, 1)?,
+                    Width::HalfUnsigned => self.read(address, 2)?,
+                };
+                (rd, value, next)
+            }
+            Instruction::Store {
+                width,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                let address = x(rs1).wrapping_add(offset as u32);
+                let value = x(rs2);
+                match width {
+                    Width::Byte | Width::ByteUnsigned => self.write(address, 1, value)?,
+                    Width::Half | Width::HalfUnsigned => self.write(address, 2, value)?,
+                    Width::Word => self.write(address, 4, value)?,
+                }
+                (0, 0, next)
+            }
+            Instruction::Immediate {
+                operation,
+                rd,
+                rs1,
+                imm,
+            } => (rd, operate(operation, x(rs1), imm as u32), next),
+            Instruction::Register {
+                operation,
+                rd,
+                rs1,
+                rs2,
+            } => (rd, operate(operation, x(rs1), x(rs2)), next),
+            Instruction::Fence => (0, 0, next),
+            Instruction::Ecall | Instruction::Ebreak => return Err(Fault::EnvironmentCall { pc }),
+        };
+        if rd != 0 {
+            self.registers[rd as usize] = value;
+        }
+        Ok(next)
+    }
+
+    /// Reads the `len` bytes at `address`, little-endian.
+    fn read(&self, address: u32, len: usize) -> Result<u32, Fault> {
+        if address == UART {
+            return Ok(0);
+        }
+        let start = address as usize;
+        let bytes = self
+            .memory
+            .get(start..start + len)
+            .ok_or(Fault::MemoryOutOfBounds { address })?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, byte| value << 8 | *byte as u32))
+    }
+
+    /// Writes the `len` low bytes of `value` at `address`, little-endian.
+    fn write(&mut self, address: u32, len: usize, value: u32) -> Result<(), Fault> {
+        if address == UART {
+            let _ = self.uart.try_push(value as u8);
+            return Ok(());
+        }
+        let start = address as usize;
+        let bytes = self
+            .memory
+            .get_mut(start..start + len)
+            .ok_or(Fault::MemoryOutOfBounds { address })?;
+        bytes.copy_from_slice(&value.to_le_bytes()[..len]);
+        Ok(())
+    }
+}
+
+/// The result of `operation` on `a` and `b`, following the M extension for divisions by zero
+/// and overflowing ones.
+fn operate(operation: Operation, a: u32, b: u32) -> u32 {
+    use Operation::*;
+    let (signed_a, signed_b) = (a as i32, b as i32);
+    match operation {
+        Add => a.wrapping_add(b),
+        Sub => a.wrapping_sub(b),
+        Sll => a << (b & 0x1f),
+        Slt => (signed_a < signed_b) as u32,
+        Sltu => (a < b) as u32,
+        Xor => a ^ b,
+        Srl => a >> (b & 0x1f),
+        Sra => (signed_a >> (b & 0x1f)) as u32,
+        Or => a | b,
+        And => a & b,
+        Mul => a.wrapping_mul(b),
+        Mulh => ((signed_a as i64 * signed_b as i64) >> 32) as u32,
+        Mulhsu => ((signed_a as i64 * b as i64) >> 32) as u32,
+        Mulhu => ((a as u64 * b as u64) >> 32) as u32,
+        Div if b == 0 => u32::MAX,
+        Div => signed_a.wrapping_div(signed_b) as u32,
+        Divu => a.checked_div(b).unwrap_or(u32::MAX),
+        Rem if b == 0 => a,
+        Rem => signed_a.wrapping_rem(signed_b) as u32,
+        Remu => a.checked_rem(b).unwrap_or(a),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::assembler::{j, jalr, lw, A0, ZERO};
+    use crate::target::Target;
+    use crate::{Action, CompiledWord, ForthCompiler, Relocation};
+    use std::vec;
+    use std::vec::Vec;
+
+    /// What a run leaves behind: the data stack, the return stack and the UART output.
+    type Outcome = (Vec<i32>, Vec<u32>, Vec<u8>);
+
+    /// Compiles each of `sources` into an output of its own, relocated right after the previous
+    /// one, and runs them in turn with the code built for `target` and `top_of_stack`.
+    fn run_with(
+        sources: &[&str],
+        target: Target,
+        top_of_stack: TopOfStack,
+        optimize: bool,
+        inline_threshold: usize,
+    ) -> Result<Outcome, Fault> {
+        let mut keys = [(); 64].map(|_| CompiledWord::new("", "", 0));
+        let mut dictionary = [0; 8192];
+        let mut relocations = [(); 64].map(|_| Relocation::data(0, 0));
+        let mut actions = [Action::Cell(0); 64];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut dictionary)
+            .relocations(&mut relocations)
+            .actions(&mut actions)
+            .target(target)
+            .top_of_stack(top_of_stack)
+            .optimize(optimize)
+            .inline_threshold(inline_threshold)
+            .build();
+        let mut memory = vec![0; 0x2_0000];
+        let mut uart = [0; 256];
+        let mut emulator = Emulator::new(&mut memory, top_of_stack).uart_storage(&mut uart);
+        let mut address = 0;
+        for source in sources {
+            let mut code = [0; 4096];
+            let len = compiler.compile(source, &mut code).unwrap();
+            compiler.relocate(&mut code[..len], address).unwrap();
+            emulator.load(address, &code[..len])?;
+            emulator.run(address, len)?;
+            address += len as u32;
+        }
+        let data = emulator.data_stack().map(|cell| cell as i32).collect();
+        let returns = emulator.return_stack().collect();
+        Ok((data, returns, emulator.uart().to_vec()))
+    }
+
+    /// Runs `sources` compiled every way the compiler can, checking that all of them leave the
+    /// same behind.
+    fn run_all(sources: &[&str]) -> Outcome {
+        let expected = run_with(sources, Target::RV32I, TopOfStack::Memory, false, 0).unwrap();
+        for target in [Target::RV32I, Target::RV32IM, Target::RV32IMC] {
+            for top_of_stack in [TopOfStack::Memory, TopOfStack::Register] {
+                for optimize in [false, true] {
+                    for inline_threshold in [0, ForthCompiler::DEFAULT_INLINE_THRESHOLD] {
+                        let outcome =
+                            run_with(sources, target, top_of_stack, optimize, inline_threshold);
+                        let mode = (target, top_of_stack, optimize, inline_threshold);
+                        assert_eq!(outcome.as_ref(), Ok(&expected), "{mode:?}");
+                    }
+                }
+            }
+        }
+        expected
+    }
+
+    /// The data stack `source` leaves.
+    fn run(source: &str) -> Vec<i32> {
+        run_all(&[source]).0
+    }
+
+    #[test]
+    fn primitives_compute_on_the_stack() {
+        assert_eq!(run("3 4 + 10 3 - 6 3 XOR 6 3 OR 6 3 AND"), [7, 7, 5, 7, 2]);
+        assert_eq!(
+            run("1 4 << -16 2 >> 2047 -2048 $12345678 $800"),
+            [16, 0x3fff_fffc, 2047, -2048, 0x1234_5678, 0x800]
+        );
+        assert_eq!(
+            run("1 2 < 2 1 < -1 1 < 1 2 > 2 1 > 3 3 = 3 4 ="),
+            [-1, 0, -1, 0, -1, -1, 0]
+        );
+    }
+
+    #[test]
+    fn comparisons_leave_their_result_in_the_cell_of_their_operands() {
+        assert_eq!(run("1 2 <"), [-1]);
+        assert_eq!(run("2 1 >"), [-1]);
+        assert_eq!(run("7 2 1 < 7 1 2 >"), [7, 0, 7, 0]);
+    }
+
+    #[test]
+    fn primitives_reach_memory_and_the_return_stack() {
+        assert_eq!(run("VARIABLE v 42 v ! v @ 1 v ! v @"), [42, 1]);
+        assert_eq!(run("5 R< 6 R> 7"), [6, 5, 7]);
+        assert_eq!(run(":NONAME 2 3 + ; BRANCH 1"), [5, 1]);
+
+        let (data, returns, _) = run_all(&["1 R< 2 R<"]);
+        assert_eq!((data, returns), (vec![], vec![1, 2]));
+    }
+
+    #[test]
+    fn routines_multiply_and_divide() {
+        assert_eq!(run("7 3 * -7 3 * 65536 65536 *"), [21, -21, 0]);
+        assert_eq!(run("-7 2 / -7 2 MOD 7 -2 /MOD"), [-3, -1, 1, -3]);
+        assert_eq!(run("-1 2 UM* 65536 65536 UM*"), [-2, 1, 0, 1]);
+        assert_eq!(run("10 0 3 UM/MOD 0 1 2 UM/MOD"), [1, 3, 0, i32::MIN]);
+        assert_eq!(run("-7 -1 2 SM/REM 7 0 -2 SM/REM"), [-1, -3, 1, -3]);
+        assert_eq!(
+            run("-7 -1 2 FM/MOD 7 0 -2 FM/MOD 6 0 -2 FM/MOD"),
+            [1, -4, -1, -4, 0, -3]
+        );
+    }
+
+    #[test]
+    fn control_structures_branch_and_loop() {
+        let source = ": sign ( n -- n ) 0 < IF -1 ELSE 1 THEN ; -5 sign 5 sign";
+        assert_eq!(run(source), [-1, 1]);
+        assert_eq!(run("0 5 0 DO I + LOOP"), [10]);
+        assert_eq!(run("0 10 0 DO I + 3 +LOOP 0 0 0 ?DO 1 + LOOP"), [18, 0]);
+        assert_eq!(run("0 3 0 DO 2 0 DO J 10 * I + + LOOP LOOP"), [63]);
+        assert_eq!(run("0 10 0 DO I 4 = IF LEAVE THEN 1 + LOOP"), [4]);
+        let source = "VARIABLE n 1 n ! BEGIN n @ 2 << n ! 99 n @ < UNTIL n @";
+        assert_eq!(run(source), [256]);
+        let source = "VARIABLE n 0 n ! BEGIN n @ 5 < WHILE n @ 1 + n ! REPEAT n @";
+        assert_eq!(run(source), [5]);
+    }
+
+    #[test]
+    fn plus_loop_stops_crossing_the_limit_downwards() {
+        assert_eq!(run("0 0 10 DO I + -1 +LOOP"), [55]);
+        assert_eq!(run("0 0 10 DO 1 + -3 +LOOP"), [4]);
+        assert_eq!(run("0 -10 -1 DO I + -2 +LOOP"), [-25]);
+        assert_eq!(run("0 10 10 DO 1 + -1 +LOOP"), [1]);
+        let source = "0 -2147483648 2147483647 DO 1 + -1073741824 +LOOP";
+        assert_eq!(run(source), [4]);
+    }
+
+    #[test]
+    fn leave_exits_its_loop_from_nested_structures() {
+        let source = "0 10 0 DO I 5 = IF BEGIN LEAVE AGAIN THEN 1 + LOOP";
+        assert_eq!(run(source), [5]);
+        let source = "0 10 0 DO I 3 = IF BEGIN 100 + LEAVE 0 UNTIL THEN 1 + LOOP";
+        assert_eq!(run(source), [103]);
+        let source = "0 3 0 DO 10 0 DO I 2 = IF LEAVE THEN 1 + LOOP 10 + LOOP";
+        assert_eq!(run(source), [36]);
+        let source = "0 3 0 DO 10 0 ?DO I J = IF LEAVE THEN 1 + LOOP LOOP";
+        assert_eq!(run(source), [3]);
+    }
+
+    #[test]
+    fn definitions_call_each_other() {
+        let source = ": add3 ( a b c -- n ) + + ; : twice ( a b c -- n n ) add3 R< 1 2 3 add3 R> ;
+            4 5 6 twice";
+        assert_eq!(run(source), [6, 15]);
+        let source = ": inc 1 + ; INLINE : inc3 inc inc inc ; 0 inc3";
+        assert_eq!(run(source), [3]);
+    }
+
+    #[test]
+    fn defining_words_reserve_data() {
+        let source = "VARIABLE a 3 CONSTANT three 7 VALUE v
+            : pair CREATE , , DOES> @ ; 1 2 pair p
+            : array CREATE 4 * ALLOT DOES> + ; 4 array arr
+            9 a ! a @ three v 8 TO v v p 5 0 arr ! 6 4 arr ! 0 arr @ 4 arr @";
+        assert_eq!(run(source), [9, 3, 7, 8, 2, 5, 6]);
+        assert_eq!(run("CREATE cells 1 , 2 , 3 , cells 8 + @ cells @"), [3, 1]);
+    }
+
+    #[test]
+    fn defined_words_keep_the_does_code_of_their_definition() {
+        let sources = [
+            ": pair CREATE , , DOES> @ ; 1 2 pair p p",
+            ": pair CREATE , , DOES> 4 + @ ; 3 4 pair q p q",
+        ];
+        assert_eq!(run_all(&sources).0, [2, 2, 3]);
+    }
+
+    #[test]
+    fn immediate_words_compile_into_definitions() {
+        let source = ": unless 0 POSTPONE LITERAL POSTPONE = POSTPONE IF ; IMMEDIATE
+            : test ( n -- n ) unless 10 ELSE 20 THEN ; 0 test 1 test
+            : lits [ 2 3 + ] LITERAL ; lits";
+        assert_eq!(run(source), [10, 20, 5]);
+    }
+
+    #[test]
+    fn strings_are_laid_out_and_typed() {
+        let (data, _, uart) = run_all(&[r#"." hello" S" abc" TYPE S" xyz" R< @ $ffffff AND R>"#]);
+        assert_eq!(uart, b"helloabc");
+        assert_eq!(data, [0x7a7978, 3]);
+        let (_, _, uart) = run_all(&[r#": greet ." hi " ; greet greet"#]);
+        assert_eq!(uart, b"hi hi ");
+    }
+
+    #[test]
+    fn strings_keep_their_spaces_and_comments_are_skipped() {
+        let source = "S\"   a  b\" TYPE .\" \" C\" xyz\" @ $ffff AND ( 1 2 ) \\ 3 4
+            : w ( n -- n ) \\ doubles ; its argument
+              2 * ( ; ) ; 3 w \\";
+        let (data, _, uart) = run_all(&[source]);
+        assert_eq!(uart, b"  a  b");
+        assert_eq!(data, [0x7803, 6]);
+    }
+
+    #[test]
+    fn later_outputs_call_words_of_earlier_ones() {
+        let sources = [
+            ": double ( n -- n ) 2 * ; 1",
+            ": quad ( n -- n ) double double ; 5 quad",
+            "3 quad double",
+        ];
+        assert_eq!(run_all(&sources).0, [1, 20, 24]);
+    }
+
+    #[test]
+    fn later_outputs_keep_calling_the_words_they_were_compiled_against() {
+        let sources = [": w 1 ;", ": x w ; : w 2 ; x w", "x w : w 3 ; x w"];
+        assert_eq!(run_all(&sources).0, [1, 2, 1, 2, 1, 3]);
+    }
+
+    #[test]
+    fn words_pushing_execution_tokens_run_wherever_they_are_inlined() {
+        assert_eq!(run(": y 7 ; : x ['] y ; : z x BRANCH ; z"), [7]);
+    }
+
+    #[test]
+    fn faults_stop_the_run() {
+        let mut memory = [0; 64];
+        let mut emulator = Emulator::new(&mut memory, TopOfStack::Memory);
+        assert_eq!(emulator.run(0, 8), Err(Fault::IllegalInstruction { pc: 0 }));
+
+        let mut run = |instruction: u32| {
+            emulator.load(0, &instruction.to_le_bytes())?;
+            emulator.run(0, 4)
+        };
+        assert_eq!(run(j(0)), Err(Fault::StepLimit));
+        assert_eq!(run(lw(A0, 60, ZERO)), Ok(()));
+        let address = 64;
+        assert_eq!(
+            run(lw(A0, 64, ZERO)),
+            Err(Fault::MemoryOutOfBounds { address })
+        );
+        assert_eq!(
+            run(jalr(ZERO, 64, ZERO)),
+            Err(Fault::MemoryOutOfBounds { address })
+        );
+        assert_eq!(
+            emulator.load(62, &[0; 4]),
+            Err(Fault::MemoryOutOfBounds { address: 62 })
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use core::fmt;
+use core::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompilerError {
+    /// The output can't hold the compiled code.
+    OutputFull,
+    MalformedCompilation,
+    UnrecognizedToken,
+    BranchOutOfRange,
+    TooManyRelocations,
+    /// A relocation refers to a word whose output wasn't relocated yet.
+    UnresolvedSymbol,
+    ObjectOutOfBounds,
+    /// A definition leaves a different number of cells than its stack comment declares, takes
+    /// more than it declares, or leaves different numbers depending on the path taken.
+    StackEffectMismatch,
+    /// A source holds more string literals, or loops more `LEAVE`s, than the compiler keeps
+    /// track of.
+    BufferOverflow,
+    /// A control structure is closed without being open, by a word that doesn't match it, or
+    /// left open.
+    UnbalancedControlFlow,
+    /// A word is defined twice in the same source, or with the name of a built-in word.
+    DuplicateDefinition,
+    /// The dictionary keys can't hold another word.
+    DictionaryFull,
+    /// The dictionary memory can't hold the body of another word.
+    DictionaryMemoryFull,
+    /// A definition starts within another one.
+    NestedDefinition,
+    /// The source ends within a definition.
+    UnterminatedDefinition,
+    /// A word only meaningful within a definition is used outside of one.
+    CompileOnly,
+    /// A word only meaningful outside of definitions, such as one defining words, is used
+    /// within one.
+    InterpretOnly,
+    /// Code run while compiling takes more values than its stack holds.
+    StackUnderflow,
+    /// Code run while compiling leaves more values than its stacks hold, calls words deeper or
+    /// compiles more at once than it keeps track of, or a definition nests control structures
+    /// deeper than that.
+    StackOverflow,
+    /// Code run while compiling divides by zero.
+    DivisionByZero,
+    /// Code run while compiling runs for longer than it is allowed to, as it does when it loops
+    /// without end.
+    TooManySteps,
+    /// Code run while compiling asks for more to be compiled at once than the storage of the
+    /// actions holds.
+    TooManyActions,
+    /// A word only meaningful on the target, reaching memory or the UART, is run while
+    /// compiling.
+    NotRunnableWhileCompiling,
+    /// Code run while compiling hands `COMPILE,` a value that isn't the execution token of a
+    /// word.
+    InvalidExecutionToken,
+}
+
+impl fmt::Display for CompilerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            CompilerError::OutputFull => "output buffer exhausted",
+            CompilerError::MalformedCompilation => "malformed definition",
+            CompilerError::UnrecognizedToken => "unrecognized token",
+            CompilerError::BranchOutOfRange => "branch target out of range",
+            CompilerError::TooManyRelocations => "relocation table exhausted",
+            CompilerError::UnresolvedSymbol => "unresolved symbol",
+            CompilerError::ObjectOutOfBounds => "object buffer exhausted",
+            CompilerError::StackEffectMismatch => "stack effect differs from the stack comment",
+            CompilerError::BufferOverflow => "too many open control structures or strings",
+            CompilerError::UnbalancedControlFlow => "unbalanced control structure",
+            CompilerError::DuplicateDefinition => "word already defined",
+            CompilerError::DictionaryFull => "dictionary full",
+            CompilerError::DictionaryMemoryFull => "dictionary memory exhausted",
+            CompilerError::NestedDefinition => "definition within a definition",
+            CompilerError::UnterminatedDefinition => "definition not terminated by `;`",
+            CompilerError::CompileOnly => "word only valid within a definition",
+            CompilerError::InterpretOnly => "word only valid outside of definitions",
+            CompilerError::StackUnderflow => "stack underflow while compiling",
+            CompilerError::StackOverflow => "stack overflow while compiling",
+            CompilerError::DivisionByZero => "division by zero while compiling",
+            CompilerError::TooManySteps => "code run while compiling doesn't end",
+            CompilerError::TooManyActions => "action queue exhausted",
+            CompilerError::NotRunnableWhileCompiling => "word can't run while compiling",
+            CompilerError::InvalidExecutionToken => "invalid execution token while compiling",
+        };
+        f.write_str(message)
+    }
+}
+
+/// A compilation error along with the part of the source it is about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic<'a> {
+    pub error: CompilerError,
+    /// Byte range of the offending token in the source, empty at its end.
+    pub span: Range<usize>,
+    /// Line of the token, counting from 1.
+    pub line: usize,
+    /// Column of the token in characters, counting from 1.
+    pub column: usize,
+    pub token: &'a str,
+    /// The whole line holding the token, for the excerpt.
+    source_line: &'a str,
+}
+
+impl<'a> Diagnostic<'a> {
+    pub fn new(error: CompilerError, source: &'a str, span: Range<usize>) -> Self {
+        let line_start = source[..span.start]
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1);
+        let line_end = source[span.start..]
+            .find('\n')
+            .map_or(source.len(), |newline| span.start + newline);
+        Diagnostic {
+            error,
+            line: source[..span.start].matches('\n').count() + 1,
+            column: source[line_start..span.start].chars().count() + 1,
+            token: &source[span.clone()],
+            source_line: source[line_start..line_end].trim_end_matches('\r'),
+            span,
+        }
+    }
+}
+
+/// Reports the error with an excerpt of the source underlining the token, as in
+///
+/// ```text
+/// 3:5: error: unrecognized token at `FOO`
+///   |
+/// 3 | 1 2 FOO +
+///   |     ^^^
+/// ```
+impl fmt::Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: error: {}", self.line, self.column, self.error)?;
+        if self.token.is_empty() {
+            writeln!(f, " at the end of the source")?;
+        } else {
+            writeln!(f, " at `{}`", self.token)?;
+        }
+
+        let gutter = Padding(digits(self.line), ' ');
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.source_line)?;
+        write!(f, "{} | ", gutter)?;
+        // Tabs are kept so the carets line up with the token however they are displayed
+        for c in self.source_line.chars().take(self.column - 1) {
+            f.write_str(if c == '\t' { "\t" } else { " " })?;
+        }
+        let carets = self.token.chars().count().max(1);
+        write!(f, "{}", Padding(carets, '^'))
+    }
+}
+
+/// `count` times the character, for aligning the excerpt.
+struct Padding(usize, char);
+
+impl fmt::Display for Padding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for _ in 0..self.0 {
+            write!(f, "{}", self.1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Number of digits of `number` in decimal.
+fn digits(number: usize) -> usize {
+    number.checked_ilog10().map_or(1, |log| log as usize + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::{CompiledWord, ForthCompiler};
+    use std::format;
+
+    fn at<'a>(source: &'a str, token: &str) -> Diagnostic<'a> {
+        let start = source.find(token).unwrap();
+        Diagnostic::new(
+            CompilerError::UnrecognizedToken,
+            source,
+            start..start + token.len(),
+        )
+    }
+
+    #[test]
+    fn columns_count_characters_from_the_start_of_the_line() {
+        let diagnostic = at("1 2\n  é FOO +", "FOO");
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 5));
+        assert_eq!(diagnostic.span, 9..12);
+        assert_eq!(diagnostic.token, "FOO");
+
+        let diagnostic = at("FOO", "FOO");
+        assert_eq!((diagnostic.line, diagnostic.column), (1, 1));
+
+        // A token right after a line break starts the next line
+        let diagnostic = at("1\r\n\r\nFOO", "FOO");
+        assert_eq!((diagnostic.line, diagnostic.column), (3, 1));
+        assert_eq!(diagnostic.source_line, "FOO");
+    }
+
+    #[test]
+    fn excerpt_underlines_the_token() {
+        let diagnostic = at("1 2\n3 ∞FOO 4\r\n5", "FOO");
+        assert_eq!(
+            format!("{diagnostic}"),
+            "2:4: error: unrecognized token at `FOO`\n  |\n2 | 3 ∞FOO 4\n  |    ^^^"
+        );
+
+        let diagnostic = at("\t\tFOO", "FOO");
+        assert_eq!(
+            format!("{diagnostic}"),
+            "1:3: error: unrecognized token at `FOO`\n  |\n1 | \t\tFOO\n  | \t\t^^^"
+        );
+
+        let source = "\n\n\n\n\n\n\n\n\n: w";
+        let diagnostic = Diagnostic::new(
+            CompilerError::UnterminatedDefinition,
+            source,
+            source.len()..source.len(),
+        );
+        assert_eq!(
+            format!("{diagnostic}"),
+            "10:4: error: definition not terminated by `;` at the end of the source\n   |\n10 | : w\n   |    ^"
+        );
+    }
+
+    #[test]
+    fn compile_reports_the_span_of_the_token_failing() {
+        let mut keys = [(); 4].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .build();
+        let mut output = [0; 1024];
+
+        let source = "1 2 +\n: w ( a -- b )  FOO ;";
+        let diagnostic = compiler.compile(source, &mut output).unwrap_err();
+        assert_eq!(diagnostic.error, CompilerError::UnrecognizedToken);
+        assert_eq!(&source[diagnostic.span.clone()], "FOO");
+        assert_eq!((diagnostic.line, diagnostic.column), (2, 17));
+
+        let source = ": w 1 +";
+        let diagnostic = compiler.compile(source, &mut output).unwrap_err();
+        assert_eq!(diagnostic.error, CompilerError::UnterminatedDefinition);
+        assert_eq!(diagnostic.span, source.len()..source.len());
+        assert_eq!((diagnostic.token, diagnostic.column), ("", 8));
+    }
+}
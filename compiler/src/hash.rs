@@ -5,7 +5,7 @@ pub struct DJB2(u64);
 impl Hasher for DJB2 {
     fn write(&mut self, bytes: &[u8]) {
         for b in bytes {
-            self.0 = ((self.0 << 5) + self.0) + (*b as u64); // hash * 33 + bytes[i]
+            self.0 = self.0.wrapping_mul(33).wrapping_add(*b as u64); // hash * 33 + bytes[i]
         }
     }
     fn finish(&self) -> u64 {
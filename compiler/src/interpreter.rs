@@ -0,0 +1,503 @@
+use crate::buffer::Buffer;
+use crate::number::parse_number;
+use crate::primitives::Primitive;
+use crate::runtime::Routine;
+use crate::token::Tokens;
+use crate::{CompiledWord, CompilerError, ForthDictionary};
+
+const STACK: usize = 32;
+
+/// Words calling each other deeper than this are taken to recurse without end.
+const DEPTH: usize = 32;
+
+// What the words run for a single token of the source ask the compiler to compile waits in the
+// storage given to `ForthCompilerBuilder::actions`, which limits it unless it is owned.
+
+/// Tokens run for a single token of the source before the words it runs are taken to loop
+/// without end.
+const STEPS: usize = 1 << 20;
+
+/// Opening words of the control structures, and the words closing them.
+const OPENING: [&str; 4] = ["IF", "BEGIN", "DO", "?DO"];
+const CLOSING: [&str; 6] = ["THEN", "UNTIL", "AGAIN", "REPEAT", "LOOP", "+LOOP"];
+
+/// Something for the compiler to compile, on behalf of a word run while compiling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action<'a> {
+    /// A token, compiled as if it came next in the source, from `POSTPONE`.
+    Compile(&'a str),
+    /// A call to a dictionary word, even an `IMMEDIATE` one, from `COMPILE,`.
+    Call(&'a str),
+    /// A value pushed by the compiled code, from `POSTPONE LITERAL`.
+    Literal(u32),
+    /// A cell written to the output as it is from `,` while compiling a definition, or
+    /// reserved in the data segment and set to the value otherwise.
+    Cell(u32),
+    /// Bytes reserved in the data segment, from `ALLOT`.
+    Allot(u32),
+    /// A word named by the next token of the source pushing the address of the data reserved
+    /// after it, from `CREATE`, and then calling the word named by `does`, from `DOES>`.
+    Create { does: Option<&'a str> },
+}
+
+/// Runs Forth on the host while compiling: the tokens between `[` and `]` within a definition,
+/// and `IMMEDIATE` words wherever they are compiled. It has a data stack of its own, which
+/// `LITERAL` takes the values it compiles from, and a return stack for `R<`, `R>` and loops.
+///
+/// Dictionary words are run from their source, as they don't run on the host once compiled.
+/// Primitives and routines do what they do on the target, except for those reaching memory or
+/// the UART, and the control structures jump within the source. Execution tokens on the host
+/// are indices in the words of the dictionary. `POSTPONE`, `,`, `COMPILE,`, `ALLOT` and `CREATE`
+/// queue actions for the compiler, which carries them out before it reads on. Words whose value
+/// is known while compiling push it, and the words run from their source stop at `DOES>`, the
+/// code that follows running on the target only. Neither the words `VALUE` defines nor those
+/// of `DOES>` words can run, as they read their cells.
+pub struct Interpreter<'a> {
+    stack: [u32; STACK],
+    len: usize,
+    returns: [u32; STACK],
+    returns_len: usize,
+    actions: Buffer<'a, Action<'a>>,
+    /// How many of the actions the compiler took already.
+    taken: usize,
+    steps: usize,
+}
+
+impl<'a> Interpreter<'a> {
+    /// An interpreter queuing its actions in `actions`, dropping those left in it.
+    pub fn new(mut actions: Buffer<'a, Action<'a>>) -> Self {
+        actions.clear();
+        Interpreter {
+            stack: [0; STACK],
+            len: 0,
+            returns: [0; STACK],
+            returns_len: 0,
+            actions,
+            taken: 0,
+            steps: 0,
+        }
+    }
+
+    /// Hands back the storage of the actions.
+    pub fn into_actions(self) -> Buffer<'a, Action<'a>> {
+        self.actions
+    }
+
+    pub fn push(&mut self, value: u32) -> Result<(), CompilerError> {
+        if self.len >= STACK {
+            return Err(CompilerError::StackOverflow);
+        }
+        self.stack[self.len] = value;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<u32, CompilerError> {
+        if self.len == 0 {
+            return Err(CompilerError::StackUnderflow);
+        }
+        self.len -= 1;
+        Ok(self.stack[self.len])
+    }
+
+    /// Number of values on the data stack.
+    pub fn depth(&self) -> usize {
+        self.len
+    }
+
+    /// Takes the `count` values on top of the data stack, the deepest first.
+    pub fn take(&mut self, count: usize) -> Result<&[u32], CompilerError> {
+        if count > self.len {
+            return Err(CompilerError::StackUnderflow);
+        }
+        self.len -= count;
+        Ok(&self.stack[self.len..self.len + count])
+    }
+
+    /// The next action queued for the compiler, in the order they were queued.
+    pub fn next_action(&mut self) -> Option<Action<'a>> {
+        let action = *self.actions.as_slice().get(self.taken)?;
+        self.taken += 1;
+        // The storage is reused once the compiler took everything queued
+        if self.taken == self.actions.len() {
+            self.actions.clear();
+            self.taken = 0;
+        }
+        Some(action)
+    }
+
+    /// Runs `token`, read from `tokens`, which the words taking a name read on from.
+    pub fn evaluate(
+        &mut self,
+        token: &'a str,
+        tokens: &mut Tokens<'a>,
+        dictionary: &ForthDictionary<'a>,
+    ) -> Result<(), CompilerError> {
+        self.steps = STEPS;
+        self.evaluate_at(token, tokens, dictionary, 0)
+    }
+
+    /// Runs the body of `word`.
+    pub fn execute(
+        &mut self,
+        word: &CompiledWord<'a>,
+        dictionary: &ForthDictionary<'a>,
+    ) -> Result<(), CompilerError> {
+        self.steps = STEPS;
+        self.execute_at(word, dictionary, 0)
+    }
+
+    fn evaluate_at(
+        &mut self,
+        token: &'a str,
+        tokens: &mut Tokens<'a>,
+        dictionary: &ForthDictionary<'a>,
+        depth: usize,
+    ) -> Result<(), CompilerError> {
+        match token {
+            "'" | "[']" => {
+                let name = tokens.next().ok_or(CompilerError::MalformedCompilation)?;
+                let xt = dictionary
+                    .position(name)
+                    .ok_or(CompilerError::UnrecognizedToken)?;
+                self.push(xt as u32)
+            }
+            "POSTPONE" => {
+                let name = tokens.next().ok_or(CompilerError::MalformedCompilation)?;
+                // Both take effect at once, as they would if they were compiled now
+                match dictionary.lookup(name) {
+                    Some((word, _)) if word.immediate => self.execute_at(word, dictionary, depth),
+                    _ if name == "LITERAL" => {
+                        let value = self.pop()?;
+                        self.queue(Action::Literal(value))
+                    }
+                    _ => self.queue(Action::Compile(name)),
+                }
+            }
+            "," => {
+                let cell = self.pop()?;
+                self.queue(Action::Cell(cell))
+            }
+            "ALLOT" => {
+                let bytes = self.pop()?;
+                self.queue(Action::Allot(bytes))
+            }
+            "COMPILE," => {
+                let xt = self.pop()?;
+                let word = dictionary
+                    .words()
+                    .get(xt as usize)
+                    .ok_or(CompilerError::InvalidExecutionToken)?;
+                self.queue(Action::Call(word.name))
+            }
+            _ => {
+                if let Ok(primitive) = token.parse::<Primitive>() {
+                    self.primitive(&primitive)
+                } else if let Ok(routine) = token.parse::<Routine>() {
+                    self.routine(routine)
+                } else if let Some((word, _)) = dictionary.lookup(token) {
+                    match word.value {
+                        Some(value) => self.push(value),
+                        None => self.execute_at(word, dictionary, depth),
+                    }
+                } else {
+                    let value = parse_number(token).ok_or(CompilerError::UnrecognizedToken)?;
+                    self.push(value)
+                }
+            }
+        }
+    }
+
+    fn execute_at(
+        &mut self,
+        word: &CompiledWord<'a>,
+        dictionary: &ForthDictionary<'a>,
+        depth: usize,
+    ) -> Result<(), CompilerError> {
+        if depth >= DEPTH {
+            return Err(CompilerError::StackOverflow);
+        }
+        // Those reading their cells have no source to run, only code reading memory
+        if word.data.is_some() && word.value.is_none() {
+            return Err(CompilerError::NotRunnableWhileCompiling);
+        }
+        let mut tokens = body(word.original);
+        // Where each open `BEGIN` and `DO` loops back to, and whether it is a `DO`
+        let mut loops = [(0, false); STACK];
+        let mut open = 0;
+        while let Some(token) = tokens.next() {
+            self.steps = self
+                .steps
+                .checked_sub(1)
+                .ok_or(CompilerError::TooManySteps)?;
+            match token {
+                ";" | "DOES>" => break,
+                "(" => {
+                    tokens.parse(')');
+                }
+                "\\" => {
+                    tokens.parse('\n');
+                }
+                "CREATE" => {
+                    let does = word.does.then_some(word.name);
+                    self.queue(Action::Create { does })?;
+                }
+                // What they worked out was compiled when the word was, and is worked out again
+                "[" | "]" | "LITERAL" => {}
+                "IF" => {
+                    if self.pop()? == 0 {
+                        skip(&mut tokens, &["ELSE", "THEN"])?;
+                    }
+                }
+                "ELSE" => {
+                    skip(&mut tokens, &["THEN"])?;
+                }
+                "THEN" => {}
+                "BEGIN" | "DO" | "?DO" => {
+                    if token != "BEGIN" {
+                        let index = self.pop()?;
+                        let limit = self.pop()?;
+                        if token == "?DO" && index == limit {
+                            skip(&mut tokens, &["LOOP", "+LOOP"])?;
+                            continue;
+                        }
+                        self.push_return(limit)?;
+                        self.push_return(index)?;
+                    }
+                    if open >= STACK {
+                        return Err(CompilerError::StackOverflow);
+                    }
+                    loops[open] = (tokens.offset(), token != "BEGIN");
+                    open += 1;
+                }
+                "UNTIL" | "AGAIN" | "REPEAT" => {
+                    let (begin, _) = *loops[..open]
+                        .last()
+                        .ok_or(CompilerError::UnbalancedControlFlow)?;
+                    if token == "UNTIL" && self.pop()? != 0 {
+                        open -= 1;
+                    } else {
+                        tokens.seek(begin);
+                    }
+                }
+                "WHILE" => {
+                    if self.pop()? == 0 {
+                        skip(&mut tokens, &["REPEAT"])?;
+                        open = open
+                            .checked_sub(1)
+                            .ok_or(CompilerError::UnbalancedControlFlow)?;
+                    }
+                }
+                "LOOP" | "+LOOP" => {
+                    let (body, _) = *loops[..open]
+                        .last()
+                        .ok_or(CompilerError::UnbalancedControlFlow)?;
+                    let step = if token == "LOOP" { 1 } else { self.pop()? };
+                    let index = self.pop_return()?;
+                    let limit = self.pop_return()?;
+                    // The loop ends when the index crosses the boundary between the limit and
+                    // the cell below it, either way
+                    let before = index.wrapping_sub(limit) as i32;
+                    let after = before.wrapping_add(step as i32);
+                    if (before ^ after) & (before ^ step as i32) < 0 {
+                        open -= 1;
+                    } else {
+                        self.push_return(limit)?;
+                        self.push_return(index.wrapping_add(step))?;
+                        tokens.seek(body);
+                    }
+                }
+                "LEAVE" => {
+                    skip(&mut tokens, &["LOOP", "+LOOP"])?;
+                    let innermost = loops[..open]
+                        .iter()
+                        .rposition(|&(_, counted)| counted)
+                        .ok_or(CompilerError::UnbalancedControlFlow)?;
+                    open = innermost;
+                    self.pop_return()?;
+                    self.pop_return()?;
+                }
+                _ => self.evaluate_at(token, &mut tokens, dictionary, depth + 1)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn primitive(&mut self, primitive: &Primitive) -> Result<(), CompilerError> {
+        use Primitive::*;
+        let value = match primitive {
+            Push(value) => *value,
+            LShift | RShift | Add | Sub | Xor | Or | And | Eq | Gt | Lt => {
+                let right = self.pop()?;
+                let left = self.pop()?;
+                match primitive {
+                    LShift => left.wrapping_shl(right),
+                    RShift => left.wrapping_shr(right),
+                    Add => left.wrapping_add(right),
+                    Sub => left.wrapping_sub(right),
+                    Xor => left ^ right,
+                    Or => left | right,
+                    And => left & right,
+                    Eq => flag(left == right),
+                    Gt => flag((left as i32) > right as i32),
+                    _ => flag((left as i32) < right as i32),
+                }
+            }
+            RTo => {
+                let value = self.pop()?;
+                return self.push_return(value);
+            }
+            RFrom => self.pop_return()?,
+            I => self.peek_return(0)?,
+            J => self.peek_return(2)?,
+            Unloop => {
+                self.pop_return()?;
+                self.pop_return()?;
+                return Ok(());
+            }
+            // Memory only exists on the target
+            Load | Fetch | Branch => return Err(CompilerError::NotRunnableWhileCompiling),
+        };
+        self.push(value)
+    }
+
+    fn routine(&mut self, routine: Routine) -> Result<(), CompilerError> {
+        use Routine::*;
+        match routine {
+            Star | UMStar => {
+                let right = self.pop()? as u64;
+                let left = self.pop()? as u64;
+                let product = left * right;
+                self.push(product as u32)?;
+                if let UMStar = routine {
+                    self.push((product >> 32) as u32)?;
+                }
+            }
+            Slash | Mod | SlashMod => {
+                let divisor = self.pop()? as i32;
+                let dividend = self.pop()? as i32;
+                if divisor == 0 {
+                    return Err(CompilerError::DivisionByZero);
+                }
+                let quotient = dividend.wrapping_div(divisor);
+                let remainder = dividend.wrapping_rem(divisor);
+                match routine {
+                    Slash => self.push(quotient as u32)?,
+                    Mod => self.push(remainder as u32)?,
+                    _ => {
+                        self.push(remainder as u32)?;
+                        self.push(quotient as u32)?;
+                    }
+                }
+            }
+            UMSlashMod | SMRem | FMSlashMod => {
+                let divisor = self.pop()?;
+                let high = self.pop()? as u64;
+                let dividend = high << 32 | self.pop()? as u64;
+                if divisor == 0 {
+                    return Err(CompilerError::DivisionByZero);
+                }
+                // Quotients that don't fit in a cell are cut down to one, as on the target
+                let (remainder, quotient) = if let UMSlashMod = routine {
+                    let divisor = divisor as u64;
+                    (dividend % divisor, dividend / divisor)
+                } else {
+                    let (dividend, divisor) = (dividend as i64, divisor as i32 as i64);
+                    let (mut remainder, mut quotient) = (
+                        dividend.wrapping_rem(divisor),
+                        dividend.wrapping_div(divisor),
+                    );
+                    if let FMSlashMod = routine {
+                        if remainder != 0 && (remainder < 0) != (divisor < 0) {
+                            remainder += divisor;
+                            quotient -= 1;
+                        }
+                    }
+                    (remainder as u64, quotient as u64)
+                };
+                self.push(remainder as u32)?;
+                self.push(quotient as u32)?;
+            }
+            // The UART only exists on the target, and the others are only called by routines
+            Multiply | Divide | Type => return Err(CompilerError::NotRunnableWhileCompiling),
+        }
+        Ok(())
+    }
+
+    fn queue(&mut self, action: Action<'a>) -> Result<(), CompilerError> {
+        self.actions
+            .try_push(action)
+            .map_err(|_| CompilerError::TooManyActions)
+    }
+
+    fn push_return(&mut self, value: u32) -> Result<(), CompilerError> {
+        if self.returns_len >= STACK {
+            return Err(CompilerError::StackOverflow);
+        }
+        self.returns[self.returns_len] = value;
+        self.returns_len += 1;
+        Ok(())
+    }
+
+    fn pop_return(&mut self) -> Result<u32, CompilerError> {
+        let value = self.peek_return(0)?;
+        self.returns_len -= 1;
+        Ok(value)
+    }
+
+    /// The cell `depth` cells below the top of the return stack.
+    fn peek_return(&self, depth: usize) -> Result<u32, CompilerError> {
+        if depth >= self.returns_len {
+            return Err(CompilerError::StackUnderflow);
+        }
+        Ok(self.returns[self.returns_len - 1 - depth])
+    }
+}
+
+fn flag(condition: bool) -> u32 {
+    if condition {
+        u32::MAX
+    } else {
+        0
+    }
+}
+
+/// The tokens of a definition past its name and stack comment.
+fn body(original: &str) -> Tokens<'_> {
+    let mut tokens = Tokens::new(original);
+    tokens.next();
+    tokens.next();
+    if tokens.next_if_eq("(").is_some() {
+        tokens.parse(')');
+    }
+    tokens
+}
+
+/// Reads up to and past the first of `ends` closing the structure the tokens are in, over
+/// nested structures, comments, strings and the names words take. Words closing the
+/// structures around it are read over too.
+fn skip<'a>(tokens: &mut Tokens<'a>, ends: &[&str]) -> Result<&'a str, CompilerError> {
+    let mut nested = 0;
+    while let Some(token) = tokens.next() {
+        match token {
+            ";" => break,
+            "(" => {
+                tokens.parse(')');
+            }
+            "\\" => {
+                tokens.parse('\n');
+            }
+            "S\"" | "C\"" | ".\"" => {
+                tokens.parse('"');
+            }
+            "'" | "[']" | "POSTPONE" | "TO" => {
+                tokens.next();
+            }
+            _ if nested == 0 && ends.contains(&token) => return Ok(token),
+            _ if OPENING.contains(&token) => nested += 1,
+            _ if CLOSING.contains(&token) && nested > 0 => nested -= 1,
+            _ => {}
+        }
+    }
+    Err(CompilerError::UnbalancedControlFlow)
+}
@@ -1,17 +1,88 @@
 #![no_std]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+mod assembler;
+mod buffer;
+mod control;
+mod data;
+mod disassembler;
+mod effect;
+mod elf;
+mod emulator;
+mod error;
 mod hash;
+mod interpreter;
+mod number;
+mod optimizer;
+mod output;
 mod primitives;
+mod relocation;
+mod runtime;
+mod stack;
+mod target;
+mod token;
+
+pub use disassembler::{Condition, Decoded, Disassembly, Instruction, Listing, Operation, Width};
+pub use effect::StackEffect;
+pub use emulator::{Emulator, Fault};
+pub use error::{CompilerError, Diagnostic};
+pub use interpreter::Action;
+pub use relocation::{relocate, Relocation, RelocationKind};
+pub use stack::TopOfStack;
+pub use target::Target;
 
+use buffer::Buffer;
+use control::{Branch, Control, ControlStack};
 use core::hash::{Hash, Hasher};
+use data::DataSection;
+use effect::Effects;
 use hash::DJB2;
+use interpreter::Interpreter;
+use number::parse_number;
+use output::Output;
 use primitives::Primitive;
+use runtime::{Routine, Routines};
+use token::Tokens;
 
 pub struct CompiledWord<'a> {
+    /// Size in bytes of the body of the word.
     pub len: usize,
+    /// Offset in bytes of the body in the dictionary memory.
     pub pos: usize,
     pub name: &'a str,
     pub original: &'a str,
+    /// Offset in bytes in the output of the instruction callers jump to.
+    pub addr: usize,
+    /// The compilation whose output holds the word.
+    pub compilation: usize,
+    /// Whether references to the word copy its instructions instead of calling it.
+    pub inline: bool,
+    /// Whether the word runs when it is compiled instead of being compiled.
+    pub immediate: bool,
+    /// Whether the word calls no other word, so its instructions can be copied anywhere.
+    pub leaf: bool,
+    /// Address of the instruction callers jump to, once its output was relocated.
+    pub load_address: Option<u32>,
+    /// Cells the word takes from the data stack and leaves on it, as declared by its stack
+    /// comment or worked out from its body. `None` when neither tells.
+    pub effect: Option<StackEffect>,
+    /// Address of the cells reserved for the word by `VARIABLE`, `VALUE` or `CREATE`.
+    pub data: Option<u32>,
+    /// Whether the word pushes the cell at its data address rather than the address, as for
+    /// `VALUE`, so that `TO` can set it.
+    pub fetch: bool,
+    /// The value the word pushes when it is known while compiling, as for `CONSTANT`,
+    /// `VARIABLE` and `CREATE`, which the interpreter pushes instead of running the word.
+    pub value: Option<u32>,
+    /// Whether the word defines words with `CREATE`, running while compiling wherever it is used
+    /// instead of being compiled.
+    pub defining: bool,
+    /// Whether the words it defines call it, its compiled body being the code following `DOES>`.
+    pub does: bool,
+    /// Hash of the name, which the dictionary keeps the words sorted by.
+    hash: u32,
 }
 
 impl<'a> CompiledWord<'a> {
@@ -21,55 +92,209 @@ impl<'a> CompiledWord<'a> {
             original,
             pos: 0,
             len,
+            addr: 0,
+            compilation: 0,
+            inline: false,
+            immediate: false,
+            leaf: true,
+            load_address: None,
+            effect: None,
+            data: None,
+            fetch: false,
+            value: None,
+            defining: false,
+            does: false,
+            hash: get_hash(name),
         }
     }
     pub fn len(&self) -> usize {
         self.len
     }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
 }
 
+/// The words compiled so far, sorted by the hash of their names so they are found with a binary
+/// search, and the code of their bodies.
 pub struct ForthDictionary<'a> {
-    len: usize,
-    keys: &'a mut [CompiledWord<'a>],
-    mem_len: usize,
-    memory: &'a mut [u32],
+    keys: Buffer<'a, CompiledWord<'a>>,
+    memory: Buffer<'a, u8>,
+    /// Index in the keys of the word defined last.
+    last: Option<usize>,
 }
 
 impl<'a> ForthDictionary<'a> {
+    /// Keeps the dictionary in the provided slices, of which the first `len` keys and `mem_len`
+    /// bytes are in use already. The keys in use must be in the order [`ForthDictionary::words`]
+    /// gives them.
     pub fn new(
         len: usize,
         keys: &'a mut [CompiledWord<'a>],
         mem_len: usize,
-        memory: &'a mut [u32],
+        memory: &'a mut [u8],
     ) -> Self {
         ForthDictionary {
-            len,
-            keys,
-            mem_len,
-            memory,
+            keys: Buffer::borrowed(len, keys),
+            memory: Buffer::borrowed(mem_len, memory),
+            last: None,
         }
     }
 
-    fn get(&self, key: u32) -> Option<(&CompiledWord<'a>, &[u32])> {
-        // TODO: Perform binary search
-        for word in self.keys.iter() {
-            if key == get_hash(word.name) {
-                return Some((word, &self.memory[word.pos..word.pos + word.len]));
-            }
+    /// Creates a dictionary that owns its storage and grows as words are defined.
+    #[cfg(feature = "alloc")]
+    pub fn owned() -> Self {
+        ForthDictionary {
+            keys: Buffer::owned(),
+            memory: Buffer::owned(),
+            last: None,
         }
-        None
     }
 
-    fn insert(&mut self, mut word: CompiledWord<'a>, instructions: &[u32]) {
-        // TODO: Use binary search to insert compiledword.
-        word.pos = self.mem_len;
-        for i in 0..instructions.len() {
-            self.memory[word.pos + i] = instructions[i];
+    /// Number of words defined.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The defined words, sorted by the hash of their names and then in definition order.
+    pub fn words(&self) -> &[CompiledWord<'a>] {
+        self.keys.as_slice()
+    }
+
+    /// The code of every defined word, laid out back to back.
+    pub fn memory(&self) -> &[u8] {
+        self.memory.as_slice()
+    }
+
+    /// The code compiled for the body of `word`.
+    pub fn instructions(&self, word: &CompiledWord) -> &[u8] {
+        &self.memory.as_slice()[word.pos..word.pos + word.len]
+    }
+
+    /// Looks up a word by name, returning it alongside its instructions. A word defined more
+    /// than once is found as defined last.
+    pub fn lookup(&self, name: &str) -> Option<(&CompiledWord<'a>, &[u8])> {
+        let word = &self.keys.as_slice()[self.position(name)?];
+        Some((word, self.instructions(word)))
+    }
+
+    /// Index in [`words`](Self::words) of the word `lookup` finds for `name`.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        let hash = get_hash(name);
+        let words = self.keys.as_slice();
+        let end = words.partition_point(|word| word.hash <= hash);
+        // Different names may share a hash
+        words[..end]
+            .iter()
+            .rev()
+            .take_while(|word| word.hash == hash)
+            .position(|word| word.name == name)
+            .map(|back| end - 1 - back)
+    }
+
+    fn last_mut(&mut self) -> Option<&mut CompiledWord<'a>> {
+        let last = self.last?;
+        self.keys.as_mut_slice().get_mut(last)
+    }
+
+    /// Overwrites the code at offset `at` of the output of `compilation` with `instructions` in
+    /// the body of the word holding it, if any.
+    fn patch(&mut self, compilation: usize, at: usize, instructions: &[u8]) {
+        let body = |word: &CompiledWord| word.addr + primitives::ENTER.len() * 4;
+        let holding = self.keys.as_slice().iter().find(|word| {
+            word.compilation == compilation && (body(word)..body(word) + word.len).contains(&at)
+        });
+        if let Some(word) = holding {
+            let start = word.pos + at - body(word);
+            self.memory.as_mut_slice()[start..start + instructions.len()]
+                .copy_from_slice(instructions);
         }
-        self.mem_len += word.len;
-        self.keys[self.len] = word;
-        self.len += 1;
     }
+
+    /// How much of its storage the dictionary uses.
+    pub fn usage(&self) -> DictionaryUsage {
+        DictionaryUsage {
+            words: self.keys.len(),
+            word_capacity: self.keys.capacity(),
+            memory: self.memory.len(),
+            memory_capacity: self.memory.capacity(),
+        }
+    }
+
+    /// Adds `word` after the words with the same hash, so that it shadows earlier definitions
+    /// of its name. Leaves the dictionary as it was when either the keys or the memory are full.
+    fn insert(
+        &mut self,
+        mut word: CompiledWord<'a>,
+        instructions: &[u8],
+    ) -> Result<(), CompilerError> {
+        if !self.keys.has_room(1) {
+            return Err(CompilerError::DictionaryFull);
+        }
+        word.pos = self.memory.len();
+        self.memory
+            .try_extend_from_slice(&instructions[..word.len])
+            .map_err(|_| CompilerError::DictionaryMemoryFull)?;
+        let idx = self
+            .keys
+            .as_slice()
+            .partition_point(|key| key.hash <= word.hash);
+        self.keys
+            .try_insert(idx, word)
+            .map_err(|_| CompilerError::DictionaryFull)?;
+        self.last = Some(idx);
+        Ok(())
+    }
+}
+
+/// How much of its storage a [`ForthDictionary`] uses. Capacities are `None` for owned storage,
+/// which grows as needed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DictionaryUsage {
+    /// Number of words defined.
+    pub words: usize,
+    pub word_capacity: Option<usize>,
+    /// Bytes of code held for the bodies of the words.
+    pub memory: usize,
+    pub memory_capacity: Option<usize>,
+}
+
+impl DictionaryUsage {
+    /// Number of words that can still be defined.
+    pub fn free_words(&self) -> Option<usize> {
+        self.word_capacity.map(|capacity| capacity - self.words)
+    }
+
+    /// Bytes of code the memory can still hold.
+    pub fn free_memory(&self) -> Option<usize> {
+        self.memory_capacity.map(|capacity| capacity - self.memory)
+    }
+}
+
+/// Where the cells reserved by `VARIABLE`, `VALUE`, `CREATE`, `ALLOT` and `,` lie, in the order
+/// they were reserved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataSegment {
+    pub address: u32,
+    /// Size in bytes.
+    pub len: usize,
+}
+
+/// Where the code compiled from a token of a definition starts, to show the source of a word
+/// alongside its instructions in a [`Listing`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SourceMark {
+    /// The word, by its [`CompiledWord::compilation`] and [`CompiledWord::addr`].
+    pub compilation: usize,
+    pub addr: usize,
+    /// Offset of the code in the body of the word.
+    pub offset: usize,
+    /// Offset of the token in [`CompiledWord::original`].
+    pub source: usize,
 }
 
 fn get_hash(s: &str) -> u32 {
@@ -80,98 +305,1723 @@ fn get_hash(s: &str) -> u32 {
 
 pub struct ForthCompiler<'a> {
     dictionary: ForthDictionary<'a>,
+    relocations: Buffer<'a, Relocation<'a>>,
+    marks: Buffer<'a, SourceMark>,
+    actions: Buffer<'a, Action<'a>>,
+    inline_threshold: usize,
+    target: Target,
+    top_of_stack: TopOfStack,
+    optimize: bool,
+    compilations: usize,
+    data_address: u32,
+    /// Bytes reserved in the data segment, and how many of them earlier compilations reserved.
+    data_len: usize,
+    data_start: usize,
+}
+
+/// Builds a [`ForthCompiler`] over either caller provided or owned dictionary storage.
+pub struct ForthCompilerBuilder<'a> {
+    dictionary: Option<ForthDictionary<'a>>,
+    relocations: Option<&'a mut [Relocation<'a>]>,
+    marks: Option<&'a mut [SourceMark]>,
+    actions: Option<&'a mut [Action<'a>]>,
+    inline_threshold: usize,
+    target: Target,
+    top_of_stack: TopOfStack,
+    optimize: bool,
+    data_address: u32,
+}
+
+impl<'a> ForthCompilerBuilder<'a> {
+    pub fn new() -> Self {
+        ForthCompilerBuilder {
+            dictionary: None,
+            relocations: None,
+            marks: None,
+            actions: None,
+            inline_threshold: ForthCompiler::DEFAULT_INLINE_THRESHOLD,
+            target: Target::default(),
+            top_of_stack: TopOfStack::default(),
+            optimize: false,
+            data_address: ForthCompiler::DEFAULT_DATA_ADDRESS,
+        }
+    }
+
+    /// Stores the dictionary in the provided slices, both start out empty.
+    pub fn storage(self, keys: &'a mut [CompiledWord<'a>], memory: &'a mut [u8]) -> Self {
+        self.dictionary(ForthDictionary::new(0, keys, 0, memory))
+    }
+
+    /// Continues compiling on top of an existing dictionary, whose words are reached through
+    /// relocations like those of earlier outputs. The data segment starts over at
+    /// [`data_address`](Self::data_address).
+    pub fn dictionary(mut self, dictionary: ForthDictionary<'a>) -> Self {
+        self.dictionary = Some(dictionary);
+        self
+    }
+
+    /// Lets the dictionary own its storage, growing it as words are defined.
+    #[cfg(feature = "alloc")]
+    pub fn owned_storage(self) -> Self {
+        self.dictionary(ForthDictionary::owned())
+    }
+
+    /// Stores the relocations of each compilation in the provided slice instead of an owned one.
+    pub fn relocations(mut self, relocations: &'a mut [Relocation<'a>]) -> Self {
+        self.relocations = Some(relocations);
+        self
+    }
+
+    /// Records where the code of each token of a definition starts in the provided slice instead
+    /// of an owned one, for [`ForthCompiler::see`]. Recording stops once it is full.
+    pub fn source_marks(mut self, marks: &'a mut [SourceMark]) -> Self {
+        self.marks = Some(marks);
+        self
+    }
+
+    /// Queues what the words run while compiling ask to compile, as `,` and `POSTPONE` do, in
+    /// the provided slice instead of an owned one. The words run for a single token of the
+    /// source can't queue more than it holds.
+    pub fn actions(mut self, actions: &'a mut [Action<'a>]) -> Self {
+        self.actions = Some(actions);
+        self
+    }
+
+    /// Words whose body takes at most this many bytes are copied into their callers instead of
+    /// being called, as long as they don't call other words themselves.
+    pub fn inline_threshold(mut self, bytes: usize) -> Self {
+        self.inline_threshold = bytes;
+        self
+    }
+
+    /// The instruction set to compile for, plain RV32I by default.
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Where the compiled code keeps the top of the data stack, in memory like the other cells
+    /// by default.
+    pub fn top_of_stack(mut self, top_of_stack: TopOfStack) -> Self {
+        self.top_of_stack = top_of_stack;
+        self
+    }
+
+    /// Rewrites the code of consecutive words to keep values in registers rather than storing
+    /// and loading them back, and to fold pushed numbers into the words using them. Off by
+    /// default.
+    pub fn optimize(mut self, optimize: bool) -> Self {
+        self.optimize = optimize;
+        self
+    }
+
+    /// Address of the data segment, where the cells reserved by the compiled code are laid out
+    /// one after the other. The start of the RAM of `link.x` by default.
+    pub fn data_address(mut self, address: u32) -> Self {
+        self.data_address = address;
+        self
+    }
+
+    /// Builds the compiler. Without any storage configured the dictionary, relocations, source
+    /// marks and actions are owned when the `alloc` feature is enabled and empty, unable to hold
+    /// anything, otherwise.
+    pub fn build(self) -> ForthCompiler<'a> {
+        let dictionary = match self.dictionary {
+            Some(dictionary) => dictionary,
+            #[cfg(feature = "alloc")]
+            None => ForthDictionary::owned(),
+            #[cfg(not(feature = "alloc"))]
+            None => ForthDictionary::new(0, &mut [], 0, &mut []),
+        };
+        let relocations = match self.relocations {
+            Some(relocations) => Buffer::borrowed(0, relocations),
+            #[cfg(feature = "alloc")]
+            None => Buffer::owned(),
+            #[cfg(not(feature = "alloc"))]
+            None => Buffer::borrowed(0, &mut []),
+        };
+        let marks = match self.marks {
+            Some(marks) => Buffer::borrowed(0, marks),
+            #[cfg(feature = "alloc")]
+            None => Buffer::owned(),
+            #[cfg(not(feature = "alloc"))]
+            None => Buffer::borrowed(0, &mut []),
+        };
+        let actions = match self.actions {
+            Some(actions) => Buffer::borrowed(0, actions),
+            #[cfg(feature = "alloc")]
+            None => Buffer::owned(),
+            #[cfg(not(feature = "alloc"))]
+            None => Buffer::borrowed(0, &mut []),
+        };
+        // Later outputs must not take the words of earlier ones for their own
+        let compilations = dictionary
+            .words()
+            .iter()
+            .map(|word| word.compilation + 1)
+            .max()
+            .unwrap_or(0);
+        ForthCompiler {
+            dictionary,
+            relocations,
+            marks,
+            actions,
+            inline_threshold: self.inline_threshold,
+            target: self.target,
+            top_of_stack: self.top_of_stack,
+            optimize: self.optimize,
+            compilations,
+            data_address: self.data_address,
+            data_len: 0,
+            data_start: 0,
+        }
+    }
 }
 
-pub enum CompilerError {
-    WordOutOfBounds,
-    MalformedCompilation,
-    UnrecognizedToken,
+impl<'a> Default for ForthCompilerBuilder<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a> ForthCompiler<'a> {
-    const COMPILE_BUFFER_SIZE: usize = 255;
-    pub fn compile(
+    /// Inline words no larger than the `jal` calling them.
+    const DEFAULT_INLINE_THRESHOLD: usize = 4;
+
+    const DEFAULT_DATA_ADDRESS: u32 = 0x0000_c800;
+
+    pub fn builder() -> ForthCompilerBuilder<'a> {
+        ForthCompilerBuilder::new()
+    }
+
+    pub fn dictionary(&self) -> &ForthDictionary<'a> {
+        &self.dictionary
+    }
+
+    /// Consumes the compiler, handing back the dictionary it built.
+    pub fn into_dictionary(self) -> ForthDictionary<'a> {
+        self.dictionary
+    }
+
+    /// The relocations of the last compilation, referring to words in earlier outputs.
+    pub fn relocations(&self) -> &[Relocation<'a>] {
+        self.relocations.as_slice()
+    }
+
+    /// Lists the instructions of `name` along with its source, `None` if the word isn't defined.
+    pub fn see(&self, name: &str) -> Option<Listing<'_>> {
+        let (word, instructions) = self.dictionary.lookup(name)?;
+        let marks = self.marks.as_slice();
+        let of_word =
+            |mark: &SourceMark| mark.compilation == word.compilation && mark.addr == word.addr;
+        let start = marks.iter().position(of_word).unwrap_or(marks.len());
+        let len = marks[start..]
+            .iter()
+            .take_while(|mark| of_word(mark))
+            .count();
+        Some(Listing::new(
+            word.original,
+            instructions,
+            &marks[start..start + len],
+        ))
+    }
+
+    /// The part of the data segment reserved by the last compilation, following that of earlier
+    /// ones.
+    pub fn data_segment(&self) -> DataSegment {
+        DataSegment {
+            address: self.data_address.wrapping_add(self.data_start as u32),
+            len: self.data_len - self.data_start,
+        }
+    }
+
+    /// Prepares the output of the last compilation to run from `address`, recording where its
+    /// words are so later outputs can refer to them. Words in earlier outputs must have been
+    /// relocated already.
+    pub fn relocate(&mut self, code: &mut [u8], address: u32) -> Result<(), CompilerError> {
+        let compilation = self.compilations.wrapping_sub(1);
+        for word in self.dictionary.keys.as_mut_slice() {
+            if word.compilation == compilation {
+                word.load_address = Some(address.wrapping_add(word.addr as u32));
+            }
+        }
+        let dictionary = &self.dictionary;
+        relocate(code, self.relocations.as_slice(), address, |relocation| {
+            dictionary
+                .words()
+                .iter()
+                .find(|word| relocation.refers_to(word))
+                .and_then(|word| word.load_address)
+        })
+    }
+
+    /// Wraps the output of the last compilation into an ELF32 RISC-V relocatable object written
+    /// to `object`, returning its length.
+    ///
+    /// The code is placed in a `.text.forth` section with a global symbol for each word defined
+    /// by the compilation, and a `forth_init_<compilation>` one for the code outside of
+    /// definitions, while its relocations refer to undefined symbols named after the words they
+    /// reach. The cells it reserved are laid out with the values they start with in a
+    /// `.data.forth` section, which the code refers to through relocations too. The object can
+    /// then be linked along the firmware with `link.x`.
+    pub fn write_object(&self, code: &[u8], object: &mut [u8]) -> Result<usize, CompilerError> {
+        elf::write_object(
+            code,
+            self.target,
+            self.dictionary.words(),
+            self.compilations.wrapping_sub(1),
+            self.relocations.as_slice(),
+            self.data_start as u32,
+            self.data_segment().len,
+            object,
+        )
+    }
+
+    /// Compiles `code` into `output`, returning the number of bytes written.
+    ///
+    /// Words are compiled in place, behind a jump that skips over them, and called with `jal`
+    /// unless they are inlined. The return point is kept on the return stack while they run.
+    /// Words from earlier outputs are reached through [`ForthCompiler::relocations`].
+    ///
+    /// Errors are reported along with the token that caused them.
+    pub fn compile(&mut self, code: &'a str, output: &mut [u8]) -> Result<usize, Diagnostic<'a>> {
+        let mut tokens = Tokens::new(code);
+        let actions = core::mem::replace(&mut self.actions, Buffer::borrowed(0, &mut []));
+        let mut interpreter = Interpreter::new(actions);
+        let result = self.compile_tokens(code, &mut tokens, &mut interpreter, output);
+        self.actions = interpreter.into_actions();
+        result.map_err(|error| Diagnostic::new(error, code, tokens.span()))
+    }
+
+    fn compile_tokens(
         &mut self,
         code: &'a str,
-        output: &'a mut [u32],
+        tokens: &mut Tokens<'a>,
+        interpreter: &mut Interpreter<'a>,
+        output: &mut [u8],
     ) -> Result<usize, CompilerError> {
-        let mut split = code.split_ascii_whitespace().peekable();
-        let mut alloc_ptr = 0;
-        let mut code_idx = 0;
+        let mut output = Output::new(output, self.target, self.optimize);
+        let mut control = ControlStack::new();
+        let mut leaves = ControlStack::new();
+        let mut routines = Routines::new();
+        let mut effects = Effects::new();
+        let mut data = DataSection::new();
+        let top = self.top_of_stack;
+        let compilation = self.compilations;
+        self.compilations += 1;
+        self.relocations.clear();
 
-        let mut compiling = false;
+        let mut state = State::TopLevel;
         let mut compiling_from = 0;
-        let mut compiling_instruction_idx = 0;
-        let mut compiling_instructions = [0; Self::COMPILE_BUFFER_SIZE];
-        let mut name: &str = "";
-        while let Some(token) = split.next() {
-            if token == ":" {
-                if let Some(_name) = split.peek() {
-                    name = _name;
-                    compiling = true;
-                    compiling_from = code_idx;
-                    compiling_instruction_idx = 0;
+        let mut compiling_skip = 0;
+        let mut compiling_body = 0;
+        let mut compiling_leaf = true;
+        let mut name: Option<&str> = None;
+        let mut defining = false;
+        let mut does = false;
+        // Numbers read outside of definitions wait on the stack of the interpreter, in case the
+        // next token takes them while compiling, and are compiled otherwise
+        let mut literals = 0;
+        self.data_start = self.data_len;
+        loop {
+            // What the words run by the interpreter compile comes before the rest of the source
+            let (token, queued, call) = match interpreter.next_action() {
+                Some(action) => {
+                    compile_literals(interpreter.take(literals)?, top, &mut output, &mut effects)?;
+                    literals = 0;
+                    match action {
+                        Action::Compile(token) => (token, true, false),
+                        Action::Call(name) => (name, true, true),
+                        Action::Literal(value) => {
+                            compile_literals(&[value], top, &mut output, &mut effects)?;
+                            continue;
+                        }
+                        Action::Cell(cell) if state == State::TopLevel => {
+                            let address = self.reserve(4);
+                            self.initialize(address, cell, &mut output)?;
+                            continue;
+                        }
+                        Action::Cell(cell) => {
+                            effects.unknown();
+                            output.emit_fixed(&[cell])?;
+                            continue;
+                        }
+                        Action::Allot(bytes) => {
+                            self.allot(bytes)?;
+                            continue;
+                        }
+                        Action::Create { does } => {
+                            let name = tokens.next().ok_or(CompilerError::MalformedCompilation)?;
+                            self.check_name(name, compilation)?;
+                            let address = self.reserve(0);
+                            let body = Body::Cells {
+                                address,
+                                fetch: false,
+                                does,
+                            };
+                            self.define(name, name, body, &mut output, compilation)?;
+                            continue;
+                        }
+                    }
+                }
+                None => match tokens.next() {
+                    Some(token) => (token, false, false),
+                    None => break,
+                },
+            };
+            if state == State::Compiling && !queued && name.is_some() {
+                // Listings show each part of the source above the code compiled from it
+                let mark = SourceMark {
+                    compilation,
+                    addr: compiling_skip + 4,
+                    offset: output.as_slice().len() - compiling_body,
+                    source: tokens.span().start - compiling_from,
+                };
+                let _ = self.marks.try_push(mark);
+            }
+            let word = self.dictionary.lookup(token).map(|(word, _)| word);
+            if state == State::TopLevel && !queued && word.is_none() {
+                if let Some(value) = parse_number(token) {
+                    if interpreter.push(value).is_err() {
+                        compile_literals(
+                            interpreter.take(literals)?,
+                            top,
+                            &mut output,
+                            &mut effects,
+                        )?;
+                        literals = 0;
+                        interpreter.push(value)?;
+                    }
+                    literals += 1;
+                    continue;
+                }
+            }
+            let runs = word.is_some_and(|word| (word.immediate || word.defining) && !call);
+            if literals > 0 && !runs && !TAKING_LITERALS.contains(&token) {
+                compile_literals(interpreter.take(literals)?, top, &mut output, &mut effects)?;
+                literals = 0;
+            }
+            if token == ":" || token == ":NONAME" {
+                if state != State::TopLevel {
+                    return Err(CompilerError::NestedDefinition);
+                }
+                if !control.is_empty() {
+                    return Err(CompilerError::UnbalancedControlFlow);
+                }
+                compiling_from = tokens.span().start;
+                name = None;
+                if token == ":" {
+                    let _name = tokens.next().ok_or(CompilerError::MalformedCompilation)?;
+                    self.check_name(_name, compilation)?;
+                    name = Some(_name);
+                }
+                state = State::Compiling;
+                compiling_skip = output.len();
+                output.emit(&[0])?;
+                output.emit(&primitives::ENTER)?;
+                compiling_body = output.len();
+                compiling_leaf = true;
+                let declared = if tokens.next_if_eq("(").is_some() {
+                    let comment = tokens
+                        .parse(')')
+                        .ok_or(CompilerError::MalformedCompilation)?;
+                    Some(StackEffect::parse(comment.split_ascii_whitespace())?)
                 } else {
-                    return Err(CompilerError::MalformedCompilation);
+                    None
+                };
+                effects.start(declared);
+                // Only the code following DOES> of a word defining others runs on the target,
+                // what comes before runs from the source while compiling
+                let mut ahead = Tokens::new(code);
+                ahead.seek(tokens.offset());
+                (defining, does) = (false, false);
+                if let Some((position, has_does)) = defining_part(&mut ahead) {
+                    tokens.seek(position);
+                    (defining, does) = (true, has_does);
+                    effects.start(None);
                 }
             } else if token == ";" {
-                let compiled_word = CompiledWord::new(
-                    name,
-                    &code[compiling_from..code_idx],
-                    compiling_instruction_idx,
-                );
-
-                self.dictionary
-                    .insert(compiled_word, &compiling_instructions);
-            } else if let Ok(primitive) = Primitive::try_from(token) {
-                let (len, instructions) = primitive.get_instructions();
-                for idx in 0..len {
-                    let instruction = instructions[idx];
-                    if compiling {
-                        if compiling_instruction_idx >= Self::COMPILE_BUFFER_SIZE {
-                            return Err(CompilerError::WordOutOfBounds);
-                        }
-                        compiling_instructions[compiling_instruction_idx] = instruction;
-                        compiling_instruction_idx += 1;
-                    } else {
-                        output[alloc_ptr] = instruction;
-                        alloc_ptr += 1;
-                    }
+                match state {
+                    State::TopLevel => return Err(CompilerError::CompileOnly),
+                    State::Interpreting => return Err(CompilerError::UnbalancedControlFlow),
+                    State::Compiling => {}
+                }
+                if !control.is_empty() {
+                    return Err(CompilerError::UnbalancedControlFlow);
+                }
+                let effect = effects.finish()?;
+                if let Some(name) = name {
+                    let len = output.len() - compiling_body;
+                    let mut compiled_word =
+                        CompiledWord::new(name, &code[compiling_from..tokens.span().end], len);
+                    compiled_word.addr = compiling_skip + 4;
+                    compiled_word.compilation = compilation;
+                    compiled_word.leaf = compiling_leaf;
+                    compiled_word.inline =
+                        !defining && compiling_leaf && len <= self.inline_threshold;
+                    compiled_word.effect = effect;
+                    compiled_word.defining = defining;
+                    compiled_word.does = does;
+
+                    self.dictionary
+                        .insert(compiled_word, &output.as_slice()[compiling_body..])?;
+                }
+                output.emit(&primitives::EXIT)?;
+                let len = output.len();
+                Control::Else(compiling_skip).resolve(output.as_mut_slice(), len)?;
+                if name.is_none() {
+                    // The word can only be reached through the address left on the stack
+                    let offset = (compiling_skip + 4) as i32 - output.len() as i32;
+                    output.emit_fixed(&RelocationKind::Address.encode(offset))?;
+                    output.emit(top.push_a0())?;
+                    effects.apply(StackEffect::new(0, 1))?;
+                }
+                state = State::TopLevel;
+            } else if token == "(" {
+                tokens
+                    .parse(')')
+                    .ok_or(CompilerError::MalformedCompilation)?;
+            } else if token == "\\" {
+                // A comment running to the end of the source is fine too
+                tokens.parse('\n');
+            } else if token == "[" {
+                match state {
+                    State::TopLevel => return Err(CompilerError::CompileOnly),
+                    State::Interpreting => return Err(CompilerError::UnbalancedControlFlow),
+                    State::Compiling => state = State::Interpreting,
+                }
+            } else if token == "]" {
+                match state {
+                    State::TopLevel => return Err(CompilerError::CompileOnly),
+                    State::Compiling => return Err(CompilerError::UnbalancedControlFlow),
+                    State::Interpreting => state = State::Compiling,
+                }
+            } else if state == State::Interpreting && !queued {
+                interpreter.evaluate(token, tokens, &self.dictionary)?;
+            } else if token == "LITERAL" {
+                if state != State::Compiling {
+                    return Err(CompilerError::CompileOnly);
                 }
-            } else if let Some((_word, compiled)) = self.dictionary.get(get_hash(token)) {
-                // XXX: We need to add some logic to decide when to branch vs when to append, For now we always clone, which will likely produce masive binaries
-                for w in compiled.iter() {
-                    if compiling {
-                        if compiling_instruction_idx >= Self::COMPILE_BUFFER_SIZE {
-                            return Err(CompilerError::WordOutOfBounds);
+                effects.apply(StackEffect::new(0, 1))?;
+                let value = Primitive::Push(interpreter.pop()?);
+                let (len, instructions) = top.instructions(&value);
+                output.emit(&instructions[..len])?;
+            } else if token == "S\"" || token == "C\"" || token == ".\"" {
+                let string = tokens
+                    .parse('"')
+                    .ok_or(CompilerError::MalformedCompilation)?;
+                data.load(string, token == "C\"", &mut output)?;
+                output.emit(top.push_a0())?;
+                if token == "C\"" {
+                    effects.apply(StackEffect::new(0, 1))?;
+                } else {
+                    effects.apply(StackEffect::new(0, 2))?;
+                    let len = Primitive::Push(string.len() as u32);
+                    let (len, instructions) = top.instructions(&len);
+                    output.emit(&instructions[..len])?;
+                }
+                if token == ".\"" {
+                    effects.apply(Routine::Type.effect())?;
+                    let address = routines.address(Routine::Type, top, &mut output)?;
+                    output.emit(&[Branch::Call.encode(output.len(), address)?])?;
+                }
+                // The address is pc relative, so the code can't be copied elsewhere
+                compiling_leaf = false;
+            } else if matches!(token, "VARIABLE" | "CREATE" | "VALUE" | "CONSTANT") {
+                if state != State::TopLevel {
+                    return Err(CompilerError::InterpretOnly);
+                }
+                let from = tokens.span().start;
+                let name = tokens.next().ok_or(CompilerError::MalformedCompilation)?;
+                self.check_name(name, compilation)?;
+                let body = match token {
+                    "CONSTANT" => Body::Constant(interpreter.pop()?),
+                    "VALUE" => {
+                        let value = interpreter.pop()?;
+                        let address = self.reserve(4);
+                        self.initialize(address, value, &mut output)?;
+                        Body::Cells {
+                            address,
+                            fetch: true,
+                            does: None,
                         }
-                        compiling_instructions[compiling_instruction_idx] = *w;
-                        compiling_instruction_idx += 1;
-                    } else {
-                        output[alloc_ptr] = *w;
-                        alloc_ptr += 1;
                     }
+                    _ => Body::Cells {
+                        address: self.reserve(if token == "VARIABLE" { 4 } else { 0 }),
+                        fetch: false,
+                        does: None,
+                    },
+                };
+                let original = &code[from..tokens.span().end];
+                self.define(name, original, body, &mut output, compilation)?;
+            } else if token == "ALLOT" {
+                if state != State::TopLevel {
+                    return Err(CompilerError::InterpretOnly);
                 }
-            } else if let Ok(n) = token.parse::<u32>() {
-                let (len, instructions) = Primitive::Push(n).get_instructions();
-                for idx in 0..len {
-                    let instruction = instructions[idx];
-                    if compiling {
-                        if compiling_instruction_idx >= Self::COMPILE_BUFFER_SIZE {
-                            return Err(CompilerError::WordOutOfBounds);
-                        }
-                        compiling_instructions[compiling_instruction_idx] = instruction;
-                        compiling_instruction_idx += 1;
-                    } else {
-                        output[alloc_ptr] = instruction;
-                        alloc_ptr += 1;
+                let bytes = interpreter.pop()?;
+                self.allot(bytes)?;
+            } else if token == "," && state == State::TopLevel {
+                let value = interpreter.pop()?;
+                let address = self.reserve(4);
+                self.initialize(address, value, &mut output)?;
+            } else if token == "TO" {
+                let name = tokens.next().ok_or(CompilerError::MalformedCompilation)?;
+                let (word, _) = self
+                    .dictionary
+                    .lookup(name)
+                    .ok_or(CompilerError::UnrecognizedToken)?;
+                let address = match word.data {
+                    Some(address) if word.fetch => address,
+                    _ => return Err(CompilerError::MalformedCompilation),
+                };
+                effects.apply(StackEffect::new(1, 0))?;
+                self.store(address, &mut output)?;
+                compiling_leaf = false;
+            } else if token == "DOES>" {
+                // Those of words defining others are skipped along with what comes before
+                return Err(match state {
+                    State::TopLevel => CompilerError::CompileOnly,
+                    _ => CompilerError::MalformedCompilation,
+                });
+            } else if token == "POSTPONE" || token == "," || token == "COMPILE," {
+                // They only do something when the word is run by the interpreter
+                if state == State::TopLevel {
+                    return Err(CompilerError::CompileOnly);
+                }
+                if token == "POSTPONE" {
+                    let name = tokens.next().ok_or(CompilerError::MalformedCompilation)?;
+                    if !is_builtin(name) && self.dictionary.lookup(name).is_none() {
+                        return Err(CompilerError::UnrecognizedToken);
                     }
                 }
+                effects.unknown();
+            } else if token == "INLINE" {
+                match self.dictionary.last_mut() {
+                    Some(word) if word.leaf && state == State::TopLevel => word.inline = true,
+                    _ => return Err(CompilerError::MalformedCompilation),
+                }
+            } else if token == "IMMEDIATE" {
+                match self.dictionary.last_mut() {
+                    Some(word) if state == State::TopLevel => word.immediate = true,
+                    _ => return Err(CompilerError::MalformedCompilation),
+                }
+            } else if token == "IF" {
+                effects.apply(StackEffect::new(1, 0))?;
+                effects.open(false)?;
+                output.emit(top.pop_a0())?;
+                control.push(Control::If(output.len()))?;
+                output.emit(&[0])?;
+            } else if token == "ELSE" {
+                let Control::If(branch) = control.pop()? else {
+                    return Err(CompilerError::UnbalancedControlFlow);
+                };
+                effects.otherwise();
+                control.push(Control::Else(output.len()))?;
+                output.emit(&[0])?;
+                let len = output.len();
+                Control::If(branch).resolve(output.as_mut_slice(), len)?;
+            } else if token == "THEN" {
+                effects.close()?;
+                let len = output.len();
+                control.pop()?.resolve(output.as_mut_slice(), len)?;
+            } else if token == "BEGIN" {
+                effects.open(false)?;
+                control.push(Control::Begin(output.len()))?;
+            } else if token == "UNTIL" {
+                let destination = control.pop()?.destination()?;
+                effects.apply(StackEffect::new(1, 0))?;
+                effects.close()?;
+                output.emit(top.pop_a0())?;
+                output.emit(&[Branch::IfZero.encode(output.len(), destination)?])?;
+            } else if token == "AGAIN" {
+                let destination = control.pop()?.destination()?;
+                effects.close()?;
+                output.emit(&[Branch::Always.encode(output.len(), destination)?])?;
+            } else if token == "WHILE" {
+                let begin = control.pop()?;
+                begin.destination()?;
+                effects.apply(StackEffect::new(1, 0))?;
+                effects.fork()?;
+                output.emit(top.pop_a0())?;
+                control.push(Control::If(output.len()))?;
+                control.push(begin)?;
+                output.emit(&[0])?;
+            } else if token == "REPEAT" {
+                let destination = control.pop()?.destination()?;
+                effects.close()?;
+                effects.join();
+                output.emit(&[Branch::Always.encode(output.len(), destination)?])?;
+                let len = output.len();
+                control.pop()?.resolve(output.as_mut_slice(), len)?;
+            } else if token == "DO" {
+                effects.apply(StackEffect::new(2, 0))?;
+                effects.open(true)?;
+                output.emit(top.loop_params())?;
+                control.push(Control::Do(output.len()))?;
+                leaves.push(Control::Do(output.len()))?;
+            } else if token == "?DO" {
+                effects.apply(StackEffect::new(2, 0))?;
+                effects.open(true)?;
+                output.emit(top.loop_params())?;
+                leaves.push(Control::Do(output.len()))?;
+                // Skip the loop straight away when the index already is at the limit
+                output.emit(&[Branch::IfNotEqual.encode(output.len(), output.len() + 8)?])?;
+                leaves.push(Control::Leave(output.len()))?;
+                output.emit(&[0])?;
+                control.push(Control::Do(output.len()))?;
+            } else if token == "LOOP" || token == "+LOOP" {
+                let body = control.pop()?.loop_body()?;
+                if token == "+LOOP" {
+                    effects.apply(StackEffect::new(1, 0))?;
+                }
+                effects.close()?;
+                let (step, branch) = if token == "LOOP" {
+                    (&primitives::LOOP_STEP[..], Branch::IfNotEqual)
+                } else {
+                    (top.plus_loop_step(), Branch::IfPositive)
+                };
+                output.emit(step)?;
+                output.emit(&[branch.encode(output.len(), body)?])?;
+                let exit = output.len();
+                let (len, instructions) = Primitive::Unloop.get_instructions();
+                output.emit(&instructions[..len])?;
+                leaves.resolve_leaves(output.as_mut_slice(), exit)?;
+            } else if token == "LEAVE" {
+                if leaves.is_empty() {
+                    return Err(CompilerError::UnbalancedControlFlow);
+                }
+                effects.leave()?;
+                leaves.push(Control::Leave(output.len()))?;
+                output.emit(&[0])?;
+            } else if token == "'" || token == "[']" {
+                let name = tokens.next().ok_or(CompilerError::MalformedCompilation)?;
+                let (word, _) = self
+                    .dictionary
+                    .lookup(name)
+                    .ok_or(CompilerError::UnrecognizedToken)?;
+                effects.apply(StackEffect::new(0, 1))?;
+                if word.compilation == compilation {
+                    let offset = word.addr as i32 - output.len() as i32;
+                    output.emit_fixed(&RelocationKind::Address.encode(offset))?;
+                } else {
+                    let relocation = Relocation::new(output.len(), RelocationKind::Address, word);
+                    self.relocations
+                        .try_push(relocation)
+                        .map_err(|_| CompilerError::TooManyRelocations)?;
+                    output.emit_fixed(&RelocationKind::Address.encode(0))?;
+                }
+                output.emit(top.push_a0())?;
+                // The address is pc relative, so the code can't be copied elsewhere
+                compiling_leaf = false;
+            } else if let Ok(primitive) = token.parse::<Primitive>() {
+                match primitive.effect() {
+                    Some(effect) => effects.apply(effect)?,
+                    None => effects.unknown(),
+                }
+                let (len, instructions) = top.instructions(&primitive);
+                output.emit(&instructions[..len])?;
+            } else if let Ok(routine) = token.parse::<Routine>() {
+                effects.apply(routine.effect())?;
+                if let Some(instructions) = routine.native(self.target, top) {
+                    output.emit(instructions)?;
+                } else {
+                    let address = routines.address(routine, top, &mut output)?;
+                    output.emit(&[Branch::Call.encode(output.len(), address)?])?;
+                    compiling_leaf = false;
+                }
+            } else if let Some((word, _)) = self
+                .dictionary
+                .lookup(token)
+                .filter(|(word, _)| (word.immediate || word.defining) && !call)
+            {
+                if word.defining && state != State::TopLevel {
+                    return Err(CompilerError::InterpretOnly);
+                }
+                interpreter.execute(word, &self.dictionary)?;
+            } else if let Some((word, compiled)) = self.dictionary.lookup(token) {
+                match word.effect {
+                    Some(effect) => effects.apply(effect)?,
+                    None => effects.unknown(),
+                }
+                if word.inline {
+                    output.bytes(compiled)?;
+                } else {
+                    let name = word.name;
+                    self.call(name, &mut output, compilation)?;
+                    compiling_leaf = false;
+                }
+            } else if let Some(n) = parse_number(token) {
+                effects.apply(StackEffect::new(0, 1))?;
+                let (len, instructions) = top.instructions(&Primitive::Push(n));
+                output.emit(&instructions[..len])?;
             } else {
                 return Err(CompilerError::UnrecognizedToken);
             }
-            code_idx += 1;
+            output.release_registers()?;
+            literals = literals.min(interpreter.depth());
+        }
+        compile_literals(interpreter.take(literals)?, top, &mut output, &mut effects)?;
+        if state != State::TopLevel {
+            return Err(CompilerError::UnterminatedDefinition);
+        }
+        if !control.is_empty() {
+            return Err(CompilerError::UnbalancedControlFlow);
+        }
+        data.write(&mut output)?;
+        // Words hold on to their code, where the addresses of the strings were only known now
+        for at in data.offsets() {
+            let instructions = &output.as_slice()[at..at + 8];
+            self.dictionary.patch(compilation, at, instructions);
+        }
+        Ok(output.len())
+    }
+}
+
+impl<'a> ForthCompiler<'a> {
+    /// Fails when `name` is built in or already defined by the current compilation.
+    fn check_name(&self, name: &str, compilation: usize) -> Result<(), CompilerError> {
+        let defined = self
+            .dictionary
+            .lookup(name)
+            .is_some_and(|(word, _)| word.compilation == compilation);
+        if defined || is_builtin(name) {
+            return Err(CompilerError::DuplicateDefinition);
+        }
+        Ok(())
+    }
+
+    /// Compiles a call to the word `name`, through a relocation when it is in an earlier output.
+    fn call(
+        &mut self,
+        name: &'a str,
+        output: &mut Output,
+        compilation: usize,
+    ) -> Result<(), CompilerError> {
+        let (word, _) = self
+            .dictionary
+            .lookup(name)
+            .ok_or(CompilerError::UnrecognizedToken)?;
+        if word.compilation == compilation {
+            return output.emit(&[Branch::Call.encode(output.len(), word.addr)?]);
+        }
+        let relocation = Relocation::new(output.len(), RelocationKind::Call, word);
+        self.relocations
+            .try_push(relocation)
+            .map_err(|_| CompilerError::TooManyRelocations)?;
+        output.emit_fixed(&RelocationKind::Call.encode(0))
+    }
+
+    /// Compiles the word `name` outside of any definition, as `:` and `;` would around `body`.
+    fn define(
+        &mut self,
+        name: &'a str,
+        original: &'a str,
+        body: Body<'a>,
+        output: &mut Output,
+        compilation: usize,
+    ) -> Result<(), CompilerError> {
+        let top = self.top_of_stack;
+        let skip = output.len();
+        output.emit(&[0])?;
+        output.emit(&primitives::ENTER)?;
+        let start = output.len();
+        let mut word = CompiledWord::new(name, original, 0);
+        word.effect = Some(StackEffect::new(0, 1));
+        match body {
+            Body::Constant(value) => {
+                let (len, instructions) = top.instructions(&Primitive::Push(value));
+                output.emit(&instructions[..len])?;
+                word.value = Some(value);
+                word.inline = true;
+            }
+            Body::Cells {
+                address,
+                fetch,
+                does,
+            } => {
+                self.push_data_address(address, output)?;
+                if fetch {
+                    let (len, instructions) = top.instructions(&Primitive::Fetch);
+                    output.emit(&instructions[..len])?;
+                } else if let Some(does) = does {
+                    self.call(does, output, compilation)?;
+                    word.effect = None;
+                } else {
+                    word.value = Some(address);
+                }
+                word.data = Some(address);
+                word.fetch = fetch;
+                // The address may be relocated, so the code can't be copied elsewhere
+                word.leaf = false;
+            }
+        }
+        word.len = output.len() - start;
+        word.addr = skip + 4;
+        word.compilation = compilation;
+        self.dictionary.insert(word, &output.as_slice()[start..])?;
+        output.emit(&primitives::EXIT)?;
+        let len = output.len();
+        Control::Else(skip).resolve(output.as_mut_slice(), len)
+    }
+
+    /// Reserves `bytes` in the data segment from the next cell on, returning their address.
+    fn reserve(&mut self, bytes: usize) -> u32 {
+        self.data_len = self.data_len.next_multiple_of(4);
+        let address = self.data_address.wrapping_add(self.data_len as u32);
+        self.data_len += bytes;
+        address
+    }
+
+    /// Reserves `bytes` in the data segment right after the last ones reserved.
+    fn allot(&mut self, bytes: u32) -> Result<(), CompilerError> {
+        // Data can't be given back
+        if (bytes as i32) < 0 {
+            return Err(CompilerError::MalformedCompilation);
+        }
+        self.data_len += bytes as usize;
+        Ok(())
+    }
+
+    /// Compiles the push of `address`, in the data segment.
+    fn push_data_address(
+        &mut self,
+        address: u32,
+        output: &mut Output,
+    ) -> Result<(), CompilerError> {
+        let relocation = Relocation::data(output.len(), address.wrapping_sub(self.data_address));
+        self.relocations
+            .try_push(relocation)
+            .map_err(|_| CompilerError::TooManyRelocations)?;
+        output.emit_fixed(&RelocationKind::Data.encode(address as i32))?;
+        output.emit(self.top_of_stack.push_a0())
+    }
+
+    /// Compiles the store of the top of the stack to `address`, in the data segment.
+    fn store(&mut self, address: u32, output: &mut Output) -> Result<(), CompilerError> {
+        self.push_data_address(address, output)?;
+        let (len, instructions) = self.top_of_stack.instructions(&Primitive::Load);
+        output.emit(&instructions[..len])
+    }
+
+    /// Compiles code setting the cell at `address` to `value` once it runs, as code outside of
+    /// definitions does. The relocation of the store records the value too.
+    fn initialize(
+        &mut self,
+        address: u32,
+        value: u32,
+        output: &mut Output,
+    ) -> Result<(), CompilerError> {
+        let (len, instructions) = self.top_of_stack.instructions(&Primitive::Push(value));
+        output.emit(&instructions[..len])?;
+        self.store(address, output)?;
+        if let Some(relocation) = self.relocations.as_mut_slice().last_mut() {
+            relocation.initial = Some(value);
+        }
+        Ok(())
+    }
+}
+
+/// What a word defined outside of `:` and `;` does.
+enum Body<'a> {
+    /// Pushes a value known while compiling, as `CONSTANT`.
+    Constant(u32),
+    /// Pushes the address of cells in the data segment, the first of them instead when `fetch`,
+    /// and calls `does` then.
+    Cells {
+        address: u32,
+        fetch: bool,
+        does: Option<&'a str>,
+    },
+}
+
+/// Tokens outside of definitions taking the numbers read before them while compiling, and
+/// comments.
+const TAKING_LITERALS: [&str; 6] = ["CONSTANT", "VALUE", "ALLOT", ",", "(", "\\"];
+
+/// Compiles pushes of `values`, the deepest first.
+fn compile_literals(
+    values: &[u32],
+    top: TopOfStack,
+    output: &mut Output,
+    effects: &mut Effects,
+) -> Result<(), CompilerError> {
+    for value in values {
+        effects.apply(StackEffect::new(0, 1))?;
+        let (len, instructions) = top.instructions(&Primitive::Push(*value));
+        output.emit(&instructions[..len])?;
+        output.release_registers()?;
+    }
+    Ok(())
+}
+
+/// Where the code compiled for a definition read from `tokens` starts when it defines words
+/// with `CREATE`: past its `DOES>`, along with `true`, or at its `;` when it has none.
+fn defining_part(tokens: &mut Tokens) -> Option<(usize, bool)> {
+    let mut creates = false;
+    while let Some(token) = tokens.next() {
+        match token {
+            ";" => return creates.then_some((tokens.span().start, false)),
+            "DOES>" => return creates.then_some((tokens.offset(), true)),
+            "CREATE" => creates = true,
+            "(" => {
+                tokens.parse(')');
+            }
+            "\\" => {
+                tokens.parse('\n');
+            }
+            "S\"" | "C\"" | ".\"" => {
+                tokens.parse('"');
+            }
+            "'" | "[']" | "POSTPONE" | "TO" => {
+                tokens.next();
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// What the compiler does with the tokens it reads, as the `STATE` of a Forth system.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Compiling code outside of any definition, which runs along with the output.
+    TopLevel,
+    /// Compiling the body of a definition.
+    Compiling,
+    /// Running tokens as they are read, between `[` and `]` within a definition.
+    Interpreting,
+}
+
+/// Whether `name` is compiled by the compiler itself rather than looked up in the dictionary.
+fn is_builtin(name: &str) -> bool {
+    const KEYWORDS: [&str; 38] = [
+        ":",
+        ":NONAME",
+        ";",
+        "[",
+        "]",
+        "LITERAL",
+        "IMMEDIATE",
+        "POSTPONE",
+        ",",
+        "COMPILE,",
+        "VARIABLE",
+        "CONSTANT",
+        "VALUE",
+        "TO",
+        "CREATE",
+        "ALLOT",
+        "DOES>",
+        "(",
+        "\\",
+        "S\"",
+        "C\"",
+        ".\"",
+        "INLINE",
+        "IF",
+        "ELSE",
+        "THEN",
+        "BEGIN",
+        "UNTIL",
+        "AGAIN",
+        "WHILE",
+        "REPEAT",
+        "DO",
+        "?DO",
+        "LOOP",
+        "+LOOP",
+        "LEAVE",
+        "'",
+        "[']",
+    ];
+    KEYWORDS.contains(&name) || name.parse::<Primitive>().is_ok() || name.parse::<Routine>().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names whose DJB2 hashes are equal once truncated to 32 bits.
+    const COLLISIONS: [(&str, &str); 7] = [
+        ("heliotropes", "neurospora"),
+        ("depravement", "serafins"),
+        ("stylist", "subgenera"),
+        ("joyful", "synaphea"),
+        ("redescribed", "urites"),
+        ("dram", "vivency"),
+        ("aB", "b!"),
+    ];
+
+    #[test]
+    fn corpus_collides() {
+        for (first, second) in COLLISIONS {
+            assert_eq!(get_hash(first), get_hash(second), "{first} {second}");
+        }
+    }
+
+    #[test]
+    fn colliding_names_resolve_to_their_own_word() {
+        let mut keys = [(); 16].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .build();
+        let mut output = [0; 4096];
+        let source = ": heliotropes 1 ; : neurospora 2 ; : stylist 3 ; : subgenera stylist ;
+            : dram 5 ; : aB 6 ; : b! aB ;";
+        compiler.compile(source, &mut output).unwrap();
+
+        let dictionary = compiler.dictionary();
+        for (first, second) in COLLISIONS {
+            for name in [first, second] {
+                if let Some((word, _)) = dictionary.lookup(name) {
+                    assert_eq!(word.name, name);
+                }
+            }
+        }
+        assert_eq!(
+            dictionary.lookup("neurospora").unwrap().0.original,
+            ": neurospora 2 ;"
+        );
+        assert!(!dictionary.lookup("subgenera").unwrap().0.leaf);
+        assert!(dictionary.lookup("vivency").is_none());
+        assert!(dictionary.lookup("serafins").is_none());
+    }
+
+    #[test]
+    fn colliding_name_does_not_shadow_a_redefinition() {
+        let mut keys = [(); 16].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .build();
+        let mut output = [0; 1024];
+        compiler.compile(": joyful 1 ;", &mut output).unwrap();
+        compiler.compile(": joyful 2 ;", &mut output).unwrap();
+        compiler.compile(": synaphea 3 ;", &mut output).unwrap();
+
+        let dictionary = compiler.dictionary();
+        assert_eq!(
+            dictionary.lookup("joyful").unwrap().0.original,
+            ": joyful 2 ;"
+        );
+        assert_eq!(
+            dictionary.lookup("synaphea").unwrap().0.original,
+            ": synaphea 3 ;"
+        );
+    }
+
+    /// Writes `instructions` to `code` as compiled for the default target, returning their size.
+    fn encode(instructions: &[u32], code: &mut [u8]) -> usize {
+        for (bytes, instruction) in code.chunks_mut(4).zip(instructions) {
+            bytes.copy_from_slice(&instruction.to_le_bytes());
+        }
+        instructions.len() * 4
+    }
+
+    #[test]
+    fn words_of_a_dictionary_handed_over_are_compiled_and_handed_back() {
+        let (len, instructions) = Primitive::Push(1).get_instructions();
+        let mut push = [0; 32];
+        let len = encode(&instructions[..len], &mut push);
+        let mut keys = [(); 4].map(|_| CompiledWord::new("", "", 0));
+        keys[0] = CompiledWord::new("one", ": one 1 ;", len);
+        keys[0].inline = true;
+        let mut memory = [0; 64];
+        memory[..len].copy_from_slice(&push[..len]);
+        let dictionary = ForthDictionary::new(1, &mut keys, len, &mut memory);
+        let mut compiler = ForthCompiler::builder().dictionary(dictionary).build();
+        let mut output = [0; 64];
+        let written = compiler.compile("one one", &mut output).unwrap();
+        assert_eq!(written, 2 * len);
+        assert!(output[..written]
+            .chunks(len)
+            .all(|copy| copy == &push[..len]));
+        assert_eq!(
+            compiler
+                .compile("two", &mut output)
+                .map_err(|diagnostic| diagnostic.error),
+            Err(CompilerError::UnrecognizedToken)
+        );
+
+        let dictionary = compiler.into_dictionary();
+        assert_eq!(dictionary.len(), 1);
+        let (word, instructions) = dictionary.lookup("one").unwrap();
+        assert_eq!((word.original, instructions), (": one 1 ;", &push[..len]));
+    }
+
+    #[test]
+    fn code_outside_of_definitions_needs_no_storage() {
+        let mut compiler = ForthCompiler::builder().build();
+        let mut output = [0; 64];
+        let written = compiler.compile("1 2 +", &mut output).unwrap();
+        let lens = [Primitive::Push(1), Primitive::Push(2), Primitive::Add]
+            .map(|primitive| primitive.get_instructions().0 * 4);
+        assert_eq!(written, lens.iter().sum::<usize>());
+        assert!(compiler.dictionary().is_empty());
+    }
+
+    #[test]
+    fn words_are_called_unless_marked_or_small_enough_to_inline() {
+        let mut keys = [(); 8].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 256];
+        let mut relocations = [Relocation::default(); 4];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .relocations(&mut relocations)
+            .build();
+        let mut output = [0; 1024];
+        let source = ": none ; : big 1 2 + ; : small 3 + ; INLINE : user none big small ;";
+        let len = compiler.compile(source, &mut output).unwrap();
+
+        let dictionary = compiler.dictionary();
+        let word = |name| dictionary.lookup(name).unwrap();
+        let (big, _) = word("big");
+        let (small, small_code) = word("small");
+        let (user, user_code) = word("user");
+        assert!(word("none").0.inline && small.inline && !big.inline);
+        assert!(big.leaf && !user.leaf);
+        let body = user.addr + primitives::ENTER.len() * 4;
+        let call = Branch::Call.encode(body, big.addr).unwrap();
+        assert_eq!(user_code[..4], call.to_le_bytes());
+        assert_eq!(&user_code[4..], small_code);
+        assert_eq!(output[..len][body..body + user.len], *user_code);
+
+        assert_eq!(
+            compiler
+                .compile(": more 4 ; : other more ; INLINE", &mut output)
+                .map_err(|diagnostic| diagnostic.error),
+            Err(CompilerError::MalformedCompilation)
+        );
+        // Execution tokens are pc relative
+        assert_eq!(
+            compiler
+                .compile(": xt ['] more ; INLINE", &mut output)
+                .map_err(|diagnostic| diagnostic.error),
+            Err(CompilerError::MalformedCompilation)
+        );
+    }
+
+    #[test]
+    fn insert_keeps_the_words_sorted_by_hash_then_definition() {
+        let names = [
+            "dup", "swap", "over", "joyful", "rot", "synaphea", "aB", "b!", "dup", "joyful", "nip",
+            "joyful",
+        ];
+        let mut keys = [(); 16].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 16];
+        let mut dictionary = ForthDictionary::new(0, &mut keys, 0, &mut memory);
+        for (idx, name) in names.into_iter().enumerate() {
+            let mut word = CompiledWord::new(name, "", 1);
+            word.addr = idx;
+            dictionary.insert(word, &[idx as u8]).unwrap();
+            let last = dictionary.last_mut().unwrap();
+            assert_eq!((last.name, last.addr), (name, idx));
+        }
+
+        for pair in dictionary.words().windows(2) {
+            let order = (pair[0].hash, pair[0].addr) < (pair[1].hash, pair[1].addr);
+            assert!(order, "{} {}", pair[0].name, pair[1].name);
+        }
+        for name in names {
+            let defined_last = names.iter().rposition(|other| *other == name).unwrap();
+            let (word, body) = dictionary.lookup(name).unwrap();
+            assert_eq!(word.addr, defined_last, "{name}");
+            assert_eq!(body, [defined_last as u8], "{name}");
+        }
+        assert!(dictionary.lookup("drop").is_none());
+    }
+
+    #[test]
+    fn redefinitions_shadow_earlier_ones_in_definition_order() {
+        let mut keys = [(); 16].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .build();
+        let mut output = [0; 1024];
+        compiler
+            .compile(": w 1 1 + ; : joyful w ;", &mut output)
+            .unwrap();
+        compiler
+            .compile(": w 2 2 + ; : synaphea w ;", &mut output)
+            .unwrap();
+        compiler
+            .compile(": w 3 3 + ; : v 4 4 + ; INLINE", &mut output)
+            .unwrap();
+
+        let dictionary = compiler.dictionary();
+        let definitions = dictionary
+            .words()
+            .iter()
+            .filter(|word| word.name == "w")
+            .map(|word| word.original);
+        assert!(definitions.eq([": w 1 1 + ;", ": w 2 2 + ;", ": w 3 3 + ;"]));
+        let (word, _) = dictionary.lookup("w").unwrap();
+        assert_eq!(word.original, ": w 3 3 + ;");
+        // `INLINE` marks the word defined last, wherever its hash sorts it
+        assert!(dictionary.lookup("v").unwrap().0.inline);
+        assert!(!word.inline);
+    }
+
+    #[test]
+    fn compiling_continues_on_a_dictionary_handed_back() {
+        let mut keys = [(); 4].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 256];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .build();
+        let mut output = [0; 256];
+        compiler.compile(": one 1 2 + ;", &mut output).unwrap();
+        let dictionary = compiler.into_dictionary();
+
+        let mut relocations = [Relocation::default(); 4];
+        let mut compiler = ForthCompiler::builder()
+            .dictionary(dictionary)
+            .relocations(&mut relocations)
+            .build();
+        compiler.compile(": three one ;", &mut output).unwrap();
+        let symbols = compiler
+            .relocations()
+            .iter()
+            .map(|relocation| relocation.symbol);
+        assert!(symbols.eq(["one"]));
+        let (three, code) = compiler.dictionary().lookup("three").unwrap();
+        assert_eq!(three.compilation, 1);
+        let mut call = [0; 8];
+        encode(&RelocationKind::Call.encode(0), &mut call);
+        assert_eq!(code, call);
+    }
+
+    #[test]
+    fn targets_with_extensions_compile_shorter_code() {
+        let mut output = [0; 1024];
+        let len = |target| {
+            let mut compiler = ForthCompiler::builder().target(target).build();
+            compiler.compile("2 3 * 4 /", &mut output).unwrap()
+        };
+        let [rv32i, rv32im, rv32imc] = [Target::RV32I, Target::RV32IM, Target::RV32IMC].map(len);
+        // The routines are left out once `mul` and `div` do their job
+        assert!(rv32im < rv32i / 2, "{rv32im} {rv32i}");
+        assert!(rv32imc < rv32im, "{rv32imc} {rv32im}");
+    }
+
+    #[test]
+    fn definitions_start_and_end_only_where_they_can() {
+        let mut keys = [(); 4].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .build();
+        let mut output = [0; 1024];
+        let cases = [
+            (
+                "IMMEDIATE",
+                CompilerError::MalformedCompilation,
+                "IMMEDIATE",
+            ),
+            (": a : b ; ;", CompilerError::NestedDefinition, ":"),
+            (
+                ": a :NONAME ; ;",
+                CompilerError::NestedDefinition,
+                ":NONAME",
+            ),
+            (":NONAME : b ;", CompilerError::NestedDefinition, ":"),
+            (": a 1", CompilerError::UnterminatedDefinition, ""),
+            (":NONAME 1 [ 2", CompilerError::UnterminatedDefinition, ""),
+            (": a", CompilerError::UnterminatedDefinition, ""),
+            ("1 2 ;", CompilerError::CompileOnly, ";"),
+            ("[ 1", CompilerError::CompileOnly, "["),
+            ("1 ]", CompilerError::CompileOnly, "]"),
+            ("1 LITERAL", CompilerError::CompileOnly, "LITERAL"),
+            (": a [ ;", CompilerError::UnbalancedControlFlow, ";"),
+            (": a ] ;", CompilerError::UnbalancedControlFlow, "]"),
+            // The definition before the stray `;` is kept
+            (": a 1 ; ;", CompilerError::CompileOnly, ";"),
+        ];
+        for (source, error, token) in cases {
+            let Err(diagnostic) = compiler.compile(source, &mut output) else {
+                panic!("{source} compiles");
+            };
+            assert_eq!(
+                (diagnostic.error, diagnostic.token),
+                (error, token),
+                "{source}"
+            );
+        }
+        let names = compiler.dictionary().words().iter().map(|word| word.name);
+        assert!(names.eq(["a"]));
+
+        compiler
+            .compile(": a [ 3 ] LITERAL ; IMMEDIATE", &mut output)
+            .unwrap();
+        let (a, _) = compiler.dictionary().lookup("a").unwrap();
+        assert!(a.immediate);
+    }
+
+    #[test]
+    fn strings_and_comments_must_be_terminated() {
+        let mut keys = [(); 4].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .build();
+        let mut output = [0; 1024];
+        for source in [
+            ": w S\" abc ;",
+            "1 C\" abc",
+            ".\"",
+            ": w ( n -- n ) ( 1 + ;",
+            "1 (",
+        ] {
+            let diagnostic = compiler.compile(source, &mut output).unwrap_err();
+            assert_eq!(
+                diagnostic.error,
+                CompilerError::MalformedCompilation,
+                "{source}"
+            );
+            let opening = source.rfind(['"', '(']).unwrap();
+            assert_eq!(diagnostic.span.end, opening + 1, "{source}");
+        }
+        // Backslash comments run to the end of the line, or of the source
+        let len = compiler.compile("1 \\ 2 \" (", &mut output);
+        assert!(len.is_ok());
+        let len = compiler.compile("1 \\", &mut output);
+        assert!(len.is_ok());
+    }
+
+    #[test]
+    fn words_keep_the_addresses_of_their_strings() {
+        let mut keys = [(); 4].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .build();
+        let mut output = [0; 1024];
+        let len = compiler
+            .compile(": hi S\" hi\" ; : there C\" there\" ;", &mut output)
+            .unwrap();
+
+        for name in ["hi", "there"] {
+            let (word, instructions) = compiler.dictionary().lookup(name).unwrap();
+            let body = word.addr + primitives::ENTER.len() * 4;
+            assert_eq!(instructions, &output[..len][body..body + word.len]);
+            let [hi, lo] = RelocationKind::Address.encode(0);
+            let mut unpatched = [0; 8];
+            unpatched[..4].copy_from_slice(&hi.to_le_bytes());
+            unpatched[4..].copy_from_slice(&lo.to_le_bytes());
+            assert_ne!(instructions[..8], unpatched);
+        }
+    }
+
+    #[test]
+    fn full_dictionary_fails_without_panicking() {
+        let mut keys = [(); 2].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 64];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .build();
+        let mut output = [0; 1024];
+        let error = compiler.compile(": a 1 ; : b 2 ; : c 3 ;", &mut output);
+        assert_eq!(error.unwrap_err().error, CompilerError::DictionaryFull);
+        let usage = compiler.dictionary().usage();
+        assert_eq!((usage.words, usage.free_words()), (2, Some(0)));
+
+        let mut keys = [(); 4].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 16];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .build();
+        let error = compiler.compile(": a 1 2 3 4 ;", &mut output);
+        assert_eq!(
+            error.unwrap_err().error,
+            CompilerError::DictionaryMemoryFull
+        );
+        assert_eq!(compiler.dictionary().usage().free_memory(), Some(16));
+
+        let error = compiler.compile("1 2 3 4", &mut output[..8]);
+        assert_eq!(error.unwrap_err().error, CompilerError::OutputFull);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn owned_storage_grows_as_words_are_defined() {
+        use alloc::{format, string::String};
+
+        let source: String = (0..100).map(|n| format!(": w{n} {n} ; ")).collect();
+        let mut compiler = ForthCompiler::builder().owned_storage().build();
+        let mut output = [0; 8192];
+        compiler.compile(&source, &mut output).unwrap();
+
+        let usage = compiler.dictionary().usage();
+        assert_eq!((usage.words, usage.free_words()), (100, None));
+        assert_eq!(usage.memory_capacity, None);
+        let (word, _) = compiler.dictionary().lookup("w42").unwrap();
+        assert_eq!(word.original, ": w42 42 ;");
+        let names = compiler.dictionary().words().windows(2);
+        assert!(names.into_iter().all(|pair| pair[0].hash <= pair[1].hash));
+    }
+
+    #[test]
+    fn immediate_words_compile_what_they_postpone() {
+        let mut keys = [(); 16].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut actions = [Action::Cell(0); 16];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .actions(&mut actions)
+            .build();
+        let mut output = [0; 4096];
+        let source = ": add POSTPONE + ; IMMEDIATE
+            : lits 3 0 DO I POSTPONE LITERAL LOOP ; IMMEDIATE
+            : inc 1 + ; INLINE : twice ['] inc COMPILE, ['] inc COMPILE, ; IMMEDIATE
+            : nop $00000013 , ; IMMEDIATE
+            : macros lits add add twice ;
+            : plain 0 1 2 + + inc inc ;
+            : raw nop ;";
+        compiler.compile(source, &mut output).unwrap();
+
+        let dictionary = compiler.dictionary();
+        let (_, macros) = dictionary.lookup("macros").unwrap();
+        let (_, plain) = dictionary.lookup("plain").unwrap();
+        assert_eq!(macros, plain);
+        let (_, raw) = dictionary.lookup("raw").unwrap();
+        assert_eq!(raw, [0x13, 0, 0, 0]);
+    }
+
+    #[test]
+    fn words_run_while_compiling_stay_on_the_host() {
+        let mut keys = [(); 16].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut relocations = [(); 8].map(|_| Relocation::default());
+        let mut actions = [Action::Cell(0); 4];
+        let mut compiler = ForthCompiler::builder()
+            .target(Target::RV32IM)
+            .storage(&mut keys, &mut memory)
+            .relocations(&mut relocations)
+            .actions(&mut actions)
+            .build();
+        let mut output = [0; 4096];
+        let cases = [
+            (
+                ": b BEGIN AGAIN ; IMMEDIATE : c b ;",
+                CompilerError::TooManySteps,
+                "b",
+            ),
+            (
+                ": c [ 0 @ ] ;",
+                CompilerError::NotRunnableWhileCompiling,
+                "@",
+            ),
+            (
+                ": c [ 1 0 ! ] ;",
+                CompilerError::NotRunnableWhileCompiling,
+                "!",
+            ),
+            (
+                ": c [ 0 BRANCH ] ;",
+                CompilerError::NotRunnableWhileCompiling,
+                "BRANCH",
+            ),
+            (
+                ": c [ 0 0 TYPE ] ;",
+                CompilerError::NotRunnableWhileCompiling,
+                "TYPE",
+            ),
+            (
+                ": c [ 99 COMPILE, ] ;",
+                CompilerError::InvalidExecutionToken,
+                "COMPILE,",
+            ),
+            // Both read their cells, which only the target has
+            (
+                "7 VALUE v : c [ v ] ;",
+                CompilerError::NotRunnableWhileCompiling,
+                "v",
+            ),
+            (
+                ": k CREATE , DOES> @ ; 5 k five : c [ five ] ;",
+                CompilerError::NotRunnableWhileCompiling,
+                "five",
+            ),
+        ];
+        for (source, error, token) in cases {
+            let Err(diagnostic) = compiler.compile(source, &mut output) else {
+                panic!("{source} compiles");
+            };
+            assert_eq!(
+                (diagnostic.error, diagnostic.token),
+                (error, token),
+                "{source}"
+            );
+        }
+
+        // Multiplying and dividing run on the host whatever the target does them with
+        compiler
+            .compile(": c [ 6 7 * 3 / ] LITERAL ; : d 14 ;", &mut output)
+            .unwrap();
+        let dictionary = compiler.dictionary();
+        assert_eq!(
+            dictionary.lookup("c").unwrap().1,
+            dictionary.lookup("d").unwrap().1
+        );
+    }
+
+    #[test]
+    fn words_run_while_compiling_queue_at_most_what_the_actions_hold() {
+        let mut keys = [(); 8].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 2048];
+        let mut actions = [Action::Cell(0); 100];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .actions(&mut actions)
+            .build();
+        let mut output = [0; 8192];
+        compiler
+            .compile(
+                ": cells 0 DO I , LOOP ; IMMEDIATE : t [ 100 ] cells ;",
+                &mut output,
+            )
+            .unwrap();
+        assert_eq!(compiler.dictionary().lookup("t").unwrap().1.len(), 400);
+
+        let result = compiler.compile(": u [ 101 ] cells ;", &mut output);
+        assert_eq!(
+            result.map_err(|diagnostic| (diagnostic.error, diagnostic.token)),
+            Err((CompilerError::TooManyActions, "cells"))
+        );
+    }
+
+    #[test]
+    fn defining_words_lay_out_the_data_segment() {
+        let mut keys = [(); 16].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut relocations = [(); 8].map(|_| Relocation::data(0, 0));
+        let mut actions = [Action::Cell(0); 4];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .relocations(&mut relocations)
+            .actions(&mut actions)
+            .data_address(0x8000)
+            .build();
+        let mut output = [0; 4096];
+        let source = "VARIABLE a 3 CONSTANT three CREATE buf 6 ALLOT 7 VALUE v
+            : pair CREATE , , DOES> @ ; 1 2 pair p";
+        compiler.compile(source, &mut output).unwrap();
+
+        let segment = compiler.data_segment();
+        assert_eq!(
+            segment,
+            DataSegment {
+                address: 0x8000,
+                len: 24
+            }
+        );
+        let dictionary = compiler.dictionary();
+        let data = |name| dictionary.lookup(name).unwrap().0.data;
+        assert_eq!(data("a"), Some(0x8000));
+        assert_eq!(data("three"), None);
+        assert_eq!(data("buf"), Some(0x8004));
+        assert_eq!(data("v"), Some(0x800c));
+        assert_eq!(data("p"), Some(0x8010));
+        assert_eq!(dictionary.lookup("three").unwrap().0.value, Some(3));
+        assert!(dictionary.lookup("pair").unwrap().0.defining);
+
+        compiler.compile("VARIABLE b", &mut output).unwrap();
+        let segment = compiler.data_segment();
+        assert_eq!(
+            segment,
+            DataSegment {
+                address: 0x8018,
+                len: 4
+            }
+        );
+    }
+
+    #[test]
+    fn to_sets_values_only_and_allot_reserves_forward_only() {
+        let mut keys = [(); 16].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut relocations = [(); 8].map(|_| Relocation::data(0, 0));
+        let mut actions = [Action::Cell(0); 4];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .relocations(&mut relocations)
+            .actions(&mut actions)
+            .build();
+        let mut output = [0; 4096];
+        let cases = [
+            (
+                "VARIABLE a 1 TO a",
+                CompilerError::MalformedCompilation,
+                "a",
+            ),
+            (
+                "3 CONSTANT b : w 1 TO b ;",
+                CompilerError::MalformedCompilation,
+                "b",
+            ),
+            ("CREATE c 1 TO c", CompilerError::MalformedCompilation, "c"),
+            (
+                ": pair CREATE , , DOES> @ ; 1 2 pair d 1 TO d",
+                CompilerError::MalformedCompilation,
+                "d",
+            ),
+            (": e ; 1 TO e", CompilerError::MalformedCompilation, "e"),
+            ("1 TO f", CompilerError::UnrecognizedToken, "f"),
+            ("-4 ALLOT", CompilerError::MalformedCompilation, "ALLOT"),
+        ];
+        for (source, error, token) in cases {
+            let Err(diagnostic) = compiler.compile(source, &mut output) else {
+                panic!("{source} compiles");
+            };
+            assert_eq!(
+                (diagnostic.error, diagnostic.token),
+                (error, token),
+                "{source}"
+            );
         }
-        Ok(alloc_ptr)
+        compiler
+            .compile("7 VALUE v : w 1 TO v ; 2 TO v 4 ALLOT", &mut output)
+            .unwrap();
+    }
+
+    #[test]
+    fn see_interleaves_source_and_instructions() {
+        extern crate std;
+        use std::string::ToString;
+
+        let mut keys = [(); 4].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 1024];
+        let mut marks = [SourceMark::default(); 16];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .source_marks(&mut marks)
+            .build();
+        let mut output = [0; 1024];
+        compiler
+            .compile(": neg? ( n -- f ) 0\n  < ;", &mut output)
+            .unwrap();
+
+        let listing = compiler.see("neg?").unwrap().to_string();
+        let lines: [&str; 12] = [
+            ": neg? ( n -- f ) 0",
+            "     0:  13050000  li a0, 0",
+            "     4:  2320a100  sw a0, 0(sp)",
+            "     8:  1301c1ff  addi sp, sp, -4",
+            "<",
+            "     c:  03254100  lw a0, 4(sp)",
+            "    10:  83258100  lw a1, 8(sp)",
+            "    14:  13014100  addi sp, sp, 4",
+            "    18:  33a5a500  slt a0, a1, a0",
+            "    1c:  1315f501  slli a0, a0, 31",
+            "    20:  1355f541  srai a0, a0, 31",
+            "    24:  2322a100  sw a0, 4(sp)",
+        ];
+        assert!(
+            listing.lines().eq(lines.into_iter().chain([";"])),
+            "{listing}"
+        );
+        assert!(compiler.see("pos?").is_none());
     }
 }
@@ -0,0 +1,124 @@
+/// Parses a numeric literal into the cell pushed for it.
+///
+/// Numbers are decimal unless prefixed by `$` or `0x` for hexadecimal, `%` for binary or `#` for
+/// decimal, and negative when a `-` precedes the digits, either before or after the prefix.
+/// Hexadecimal and binary digits may be grouped with `_`, as in `0x1001_3000`.
+/// `'c'` pushes the code of the character. Any value from `-2^31` to `2^32 - 1` is accepted,
+/// negative ones in two's complement.
+pub fn parse_number(token: &str) -> Option<u32> {
+    if let Some(c) = parse_char(token) {
+        return Some(c as u32);
+    }
+    let (negative, token) = match token.strip_prefix('-') {
+        Some(token) => (true, token),
+        None => (false, token),
+    };
+    let (radix, digits) = if let Some(digits) = token.strip_prefix('$') {
+        (16, digits)
+    } else if let Some(digits) = token.strip_prefix("0x") {
+        (16, digits)
+    } else if let Some(digits) = token.strip_prefix('%') {
+        (2, digits)
+    } else if let Some(digits) = token.strip_prefix('#') {
+        (10, digits)
+    } else {
+        (10, token)
+    };
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(digits) if !negative => (true, digits),
+        Some(_) => return None,
+        None => (negative, digits),
+    };
+    let grouped = radix != 10 && !digits.starts_with('_') && !digits.ends_with('_');
+    let digit = |c: char| c.is_digit(radix) || (grouped && c == '_');
+    if digits.is_empty() || !digits.chars().all(digit) {
+        return None;
+    }
+    let mut value: u32 = 0;
+    for digit in digits.chars().filter_map(|c| c.to_digit(radix)) {
+        value = value.checked_mul(radix)?.checked_add(digit)?;
+    }
+    if !negative {
+        Some(value)
+    } else if value <= 1 << 31 {
+        Some(value.wrapping_neg())
+    } else {
+        None
+    }
+}
+
+fn parse_char(token: &str) -> Option<char> {
+    let mut chars = token.strip_prefix('\'')?.strip_suffix('\'')?.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimal() {
+        assert_eq!(parse_number("0"), Some(0));
+        assert_eq!(parse_number("42"), Some(42));
+        assert_eq!(parse_number("#42"), Some(42));
+        assert_eq!(parse_number("4294967295"), Some(u32::MAX));
+        assert_eq!(parse_number("4294967296"), None);
+    }
+
+    #[test]
+    fn negative() {
+        assert_eq!(parse_number("-1"), Some(u32::MAX));
+        assert_eq!(parse_number("#-42"), Some(-42i32 as u32));
+        assert_eq!(parse_number("-2147483648"), Some(0x8000_0000));
+        assert_eq!(parse_number("-2147483649"), None);
+        assert_eq!(parse_number("-$10"), Some(-16i32 as u32));
+        assert_eq!(parse_number("$-10"), Some(-16i32 as u32));
+        assert_eq!(parse_number("--1"), None);
+        assert_eq!(parse_number("-$-1"), None);
+    }
+
+    #[test]
+    fn hexadecimal() {
+        assert_eq!(parse_number("$ff"), Some(0xff));
+        assert_eq!(parse_number("$FF"), Some(0xff));
+        assert_eq!(parse_number("0x1001_3000"), Some(0x1001_3000));
+        assert_eq!(parse_number("$ffff_f800"), Some(-2048i32 as u32));
+        assert_eq!(parse_number("0x_1"), None);
+        assert_eq!(parse_number("0x1_"), None);
+        assert_eq!(parse_number("0x1_0000_0000"), None);
+        assert_eq!(parse_number("0x10013000"), Some(0x1001_3000));
+        assert_eq!(parse_number("0xFFFFFFFF"), Some(u32::MAX));
+        assert_eq!(parse_number("0x"), None);
+    }
+
+    #[test]
+    fn binary() {
+        assert_eq!(parse_number("%1010"), Some(10));
+        assert_eq!(parse_number("%-1"), Some(u32::MAX));
+        assert_eq!(parse_number("%102"), None);
+        assert_eq!(parse_number("%1010_0101"), Some(0xa5));
+        assert_eq!(parse_number("1_000"), None);
+    }
+
+    #[test]
+    fn character() {
+        assert_eq!(parse_number("'a'"), Some(97));
+        assert_eq!(parse_number("'0'"), Some(48));
+        assert_eq!(parse_number("'''"), Some(39));
+        assert_eq!(parse_number("'ab'"), None);
+        assert_eq!(parse_number("'"), None);
+        assert_eq!(parse_number("''"), None);
+    }
+
+    #[test]
+    fn not_a_number() {
+        assert_eq!(parse_number(""), None);
+        assert_eq!(parse_number("-"), None);
+        assert_eq!(parse_number("+1"), None);
+        assert_eq!(parse_number("DUP"), None);
+        assert_eq!(parse_number("1+"), None);
+    }
+}
@@ -0,0 +1,831 @@
+use crate::assembler::{self, SP, ZERO};
+use crate::disassembler::{Instruction, Operation, Width};
+
+/// Decodes `instruction`, in natural byte order, if it is of the forms the optimizer rewrites:
+/// word loads and stores, arithmetic and `lui`.
+pub fn decode(instruction: u32) -> Option<Instruction> {
+    match Instruction::decode(instruction)? {
+        decoded @ (Instruction::Load {
+            width: Width::Word, ..
+        }
+        | Instruction::Store {
+            width: Width::Word, ..
+        }
+        | Instruction::Immediate { .. }
+        | Instruction::Register { .. }
+        | Instruction::Lui { .. }) => Some(decoded),
+        _ => None,
+    }
+}
+
+/// Encodes an instruction of the window, all of which [`decode`] accepts.
+pub fn encode(instruction: Instruction) -> u32 {
+    assembler::encode(instruction).expect("the window only holds instructions it decoded")
+}
+
+/// The single instruction loading `value` into `rd`, if there is one.
+fn li(rd: u32, value: u32) -> Option<Instruction> {
+    if fits(value as i32) {
+        Some(Instruction::Immediate {
+            operation: Operation::Add,
+            rd,
+            rs1: ZERO,
+            imm: value as i32,
+        })
+    } else if value & 0xfff == 0 {
+        Some(Instruction::Lui {
+            rd,
+            imm: value >> 12,
+        })
+    } else {
+        None
+    }
+}
+
+/// `addi sp, sp, amount`
+fn adjust_stack(amount: i32) -> Instruction {
+    Instruction::Immediate {
+        operation: Operation::Add,
+        rd: SP,
+        rs1: SP,
+        imm: amount,
+    }
+}
+
+/// `mv rd, rs`
+fn mv(rd: u32, rs: u32) -> Instruction {
+    Instruction::Immediate {
+        operation: Operation::Add,
+        rd,
+        rs1: rs,
+        imm: 0,
+    }
+}
+
+/// The register written, other than x0.
+fn writes(instruction: Instruction) -> Option<u32> {
+    let rd = match instruction {
+        Instruction::Load { rd, .. }
+        | Instruction::Immediate { rd, .. }
+        | Instruction::Register { rd, .. }
+        | Instruction::Lui { rd, .. } => rd,
+        _ => return None,
+    };
+    Some(rd).filter(|rd| *rd != ZERO)
+}
+
+fn reads(instruction: Instruction, register: u32) -> bool {
+    match instruction {
+        Instruction::Load { rs1, .. } | Instruction::Immediate { rs1, .. } => rs1 == register,
+        Instruction::Store { rs1, rs2, .. } | Instruction::Register { rs1, rs2, .. } => {
+            rs1 == register || rs2 == register
+        }
+        Instruction::Lui { .. } => false,
+        _ => true,
+    }
+}
+
+/// The amount added to sp, if the instruction is `addi sp, sp, amount`.
+fn stack_adjustment(instruction: Instruction) -> Option<i32> {
+    match instruction {
+        Instruction::Immediate {
+            operation: Operation::Add,
+            rd: SP,
+            rs1: SP,
+            imm,
+        } => Some(imm),
+        _ => None,
+    }
+}
+
+/// The instruction moved before `addi sp, sp, amount`, if it only uses sp as the base of a load
+/// or store that stays within the cells in use.
+fn before_stack_adjustment(instruction: Instruction, amount: i32) -> Option<Instruction> {
+    match instruction {
+        Instruction::Load {
+            width,
+            rd,
+            rs1: SP,
+            offset,
+        } if rd != SP && live(offset - amount) => Some(Instruction::Load {
+            width,
+            rd,
+            rs1: SP,
+            offset: offset - amount,
+        }),
+        Instruction::Store {
+            width,
+            rs1: SP,
+            rs2,
+            offset,
+        } if rs2 != SP && live(offset - amount) => Some(Instruction::Store {
+            width,
+            rs1: SP,
+            rs2,
+            offset: offset - amount,
+        }),
+        Instruction::Load { .. }
+        | Instruction::Store { .. }
+        | Instruction::Immediate { .. }
+        | Instruction::Register { .. }
+        | Instruction::Lui { .. }
+            if writes(instruction) != Some(SP) && !reads(instruction, SP) =>
+        {
+            Some(instruction)
+        }
+        _ => None,
+    }
+}
+
+/// The value the instruction computes from the values of its source registers.
+fn evaluate(instruction: Instruction, rs1: u32, rs2: u32) -> Option<u32> {
+    match instruction {
+        Instruction::Immediate { operation, imm, .. } => alu(operation, rs1, imm as u32),
+        Instruction::Register { operation, .. } => alu(operation, rs1, rs2),
+        Instruction::Lui { imm, .. } => Some(imm << 12),
+        _ => None,
+    }
+}
+
+/// The instruction with `register` read from `replacement` instead.
+fn replace_source(instruction: Instruction, register: u32, replacement: u32) -> Instruction {
+    let replace = |source: u32| {
+        if source == register {
+            replacement
+        } else {
+            source
+        }
+    };
+    match instruction {
+        Instruction::Load {
+            width,
+            rd,
+            rs1,
+            offset,
+        } => Instruction::Load {
+            width,
+            rd,
+            rs1: replace(rs1),
+            offset,
+        },
+        Instruction::Store {
+            width,
+            rs1,
+            rs2,
+            offset,
+        } => Instruction::Store {
+            width,
+            rs1: replace(rs1),
+            rs2: replace(rs2),
+            offset,
+        },
+        Instruction::Immediate {
+            operation,
+            rd,
+            rs1,
+            imm,
+        } => Instruction::Immediate {
+            operation,
+            rd,
+            rs1: replace(rs1),
+            imm,
+        },
+        Instruction::Register {
+            operation,
+            rd,
+            rs1,
+            rs2,
+        } => Instruction::Register {
+            operation,
+            rd,
+            rs1: replace(rs1),
+            rs2: replace(rs2),
+        },
+        other => other,
+    }
+}
+
+/// Source registers, x0 standing for none.
+fn sources(instruction: Instruction) -> [u32; 2] {
+    match instruction {
+        Instruction::Load { rs1, .. } | Instruction::Immediate { rs1, .. } => [rs1, ZERO],
+        Instruction::Store { rs1, rs2, .. } | Instruction::Register { rs1, rs2, .. } => [rs1, rs2],
+        _ => [ZERO, ZERO],
+    }
+}
+
+/// Evaluates `operation`, multiplication being the only one of the M extension worth folding.
+fn alu(operation: Operation, a: u32, b: u32) -> Option<u32> {
+    let value = match operation {
+        Operation::Add => a.wrapping_add(b),
+        Operation::Sub => a.wrapping_sub(b),
+        Operation::Sll => a << (b & 0x1f),
+        Operation::Slt => ((a as i32) < (b as i32)) as u32,
+        Operation::Sltu => (a < b) as u32,
+        Operation::Xor => a ^ b,
+        Operation::Srl => a >> (b & 0x1f),
+        Operation::Sra => ((a as i32) >> (b & 0x1f)) as u32,
+        Operation::Or => a | b,
+        Operation::And => a & b,
+        Operation::Mul => a.wrapping_mul(b),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Whether the instruction is a `mv` of a register to itself.
+fn is_nop(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Immediate { operation: Operation::Add, rd, rs1, imm: 0 } if rd == rs1
+    )
+}
+
+/// Whether `imm` fits the 12 bit immediates.
+fn fits(imm: i32) -> bool {
+    (-2048..2048).contains(&imm)
+}
+
+/// Whether `offset` from sp addresses a cell in use, the data stack growing downwards from an
+/// empty cell.
+fn live(offset: i32) -> bool {
+    offset > 0 && fits(offset)
+}
+
+/// Whether the register only holds values for the code of the token being compiled, rather than
+/// the stacks or the return point.
+fn scratch(register: u32) -> bool {
+    matches!(register, 5..=7 | 10..=17 | 28..=31)
+}
+
+const WINDOW: usize = 32;
+
+/// The instructions emitted last, since the last one the optimizer can't look past, rewritten
+/// in place to cut down the stack traffic between primitives.
+///
+/// The rewrites rely on the conventions of the compiled code: the cells at and below sp are
+/// free, so nothing reads them before storing to them, and the scratch registers hold nothing
+/// once the code of a token is over.
+pub struct Peephole {
+    window: [Instruction; WINDOW],
+    len: usize,
+}
+
+impl Peephole {
+    pub fn new() -> Self {
+        Peephole {
+            window: [mv(ZERO, ZERO); WINDOW],
+            len: 0,
+        }
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.window[..self.len]
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Appends `instruction`, handing back the oldest one when the window is full.
+    pub fn push(&mut self, instruction: Instruction) -> Option<Instruction> {
+        let evicted = if self.len == WINDOW {
+            let oldest = self.window[0];
+            self.remove(0);
+            Some(oldest)
+        } else {
+            None
+        };
+        self.window[self.len] = instruction;
+        self.len += 1;
+        evicted
+    }
+
+    /// Rewrites the window until no rule applies. With `released` the scratch registers hold
+    /// nothing past the last instruction.
+    pub fn optimize(&mut self, released: bool) {
+        while self.hoist_stack_adjustment()
+            || self.forward_store()
+            || self.fold_constant()
+            || self.propagate_copy()
+            || self.drop_dead_store()
+            || self.drop_dead_write(released)
+        {}
+    }
+
+    fn remove(&mut self, idx: usize) {
+        self.window.copy_within(idx + 1..self.len, idx);
+        self.len -= 1;
+    }
+
+    fn insert(&mut self, idx: usize, instruction: Instruction) {
+        self.window.copy_within(idx..self.len, idx + 1);
+        self.window[idx] = instruction;
+        self.len += 1;
+    }
+
+    /// Moves `addi sp, sp, n` towards the start of the window, merging them on the way, so
+    /// that the loads and stores in between address the cells relative to a single sp.
+    fn hoist_stack_adjustment(&mut self) -> bool {
+        for idx in 1..self.len {
+            let Some(amount) = stack_adjustment(self.window[idx]) else {
+                continue;
+            };
+            let previous = self.window[idx - 1];
+            if let Some(other) = stack_adjustment(previous) {
+                if !fits(amount + other) {
+                    continue;
+                }
+                self.remove(idx);
+                if amount + other == 0 {
+                    self.remove(idx - 1);
+                } else {
+                    self.window[idx - 1] = adjust_stack(amount + other);
+                }
+                return true;
+            }
+            if let Some(moved) = before_stack_adjustment(previous, amount) {
+                self.window[idx - 1] = self.window[idx];
+                self.window[idx] = moved;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Replaces a load of a cell by the register stored to it earlier in the window.
+    fn forward_store(&mut self) -> bool {
+        for idx in 0..self.len {
+            let Instruction::Load {
+                rd,
+                rs1: SP,
+                offset: load,
+                ..
+            } = self.window[idx]
+            else {
+                continue;
+            };
+            if rd == SP {
+                continue;
+            }
+            let mut offset = load;
+            for store in (0..idx).rev() {
+                match self.window[store] {
+                    Instruction::Store {
+                        rs1: SP,
+                        rs2,
+                        offset: stored,
+                        ..
+                    } if stored == offset => {
+                        self.remove(idx);
+                        if rs2 != rd {
+                            self.insert(store + 1, mv(rd, rs2));
+                        }
+                        return true;
+                    }
+                    Instruction::Store { rs1: SP, .. } => {}
+                    Instruction::Store { .. } => break,
+                    instruction => {
+                        if let Some(amount) = stack_adjustment(instruction) {
+                            offset += amount;
+                            continue;
+                        }
+                        if writes(instruction) == Some(SP) {
+                            break;
+                        }
+                    }
+                }
+                let instruction = self.window[store];
+                if writes(instruction) == Some(rd) || reads(instruction, rd) {
+                    break;
+                }
+            }
+        }
+        false
+    }
+
+    /// The value `register` holds before the instruction at `idx`, if the window loaded it
+    /// with a constant.
+    fn constant(&self, idx: usize, register: u32) -> Option<u32> {
+        if register == ZERO {
+            return Some(0);
+        }
+        let writer = (0..idx)
+            .rev()
+            .find(|writer| writes(self.window[*writer]) == Some(register))?;
+        match self.window[writer] {
+            Instruction::Immediate {
+                operation: Operation::Add,
+                rs1: ZERO,
+                imm,
+                ..
+            } => Some(imm as u32),
+            Instruction::Lui { imm, .. } => Some(imm << 12),
+            _ => None,
+        }
+    }
+
+    /// Computes instructions whose operands are constants, and turns those with a constant
+    /// operand into their immediate form.
+    fn fold_constant(&mut self) -> bool {
+        for idx in 0..self.len {
+            let instruction = self.window[idx];
+            let folded = match instruction {
+                Instruction::Immediate { rd, rs1, .. } if rs1 != ZERO => self
+                    .constant(idx, rs1)
+                    .and_then(|value| evaluate(instruction, value, 0))
+                    .and_then(|value| li(rd, value)),
+                Instruction::Register {
+                    operation,
+                    rd,
+                    rs1,
+                    rs2,
+                } => {
+                    let values = (self.constant(idx, rs1), self.constant(idx, rs2));
+                    match values {
+                        (Some(a), Some(b)) => {
+                            evaluate(instruction, a, b).and_then(|value| li(rd, value))
+                        }
+                        (_, Some(b)) => immediate(operation, rd, rs1, b),
+                        // Commutative operations take the constant on either side
+                        (Some(a), None)
+                            if matches!(
+                                operation,
+                                Operation::Add | Operation::Xor | Operation::Or | Operation::And
+                            ) =>
+                        {
+                            immediate(operation, rd, rs2, a)
+                        }
+                        _ => None,
+                    }
+                }
+                Instruction::Store {
+                    width,
+                    rs1,
+                    rs2,
+                    offset,
+                } if rs2 != ZERO => match self.constant(idx, rs2) {
+                    Some(0) => Some(Instruction::Store {
+                        width,
+                        rs1,
+                        rs2: ZERO,
+                        offset,
+                    }),
+                    _ => None,
+                },
+                _ => None,
+            };
+            if let Some(folded) = folded.filter(|folded| *folded != instruction) {
+                self.window[idx] = folded;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Reads the source of a `mv` instead of its destination, so the `mv` can turn dead.
+    fn propagate_copy(&mut self) -> bool {
+        for idx in 0..self.len {
+            let instruction = self.window[idx];
+            for register in sources(instruction) {
+                if register == ZERO || register == SP {
+                    continue;
+                }
+                let Some(writer) = (0..idx)
+                    .rev()
+                    .find(|writer| writes(self.window[*writer]) == Some(register))
+                else {
+                    continue;
+                };
+                let Instruction::Immediate {
+                    operation: Operation::Add,
+                    rs1: source,
+                    imm: 0,
+                    ..
+                } = self.window[writer]
+                else {
+                    continue;
+                };
+                if source == ZERO || source == SP || source == register {
+                    continue;
+                }
+                let overwritten = self.window[writer + 1..idx]
+                    .iter()
+                    .any(|between| writes(*between) == Some(source));
+                if !overwritten {
+                    self.window[idx] = replace_source(instruction, register, source);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Drops stores to cells that are stored to again, or freed, before anything reads them.
+    fn drop_dead_store(&mut self) -> bool {
+        for idx in 0..self.len {
+            if self.dead_store(idx) {
+                self.remove(idx);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether the instruction at `idx` stores to a cell nothing reads before it is stored to
+    /// again or freed.
+    fn dead_store(&self, idx: usize) -> bool {
+        let Instruction::Store {
+            rs1: SP,
+            offset: stored,
+            ..
+        } = self.window[idx]
+        else {
+            return false;
+        };
+        let mut offset = stored;
+        for instruction in &self.window[idx + 1..self.len] {
+            match *instruction {
+                Instruction::Store {
+                    rs1: SP,
+                    offset: stored,
+                    ..
+                } if stored == offset => return true,
+                Instruction::Load {
+                    rs1: SP,
+                    offset: loaded,
+                    ..
+                } if loaded != offset => {}
+                // Other loads may read the cell through another register
+                Instruction::Load { .. } => return false,
+                instruction => {
+                    if let Some(amount) = stack_adjustment(instruction) {
+                        offset -= amount;
+                    } else if writes(instruction) == Some(SP) {
+                        return false;
+                    }
+                }
+            }
+        }
+        // Cells at and below sp are free
+        offset <= 0
+    }
+
+    /// Drops computations whose result is overwritten, or released, before anything reads it.
+    fn drop_dead_write(&mut self, released: bool) -> bool {
+        for idx in 0..self.len {
+            let instruction = self.window[idx];
+            if stack_adjustment(instruction) == Some(0) || is_nop(instruction) {
+                self.remove(idx);
+                return true;
+            }
+            let pure = match instruction {
+                Instruction::Load { rs1, .. } => rs1 == SP,
+                Instruction::Immediate { .. }
+                | Instruction::Register { .. }
+                | Instruction::Lui { .. } => true,
+                _ => false,
+            };
+            let Some(rd) = writes(instruction).filter(|rd| pure && *rd != SP) else {
+                continue;
+            };
+            let mut dead = released && scratch(rd);
+            for later in &self.window[idx + 1..self.len] {
+                if reads(*later, rd) {
+                    dead = false;
+                    break;
+                }
+                if writes(*later) == Some(rd) {
+                    dead = true;
+                    break;
+                }
+            }
+            if dead {
+                self.remove(idx);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// The immediate form of `operation` with `value` as its second operand.
+fn immediate(operation: Operation, rd: u32, rs1: u32, value: u32) -> Option<Instruction> {
+    let (operation, imm) = match operation {
+        Operation::Sub => (Operation::Add, (value as i32).checked_neg()?),
+        Operation::Sll | Operation::Srl | Operation::Sra => (operation, (value & 0x1f) as i32),
+        Operation::Add
+        | Operation::Slt
+        | Operation::Sltu
+        | Operation::Xor
+        | Operation::Or
+        | Operation::And => (operation, value as i32),
+        _ => return None,
+    };
+    fits(imm).then_some(Instruction::Immediate {
+        operation,
+        rd,
+        rs1,
+        imm,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, Peephole};
+    use crate::assembler::{
+        add, addi, and, andi, li, lui, lw, mul, mv, sll, slli, sub, sw, A0, A1, A2, A3, S1, SP,
+        ZERO,
+    };
+
+    fn window(instructions: &[u32]) -> Peephole {
+        let mut peephole = Peephole::new();
+        for instruction in instructions {
+            let decoded = decode(*instruction).expect("an instruction the optimizer rewrites");
+            assert_eq!(peephole.push(decoded), None);
+        }
+        peephole
+    }
+
+    /// Applies `rule` to the window of `before` until it no longer does, expecting `after`.
+    fn rewrites(before: &[u32], rule: impl Fn(&mut Peephole) -> bool, after: &[u32]) {
+        let mut peephole = window(before);
+        while rule(&mut peephole) {}
+        let rewritten = peephole.instructions();
+        assert_eq!(rewritten.len(), after.len(), "{rewritten:?}");
+        for (instruction, expected) in rewritten.iter().zip(after) {
+            assert_eq!(encode(*instruction), *expected, "{rewritten:?}");
+        }
+    }
+
+    #[test]
+    fn stack_adjustments_move_up_and_merge() {
+        let rule = Peephole::hoist_stack_adjustment;
+        rewrites(
+            &[
+                sw(A0, 0, SP),
+                addi(SP, SP, -4),
+                sw(A1, 0, SP),
+                addi(SP, SP, -4),
+            ],
+            rule,
+            &[addi(SP, SP, -8), sw(A0, 8, SP), sw(A1, 4, SP)],
+        );
+        rewrites(
+            &[li(A0, 1), addi(SP, SP, 4), addi(SP, SP, -4)],
+            rule,
+            &[li(A0, 1)],
+        );
+        // Neither past an instruction reading sp otherwise, nor out of the cells in use
+        rewrites(
+            &[mv(A0, SP), addi(SP, SP, 4)],
+            rule,
+            &[mv(A0, SP), addi(SP, SP, 4)],
+        );
+        rewrites(
+            &[lw(A0, 4, SP), addi(SP, SP, 4)],
+            rule,
+            &[lw(A0, 4, SP), addi(SP, SP, 4)],
+        );
+    }
+
+    #[test]
+    fn loads_take_the_register_stored_to_their_cell() {
+        let rule = Peephole::forward_store;
+        rewrites(
+            &[sw(A0, 4, SP), addi(SP, SP, 4), lw(A1, 0, SP)],
+            rule,
+            &[sw(A0, 4, SP), mv(A1, A0), addi(SP, SP, 4)],
+        );
+        rewrites(&[sw(A0, 0, SP), lw(A0, 0, SP)], rule, &[sw(A0, 0, SP)]);
+        // A store through another register may be to the same cell
+        rewrites(
+            &[sw(A0, 0, SP), sw(A1, 0, A2), lw(A3, 0, SP)],
+            rule,
+            &[sw(A0, 0, SP), sw(A1, 0, A2), lw(A3, 0, SP)],
+        );
+        // The register loaded is read in between
+        rewrites(
+            &[sw(A0, 0, SP), mv(A2, A1), lw(A1, 0, SP)],
+            rule,
+            &[sw(A0, 0, SP), mv(A2, A1), lw(A1, 0, SP)],
+        );
+    }
+
+    #[test]
+    fn constant_operands_fold_into_immediates() {
+        let rule = Peephole::fold_constant;
+        rewrites(
+            &[li(A0, 6), li(A1, 7), add(A0, A0, A1)],
+            rule,
+            &[li(A0, 6), li(A1, 7), li(A0, 13)],
+        );
+        rewrites(
+            &[lui(A0, 1), slli(A0, A0, 4)],
+            rule,
+            &[lui(A0, 1), lui(A0, 0x10)],
+        );
+        rewrites(
+            &[li(A1, 3), sub(A0, A0, A1)],
+            rule,
+            &[li(A1, 3), addi(A0, A0, -3)],
+        );
+        rewrites(
+            &[li(A1, 5), and(A0, A1, A0)],
+            rule,
+            &[li(A1, 5), andi(A0, A0, 5)],
+        );
+        rewrites(
+            &[li(A1, 33), sll(A0, A0, A1)],
+            rule,
+            &[li(A1, 33), slli(A0, A0, 1)],
+        );
+        rewrites(
+            &[li(A1, 0), sw(A1, 0, SP)],
+            rule,
+            &[li(A1, 0), sw(ZERO, 0, SP)],
+        );
+        // Neither multiplication by a single constant nor a constant too wide to be immediate
+        rewrites(
+            &[li(A1, 3), mul(A0, A0, A1)],
+            rule,
+            &[li(A1, 3), mul(A0, A0, A1)],
+        );
+        rewrites(
+            &[lui(A1, 1), add(A0, A0, A1)],
+            rule,
+            &[lui(A1, 1), add(A0, A0, A1)],
+        );
+    }
+
+    #[test]
+    fn copies_are_read_from_their_source() {
+        let rule = Peephole::propagate_copy;
+        rewrites(
+            &[mv(A1, A0), add(A2, A1, A1)],
+            rule,
+            &[mv(A1, A0), add(A2, A0, A0)],
+        );
+        // The source is overwritten in between
+        rewrites(
+            &[mv(A1, A0), li(A0, 1), sw(A1, 0, SP)],
+            rule,
+            &[mv(A1, A0), li(A0, 1), sw(A1, 0, SP)],
+        );
+        // sp is never copied
+        rewrites(
+            &[mv(A1, SP), lw(A0, 0, A1)],
+            rule,
+            &[mv(A1, SP), lw(A0, 0, A1)],
+        );
+    }
+
+    #[test]
+    fn stores_stored_over_or_freed_are_dropped() {
+        let rule = Peephole::drop_dead_store;
+        rewrites(&[sw(A0, 4, SP), sw(A1, 4, SP)], rule, &[sw(A1, 4, SP)]);
+        rewrites(
+            &[sw(A0, 4, SP), lw(A1, 0, SP), addi(SP, SP, 4)],
+            rule,
+            &[lw(A1, 0, SP), addi(SP, SP, 4)],
+        );
+        rewrites(
+            &[sw(A0, 4, SP), lw(A1, 4, SP), addi(SP, SP, 4)],
+            rule,
+            &[sw(A0, 4, SP), lw(A1, 4, SP), addi(SP, SP, 4)],
+        );
+        // Loads through other registers may read the cell, and cells above sp stay in use
+        rewrites(
+            &[sw(A0, 4, SP), lw(A1, 0, A2), sw(A1, 4, SP)],
+            rule,
+            &[sw(A0, 4, SP), lw(A1, 0, A2), sw(A1, 4, SP)],
+        );
+        rewrites(&[sw(A0, 4, SP)], rule, &[sw(A0, 4, SP)]);
+    }
+
+    #[test]
+    fn writes_overwritten_or_released_are_dropped() {
+        rewrites(
+            &[li(A0, 1), li(A0, 2), mv(A1, A1), addi(SP, SP, 0)],
+            |peephole| peephole.drop_dead_write(false),
+            &[li(A0, 2)],
+        );
+        // Until the scratch registers are released, their values may be read further on
+        rewrites(
+            &[li(A0, 1), mv(A1, A0)],
+            |peephole| peephole.drop_dead_write(false),
+            &[li(A0, 1), mv(A1, A0)],
+        );
+        rewrites(
+            &[li(A0, 1), mv(A1, A0)],
+            |peephole| peephole.drop_dead_write(true),
+            &[],
+        );
+        // Registers holding the stacks, and loads other than those of cells, stay
+        rewrites(
+            &[li(S1, 1), lw(A0, 0, A1)],
+            |peephole| peephole.drop_dead_write(true),
+            &[li(S1, 1), lw(A0, 0, A1)],
+        );
+    }
+}
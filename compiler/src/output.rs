@@ -0,0 +1,155 @@
+use core::cell::Cell;
+
+use crate::buffer::Buffer;
+use crate::optimizer::{self, Peephole};
+use crate::target::Target;
+use crate::CompilerError;
+
+/// The code being compiled, as the bytes the target runs.
+///
+/// Instructions are given as encoded by [`crate::assembler`] and written little-endian, in their
+/// 16 bit form when the target has one.
+///
+/// When optimizing, the instructions written since the last one the optimizer can't look past
+/// are kept in a [`Peephole`] and rewritten after every call to [`Output::emit`], each of which
+/// must leave the data stack consistent. Asking for [`Output::len`] freezes the code written so
+/// far, as the offset may be the target of a branch or the site of a patch.
+pub struct Output<'o> {
+    code: Buffer<'o, u8>,
+    target: Target,
+    peephole: Option<Peephole>,
+    /// Offset of the first instruction in the peephole.
+    start: usize,
+    fenced: Cell<bool>,
+}
+
+impl<'o> Output<'o> {
+    pub fn new(code: &'o mut [u8], target: Target, optimize: bool) -> Self {
+        Output {
+            code: Buffer::borrowed(0, code),
+            target,
+            peephole: optimize.then(Peephole::new),
+            start: 0,
+            fenced: Cell::new(false),
+        }
+    }
+
+    /// Number of bytes written, also the offset of the next instruction.
+    pub fn len(&self) -> usize {
+        self.fenced.set(true);
+        self.code.len()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.code.as_slice()
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.code.as_mut_slice()
+    }
+
+    /// Appends `instructions`, compressed whenever the target allows it.
+    pub fn emit(&mut self, instructions: &[u32]) -> Result<(), CompilerError> {
+        if self.peephole.is_none() {
+            for instruction in instructions {
+                self.write(*instruction)?;
+            }
+            return Ok(());
+        }
+        if self.fenced.take() {
+            self.flush();
+        }
+        for instruction in instructions {
+            match optimizer::decode(*instruction) {
+                None => {
+                    self.flush();
+                    self.write(*instruction)?;
+                    self.start = self.code.len();
+                }
+                Some(decoded) => {
+                    if let Some(peephole) = &mut self.peephole {
+                        if let Some(evicted) = peephole.push(decoded) {
+                            self.start += self.target.size(&[optimizer::encode(evicted)]);
+                        }
+                    }
+                    self.write(*instruction)?;
+                }
+            }
+        }
+        self.rewrite(false)
+    }
+
+    /// Appends `instructions` in their 32 bit form, for those patched once emitted or holding
+    /// branches whose offsets were worked out by hand.
+    pub fn emit_fixed(&mut self, instructions: &[u32]) -> Result<(), CompilerError> {
+        self.flush();
+        for instruction in instructions {
+            self.push(&instruction.to_le_bytes())?;
+        }
+        self.start = self.code.len();
+        Ok(())
+    }
+
+    /// Appends code encoded already, such as the body of an inlined word.
+    pub fn bytes(&mut self, bytes: &[u8]) -> Result<(), CompilerError> {
+        self.flush();
+        self.push(bytes)?;
+        self.start = self.code.len();
+        Ok(())
+    }
+
+    /// Lets the optimizer drop values left in scratch registers, once the code of a token is
+    /// over.
+    pub fn release_registers(&mut self) -> Result<(), CompilerError> {
+        if self.peephole.is_none() || self.fenced.get() {
+            return Ok(());
+        }
+        self.rewrite(true)
+    }
+
+    /// Optimizes the instructions in the peephole and writes them in place of those emitted.
+    fn rewrite(&mut self, released: bool) -> Result<(), CompilerError> {
+        let Some(peephole) = &mut self.peephole else {
+            return Ok(());
+        };
+        peephole.optimize(released);
+        self.code.truncate(self.start);
+        for instruction in peephole.instructions() {
+            write(&mut self.code, self.target, optimizer::encode(*instruction))?;
+        }
+        Ok(())
+    }
+
+    /// Leaves the code written so far as it is.
+    fn flush(&mut self) {
+        if let Some(peephole) = &mut self.peephole {
+            peephole.clear();
+        }
+        self.start = self.code.len();
+    }
+
+    fn write(&mut self, instruction: u32) -> Result<(), CompilerError> {
+        write(&mut self.code, self.target, instruction)
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> Result<(), CompilerError> {
+        push(&mut self.code, bytes)
+    }
+}
+
+fn write(code: &mut Buffer<u8>, target: Target, instruction: u32) -> Result<(), CompilerError> {
+    match target.compress(instruction) {
+        Some(compressed) => push(code, &compressed.to_le_bytes()),
+        None => push(code, &instruction.to_le_bytes()),
+    }
+}
+
+fn push(code: &mut Buffer<u8>, bytes: &[u8]) -> Result<(), CompilerError> {
+    code.try_extend_from_slice(bytes)
+        .map_err(|_| CompilerError::OutputFull)
+}
+
+/// Overwrites the 32 bit instruction at `offset` in `code`.
+pub fn patch(code: &mut [u8], offset: usize, instruction: u32) {
+    code[offset..offset + 4].copy_from_slice(&instruction.to_le_bytes());
+}
@@ -1,5 +1,113 @@
-use riscv_isa_types::format::*;
-use riscv_isa_types::rv32i::RV32i;
+use crate::assembler::*;
+use crate::effect::StackEffect;
+use core::str::FromStr;
+
+/// Holds the top of the data stack when it is cached in a register.
+pub const TOS: u32 = S1;
+
+/// Pops the top of the data stack into a0, ahead of a conditional branch on its value.
+pub const POP_A0: [u32; 2] = [
+    lw(A0, 4, SP),   // load value
+    addi(SP, SP, 4), // reduce stack size by one cell
+];
+
+/// Starts the body of a word called with `jal`, saving the return point.
+pub const ENTER: [u32; 2] = [
+    sw(RA, 0, FP), // add return pt to Rstack
+    addi(FP, FP, -4),
+];
+
+/// Ends the body of a word called with `jal`, returning to the saved return point.
+pub const EXIT: [u32; 3] = [
+    addi(FP, FP, 4), // recover return pt from Rstack
+    lw(RA, 0, FP),
+    ret(), // return
+];
+
+/// Pushes a0 on the data stack.
+pub const PUSH_A0: [u32; 2] = [
+    sw(A0, 0, SP),    // store the value in the stack
+    addi(SP, SP, -4), // inscrease data stack size by one cell
+];
+
+/// Moves the limit and index of a counted loop from the data stack to the return stack, leaving
+/// the index in a0 and the limit in a1.
+pub const LOOP_PARAMS: [u32; 6] = [
+    lw(A0, 4, SP),    // load index
+    lw(A1, 8, SP),    // load limit
+    addi(SP, SP, 8),  // reduce stack size by two cells
+    sw(A1, 0, FP),    // add limit to return stack
+    sw(A0, -4, FP),   // add index to return stack
+    addi(FP, FP, -8), // increase return stack size by two cells
+];
+
+/// Increments the loop index by one, leaving it in a0 and the limit in a1. Followed by a branch
+/// back to the loop body while they differ.
+pub const LOOP_STEP: [u32; 4] = [
+    lw(A0, 4, FP),   // load index
+    lw(A1, 8, FP),   // load limit
+    addi(A0, A0, 1), // increment index
+    sw(A0, 4, FP),   // store index
+];
+
+/// Increments the loop index by the top of the stack, leaving in a0 a value that turns negative
+/// when the index crossed the boundary between limit - 1 and limit. Followed by a branch back to
+/// the loop body while a0 is positive.
+pub const PLUS_LOOP_STEP: [u32; 11] = [
+    lw(A0, 4, SP),   // load increment
+    addi(SP, SP, 4), // reduce stack size by one cell
+    lw(A1, 4, FP),   // load index
+    lw(A2, 8, FP),   // load limit
+    sub(A2, A1, A2), // distance from the index to the limit
+    add(A1, A1, A0), // increment index
+    sw(A1, 4, FP),   // store index
+    add(A1, A2, A0), // distance after the increment
+    xor(A1, A1, A2), // negative if the distance changed sign
+    xor(A0, A0, A2), // negative if the increment goes towards the limit
+    and(A0, A0, A1),
+];
+
+/// Pops the top of the data stack cached in s1 into a0, ahead of a conditional branch on its
+/// value.
+pub const CACHED_POP_A0: [u32; 3] = [
+    mv(A0, TOS),     // take the value
+    lw(TOS, 4, SP),  // load the cell below it
+    addi(SP, SP, 4), // reduce stack size by one cell
+];
+
+/// Pushes a0 on the data stack whose top is cached in s1.
+pub const CACHED_PUSH_A0: [u32; 3] = [
+    sw(TOS, 0, SP),   // spill the top of the stack
+    addi(SP, SP, -4), // inscrease data stack size by one cell
+    mv(TOS, A0),
+];
+
+/// [`LOOP_PARAMS`] with the top of the data stack cached in s1.
+pub const CACHED_LOOP_PARAMS: [u32; 7] = [
+    mv(A0, TOS),      // take index
+    lw(A1, 4, SP),    // load limit
+    lw(TOS, 8, SP),   // load the cell below them
+    addi(SP, SP, 8),  // reduce stack size by two cells
+    sw(A1, 0, FP),    // add limit to return stack
+    sw(A0, -4, FP),   // add index to return stack
+    addi(FP, FP, -8), // increase return stack size by two cells
+];
+
+/// [`PLUS_LOOP_STEP`] with the top of the data stack cached in s1.
+pub const CACHED_PLUS_LOOP_STEP: [u32; 12] = [
+    mv(A0, TOS),     // take increment
+    lw(TOS, 4, SP),  // load the cell below it
+    addi(SP, SP, 4), // reduce stack size by one cell
+    lw(A1, 4, FP),   // load index
+    lw(A2, 8, FP),   // load limit
+    sub(A2, A1, A2), // distance from the index to the limit
+    add(A1, A1, A0), // increment index
+    sw(A1, 4, FP),   // store index
+    add(A1, A2, A0), // distance after the increment
+    xor(A1, A1, A2), // negative if the distance changed sign
+    xor(A0, A0, A2), // negative if the increment goes towards the limit
+    and(A0, A0, A1),
+];
 
 pub enum Primitive {
     Load,
@@ -18,240 +126,295 @@ pub enum Primitive {
     Branch,
     RFrom,
     RTo,
+    I,
+    J,
+    Unloop,
 }
 
 impl Primitive {
+    /// Cells the primitive takes from the data stack and leaves on it. `BRANCH` runs code only
+    /// known at run time, so its effect isn't known.
+    pub fn effect(&self) -> Option<StackEffect> {
+        use Primitive::*;
+        let (inputs, outputs) = match self {
+            Load => (2, 0),
+            Fetch => (1, 1),
+            LShift | RShift | Add | Sub | Xor | Or | And | Eq | Gt | Lt => (2, 1),
+            Branch => return None,
+            RTo => (1, 0),
+            RFrom | I | J | Push(_) => (0, 1),
+            Unloop => (0, 0),
+        };
+        Some(StackEffect::new(inputs, outputs))
+    }
+
     pub fn get_instructions(&self) -> (usize, [u32; 8]) {
         use Primitive::*;
         match self {
-            Load => (
-                4,
-                [
-                    0x03254100, // lw a0, 4(sp)   # load addr
-                    0x83258100, // lw a1, 8(sp)   # load value
-                    0x13018100, // addi sp, sp, 8 # move stack pt 2 cells up
-                    0x2320b500, // sw a1, 0(a0)   # write to memory(addr) the value
-                    0, 0, 0, 0,
-                ],
-            ),
-            Fetch => (
-                3,
-                [
-                    0x03254100, // lw a0, 4(sp) # Load addr
-                    0x03250500, // lw a0, 0(a0) # Load value at memory(addr)
-                    0x2322a100, // sw a0, 4(sp) # Push value on stack
-                    0, 0, 0, 0, 0,
-                ],
-            ),
-            LShift => (
-                5,
-                [
-                    0x03254100, // lw a0, 4(sp)    # load amount
-                    0x83258100, // lw a1, 8(sp)    # load value
-                    0x3395a500, // sll a0, a1, a0  # left logical shift value << amount
-                    0x13014100, // addi sp, sp, 4  # reduce stack size by one cell
-                    0x2322a100, // sw a0, 4(sp)    # store shifted value to stack
-                    0, 0, 0,
-                ],
-            ),
-            RShift => (
-                5,
-                [
-                    0x03254100, // lw a0, 4(sp)    # load amount
-                    0x83258100, // lw a1, 8(sp)    # load value
-                    0x33d5a500, // srl a0, a1, a0  # right logical shift value >> amount
-                    0x13014100, // addi sp, sp, 4  # reduce stack size by one cell
-                    0x2322a100, // sw a0, 4(sp)    # store shifted value to stack
-                    0, 0, 0,
-                ],
-            ),
-            Add => (
-                5,
-                [
-                    0x03254100, // lw a0, 4(sp)   # load operands
-                    0x83258100, // lw a1, 8(sp)
-                    0x3385a500, // add a0, a1, a0 # perform operation
-                    0x13014100, // addi sp, sp, 4 # reduce stack size by one cell
-                    0x2322a100, // sw a0, 4(sp)   # store result to stack
-                    0, 0, 0,
-                ],
-            ),
-            Sub => (
-                5,
-                [
-                    0x03254100, // lw a0, 4(sp)   # load operands
-                    0x83258100, // lw a1, 8(sp)
-                    0x3385a540, // sub a0, a1, a0 # perform operation
-                    0x13014100, // addi sp, sp, 4 # reduce stack size by one cell
-                    0x2322a100, // sw a0, 4(sp)   # store result to stack
-                    0, 0, 0,
-                ],
-            ),
-            Xor => (
-                5,
-                [
-                    0x03254100, // lw a0, 4(sp)   # load operands
-                    0x83258100, // lw a1, 8(sp)
-                    0x33c5a500, // xor a0, a1, a0 # perform operation
-                    0x13014100, // addi sp, sp, 4 # reduce stack size by one cell
-                    0x2322a100, // sw a0, 4(sp)   # store result to stack
-                    0, 0, 0,
-                ],
-            ),
-            Or => (
-                5,
-                [
-                    0x03254100, // lw a0, 4(sp)   # load operands
-                    0x83258100, // lw a1, 8(sp)
-                    0x33e5a500, // or a0, a1, a0  # perform operation
-                    0x13014100, // addi sp, sp, 4 # reduce stack size by one cell
-                    0x2322a100, // sw a0, 4(sp)   # store result to stack
-                    0, 0, 0,
-                ],
-            ),
-            And => (
-                5,
-                [
-                    0x03254100, // lw a0, 4(sp)   # load operands
-                    0x83258100, // lw a1, 8(sp)
-                    0x33f5a500, // and a0, a1, a0 # perform operation
-                    0x13014100, // addi sp, sp, 4 # reduce stack size by one cell
-                    0x2322a100, // sw a0, 4(sp)   # store result to stack
-                    0, 0, 0,
-                ],
-            ),
-            Eq => (
-                8,
-                [
-                    0x03254100, // lw a0, 4(sp)    # load left
-                    0x83258100, // lw a1, 8(sp)    # load right
-                    0x13014100, // addi sp, sp, 4  # reduce stack size by one cell
-                    0x3345b500, // xor a0, a0, a1  # perform eq checks
-                    0x13351500, // seqz a0, a0
-                    0x1315f501, // slli a0, a0, 31 # sext
-                    0x1355f541, // srai a0, a0, 31
-                    0x2322a100, // sw a0, 4(sp)    # store result to stack
-                ],
-            ),
-            Lt => (
-                7,
-                [
-                    0x03254100, // lw a0, 4(sp)     # load right
-                    0x83258100, // lw a1, 8(sp)     # load left
-                    0x13014100, // addi sp, sp, 4   # reduce stack size by one cell
-                    0x33a5a500, // slt a0, a1, a0   # check less than
-                    0x1315f501, // slli a0, a0, 31  # sext
-                    0x1355f541, // srai a0, a0, 31
-                    0x2320a100, // sw a0, 0(sp)     # store result to stack
-                    0,
-                ],
-            ),
-            Gt => (
-                7,
-                [
-                    0x03254100, // lw a0, 4(sp)     # load right
-                    0x83258100, // lw a1, 8(sp)     # load left
-                    0x13014100, // addi sp, sp, 4   # reduce stack size by one cell
-                    0x3325b500, // slt a0, a0, a1   # check greater than
-                    0x1315f501, // slli a0, a0, 31  # sext
-                    0x1355f541, // srai a0, a0, 31
-                    0x2320a100, // sw a0, 0(sp)     # store result to stack
-                    0,
-                ],
-            ),
-            Branch => (
-                7,
-                [
-                    0x13014100, // addi sp, sp, 4   # load address
-                    0x03250100, // lw a0, 0(sp)
-                    0x23201400, // sw x1, 0(fp)     # add return pt to Rstack
-                    0x1304c4ff, // addi fp, fp, -4
-                    0xe7000500, // jalr x1, 0(a0)   # jump and link
-                    0x13044400, // addi fp, fp, 4   # recover return pt from Rstack
-                    0x83200400, // lw x1, 0(fp)
-                    0,
-                ],
-            ),
-            RTo => (
-                4,
-                [
-                    0x03254100, // lw a0, 4(sp)     # load value from stack
-                    0x13014100, // addi sp, sp, 4   # reduce stack size by one cell
-                    0x2320a400, // sw a0, 0(fp)     # add value to return stack
-                    0x1304c4ff, // addi fp, fp, -4  # increase return stack size by one cell
-                    0, 0, 0, 0,
-                ],
-            ),
-            RFrom => (
-                4,
-                [
-                    0x03254400, // lw a0, 4(fp)     # load value from return stack
-                    0x13044400, // addi fp, fp, 4   # reduce Rstack by one cell
-                    0x2320a100, // sw a0, 0(sp)     # add value to data stack
-                    0x1301c1ff, // addi sp, sp, -4  # inscrease data stack size by one cell
-                    0, 0, 0, 0,
-                ],
-            ),
+            Load => padded([
+                lw(A0, 4, SP),   // load addr
+                lw(A1, 8, SP),   // load value
+                addi(SP, SP, 8), // move stack pt 2 cells up
+                sw(A1, 0, A0),   // write to memory(addr) the value
+            ]),
+            Fetch => padded([
+                lw(A0, 4, SP), // Load addr
+                lw(A0, 0, A0), // Load value at memory(addr)
+                sw(A0, 4, SP), // Push value on stack
+            ]),
+            LShift => padded([
+                lw(A0, 4, SP),   // load amount
+                lw(A1, 8, SP),   // load value
+                sll(A0, A1, A0), // left logical shift value << amount
+                addi(SP, SP, 4), // reduce stack size by one cell
+                sw(A0, 4, SP),   // store shifted value to stack
+            ]),
+            RShift => padded([
+                lw(A0, 4, SP),   // load amount
+                lw(A1, 8, SP),   // load value
+                srl(A0, A1, A0), // right logical shift value >> amount
+                addi(SP, SP, 4), // reduce stack size by one cell
+                sw(A0, 4, SP),   // store shifted value to stack
+            ]),
+            Add => padded([
+                lw(A0, 4, SP), // load operands
+                lw(A1, 8, SP),
+                add(A0, A1, A0), // perform operation
+                addi(SP, SP, 4), // reduce stack size by one cell
+                sw(A0, 4, SP),   // store result to stack
+            ]),
+            Sub => padded([
+                lw(A0, 4, SP), // load operands
+                lw(A1, 8, SP),
+                sub(A0, A1, A0), // perform operation
+                addi(SP, SP, 4), // reduce stack size by one cell
+                sw(A0, 4, SP),   // store result to stack
+            ]),
+            Xor => padded([
+                lw(A0, 4, SP), // load operands
+                lw(A1, 8, SP),
+                xor(A0, A1, A0), // perform operation
+                addi(SP, SP, 4), // reduce stack size by one cell
+                sw(A0, 4, SP),   // store result to stack
+            ]),
+            Or => padded([
+                lw(A0, 4, SP), // load operands
+                lw(A1, 8, SP),
+                or(A0, A1, A0),  // perform operation
+                addi(SP, SP, 4), // reduce stack size by one cell
+                sw(A0, 4, SP),   // store result to stack
+            ]),
+            And => padded([
+                lw(A0, 4, SP), // load operands
+                lw(A1, 8, SP),
+                and(A0, A1, A0), // perform operation
+                addi(SP, SP, 4), // reduce stack size by one cell
+                sw(A0, 4, SP),   // store result to stack
+            ]),
+            Eq => padded([
+                lw(A0, 4, SP),   // load left
+                lw(A1, 8, SP),   // load right
+                addi(SP, SP, 4), // reduce stack size by one cell
+                xor(A0, A0, A1), // perform eq checks
+                seqz(A0, A0),
+                slli(A0, A0, 31), // sext
+                srai(A0, A0, 31),
+                sw(A0, 4, SP), // store result to stack
+            ]),
+            Lt => padded([
+                lw(A0, 4, SP),    // load right
+                lw(A1, 8, SP),    // load left
+                addi(SP, SP, 4),  // reduce stack size by one cell
+                slt(A0, A1, A0),  // check less than
+                slli(A0, A0, 31), // sext
+                srai(A0, A0, 31),
+                sw(A0, 4, SP), // store result to stack
+            ]),
+            Gt => padded([
+                lw(A0, 4, SP),    // load right
+                lw(A1, 8, SP),    // load left
+                addi(SP, SP, 4),  // reduce stack size by one cell
+                slt(A0, A0, A1),  // check greater than
+                slli(A0, A0, 31), // sext
+                srai(A0, A0, 31),
+                sw(A0, 4, SP), // store result to stack
+            ]),
+            Branch => padded([
+                lw(A0, 4, SP),   // load address
+                addi(SP, SP, 4), // reduce stack size by one cell
+                sw(RA, 0, FP),   // add return pt to Rstack
+                addi(FP, FP, -4),
+                jalr(RA, 0, A0), // jump and link
+                addi(FP, FP, 4), // recover return pt from Rstack
+                lw(RA, 0, FP),
+            ]),
+            RTo => padded([
+                lw(A0, 4, SP),    // load value from stack
+                addi(SP, SP, 4),  // reduce stack size by one cell
+                sw(A0, 0, FP),    // add value to return stack
+                addi(FP, FP, -4), // increase return stack size by one cell
+            ]),
+            RFrom => padded([
+                lw(A0, 4, FP),    // load value from return stack
+                addi(FP, FP, 4),  // reduce Rstack by one cell
+                sw(A0, 0, SP),    // add value to data stack
+                addi(SP, SP, -4), // inscrease data stack size by one cell
+            ]),
+            I => padded([
+                lw(A0, 4, FP),    // load index from return stack
+                sw(A0, 0, SP),    // add value to data stack
+                addi(SP, SP, -4), // inscrease data stack size by one cell
+            ]),
+            J => padded([
+                lw(A0, 12, FP),   // load outer index, past the inner limit
+                sw(A0, 0, SP),    // add value to data stack
+                addi(SP, SP, -4), // inscrease data stack size by one cell
+            ]),
+            Unloop => padded([
+                addi(FP, FP, 8), // drop loop limit and index from Rstack
+            ]),
+            Push(v) => {
+                let (len, load) = load_immediate(A0, *v);
+                let mut instructions = [0; 8];
+                instructions[..len].copy_from_slice(&load[..len]);
+                instructions[len..len + 2].copy_from_slice(&[
+                    sw(A0, 0, SP),    // store the result in the stack
+                    addi(SP, SP, -4), // inscrease data stack size by one cell
+                ]);
+                (len + 2, instructions)
+            }
+        }
+    }
+
+    /// The instructions of the primitive when the top of the data stack is cached in s1, with
+    /// the cells below it in memory from 4(sp) on.
+    pub fn get_cached_instructions(&self) -> (usize, [u32; 8]) {
+        use Primitive::*;
+        match self {
+            Load => padded([
+                lw(A1, 4, SP),   // load value
+                sw(A1, 0, TOS),  // write to memory(addr) the value
+                lw(TOS, 8, SP),  // load the new top of the stack
+                addi(SP, SP, 8), // move stack pt 2 cells up
+            ]),
+            Fetch => padded([
+                lw(TOS, 0, TOS), // Load value at memory(addr)
+            ]),
+            LShift => cached_binary(sll(TOS, A1, TOS)),
+            RShift => cached_binary(srl(TOS, A1, TOS)),
+            Add => cached_binary(add(TOS, A1, TOS)),
+            Sub => cached_binary(sub(TOS, A1, TOS)),
+            Xor => cached_binary(xor(TOS, A1, TOS)),
+            Or => cached_binary(or(TOS, A1, TOS)),
+            And => cached_binary(and(TOS, A1, TOS)),
+            Eq => padded([
+                lw(A1, 4, SP),     // load left
+                addi(SP, SP, 4),   // reduce stack size by one cell
+                xor(TOS, A1, TOS), // perform eq checks
+                seqz(TOS, TOS),
+                neg(TOS, TOS), // all bits set when true
+            ]),
+            Lt => padded([
+                lw(A1, 4, SP),     // load left
+                addi(SP, SP, 4),   // reduce stack size by one cell
+                slt(TOS, A1, TOS), // check less than
+                neg(TOS, TOS),     // all bits set when true
+            ]),
+            Gt => padded([
+                lw(A1, 4, SP),     // load left
+                addi(SP, SP, 4),   // reduce stack size by one cell
+                slt(TOS, TOS, A1), // check greater than
+                neg(TOS, TOS),     // all bits set when true
+            ]),
+            Branch => padded([
+                mv(A0, TOS),     // take address
+                lw(TOS, 4, SP),  // load the cell below it
+                addi(SP, SP, 4), // reduce stack size by one cell
+                sw(RA, 0, FP),   // add return pt to Rstack
+                addi(FP, FP, -4),
+                jalr(RA, 0, A0), // jump and link
+                addi(FP, FP, 4), // recover return pt from Rstack
+                lw(RA, 0, FP),
+            ]),
+            RTo => padded([
+                sw(TOS, 0, FP),   // add value to return stack
+                addi(FP, FP, -4), // increase return stack size by one cell
+                lw(TOS, 4, SP),   // load the cell below it
+                addi(SP, SP, 4),  // reduce stack size by one cell
+            ]),
+            RFrom => padded([
+                sw(TOS, 0, SP),   // spill the top of the stack
+                addi(SP, SP, -4), // inscrease data stack size by one cell
+                lw(TOS, 4, FP),   // load value from return stack
+                addi(FP, FP, 4),  // reduce Rstack by one cell
+            ]),
+            I => padded([
+                sw(TOS, 0, SP),   // spill the top of the stack
+                addi(SP, SP, -4), // inscrease data stack size by one cell
+                lw(TOS, 4, FP),   // load index from return stack
+            ]),
+            J => padded([
+                sw(TOS, 0, SP),   // spill the top of the stack
+                addi(SP, SP, -4), // inscrease data stack size by one cell
+                lw(TOS, 12, FP),  // load outer index, past the inner limit
+            ]),
+            Unloop => self.get_instructions(),
             Push(v) => {
-                if *v < 0xfff {
-                    let addi_format = IFormat {
-                        funct3: 0b000,
-                        imm: *v,
-                        op: 0b0010011,
-                        rd: 10, // x10|a0
-                        rs1: 0, // x0|zero
-                    };
-                    let addi = RV32i::ADDI(addi_format);
-                    let addi: u32 = addi.into();
-                    (
-                        3,
-                        [
-                            addi,
-                            0x2320a100, // sw a0, 0(sp)     # store the result in the stack
-                            0x1301c1ff, // addi sp, sp, -4  # inscrease data stack size by one cell
-                            0, 0, 0, 0, 0,
-                        ],
-                    )
-                } else {
-                    // lui + addi
-                    let vu = v >> 12;
-                    let vl = (v << 20) >> 20;
-                    let lui_format = UFormat {
-                        op: 0b0110111,
-                        imm: vu,
-                        rd: 10,
-                    };
-                    let lui = RV32i::LUI(lui_format);
-                    let addi_format = IFormat {
-                        funct3: 0b000,
-                        imm: vl,
-                        op: 0b0010011,
-                        rd: 10, // x10|a0
-                        rs1: 0, // x0|zero
-                    };
-                    let addi = RV32i::ADDI(addi_format);
-                    (
-                        4,
-                        [
-                            lui.into(),
-                            addi.into(),
-                            0x2320a100, // sw a0, 0(sp)     # store the result in the stack
-                            0x1301c1ff, // addi sp, sp, -4  # inscrease data stack size by one cell
-                            0,
-                            0,
-                            0,
-                            0,
-                        ],
-                    )
-                }
+                let (len, load) = load_immediate(TOS, *v);
+                let mut instructions = [0; 8];
+                instructions[..2].copy_from_slice(&[
+                    sw(TOS, 0, SP),   // spill the top of the stack
+                    addi(SP, SP, -4), // inscrease data stack size by one cell
+                ]);
+                instructions[2..len + 2].copy_from_slice(&load[..len]);
+                (len + 2, instructions)
             }
         }
     }
 }
 
-impl TryFrom<&str> for Primitive {
-    type Error = ();
-    fn try_from(s: &str) -> Result<Primitive, ()> {
+/// The instructions of a primitive and how many of them there are, padded with zeros.
+fn padded<const N: usize>(instructions: [u32; N]) -> (usize, [u32; 8]) {
+    let mut padded = [0; 8];
+    padded[..N].copy_from_slice(&instructions);
+    (N, padded)
+}
+
+/// Combines the cell below the top of the stack, loaded into a1, with the top cached in s1 by
+/// `operation`, leaving the result in s1.
+fn cached_binary(operation: u32) -> (usize, [u32; 8]) {
+    padded([
+        lw(A1, 4, SP),   // load left
+        addi(SP, SP, 4), // reduce stack size by one cell
+        operation,
+    ])
+}
+
+/// Loads `value` into `rd` with as few of `lui` and `addi` as it takes.
+fn load_immediate(rd: u32, value: u32) -> (usize, [u32; 2]) {
+    // addi sign extends its immediate, so the upper part is rounded up whenever bit 11 is set
+    // to compensate for the lower part turning negative.
+    let (upper, lower) = split_immediate(value);
+    if upper == 0 {
+        (1, [li(rd, lower), 0])
+    } else if lower == 0 {
+        (1, [lui(rd, upper), 0])
+    } else {
+        (2, [lui(rd, upper), addi(rd, rd, lower)])
+    }
+}
+
+/// Splits a value in the upper immediate for `lui` or `auipc` and the sign extended lower
+/// immediate of the `addi`, or `jalr`, following it so that together they add up to the value.
+pub fn split_immediate(value: u32) -> (u32, i32) {
+    let upper = value.wrapping_add(0x800) >> 12;
+    let lower = value.wrapping_sub(upper << 12) as i32;
+    (upper, lower)
+}
+
+impl FromStr for Primitive {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Primitive, ()> {
         use Primitive::*;
         match s {
             "!" => Ok(Load),
@@ -269,7 +432,92 @@ impl TryFrom<&str> for Primitive {
             "BRANCH" => Ok(Branch),
             "R<" => Ok(RTo),
             "R>" => Ok(RFrom),
+            "I" => Ok(I),
+            "J" => Ok(J),
+            "UNLOOP" => Ok(Unloop),
             _ => Err(()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The value `lui rd, upper` followed by `addi rd, rd, lower` leaves in rd.
+    fn materialize(upper: u32, lower: i32) -> u32 {
+        (upper << 12).wrapping_add(lower as u32)
+    }
+
+    #[test]
+    fn split_immediate_adds_up_around_boundaries() {
+        let boundaries: [u32; 13] = [
+            0x0000_0000,
+            0x0000_07ff,
+            0x0000_0800,
+            0x0000_0fff,
+            0x0000_1000,
+            0x7fff_f7ff,
+            0x7fff_f800,
+            0x7fff_ffff,
+            0x8000_0000,
+            0x8000_0800,
+            0xffff_f000,
+            0xffff_f800,
+            0xffff_ffff,
+        ];
+        for boundary in boundaries {
+            for delta in -64i32..64 {
+                let value = boundary.wrapping_add(delta as u32);
+                let (upper, lower) = split_immediate(value);
+                assert!(upper < 1 << 20, "{value:#x}");
+                assert!((-2048..2048).contains(&lower), "{value:#x}");
+                assert_eq!(materialize(upper, lower), value, "{value:#x}");
+            }
+        }
+    }
+
+    #[test]
+    fn split_immediate_adds_up_for_every_lower_part() {
+        for upper in [0, 1, 0x7ffff, 0x80000, 0xfffff] {
+            for low in 0..0x1000 {
+                let value = upper << 12 | low;
+                let (upper, lower) = split_immediate(value);
+                assert!((-2048..2048).contains(&lower), "{value:#x}");
+                assert_eq!(materialize(upper, lower), value, "{value:#x}");
+            }
+        }
+    }
+
+    #[test]
+    fn push_skips_unneeded_instructions() {
+        assert_eq!(Primitive::Push(0).get_instructions().0, 3);
+        assert_eq!(Primitive::Push(2047).get_instructions().0, 3);
+        assert_eq!(Primitive::Push(-2048i32 as u32).get_instructions().0, 3);
+        assert_eq!(Primitive::Push(0x1000).get_instructions().0, 3);
+        assert_eq!(Primitive::Push(0x800).get_instructions().0, 4);
+        assert_eq!(Primitive::Push(0xffff_ffff).get_instructions().0, 3);
+        assert_eq!(Primitive::Push(0x1234_5678).get_instructions().0, 4);
+    }
+
+    #[test]
+    fn push_loads_values_around_the_sign_of_addi() {
+        const STORE: [u32; 2] = [
+            0x00a1_2023, // sw a0, 0(sp)
+            0xffc1_0113, // addi sp, sp, -4
+        ];
+        let cases: [(u32, &[u32]); 5] = [
+            (0x7ff, &[0x7ff0_0513]),                    // addi a0, zero, 2047
+            (0x800, &[0x0000_1537, 0x8005_0513]),       // lui a0, 1; addi a0, a0, -2048
+            (-2048i32 as u32, &[0x8000_0513]),          // addi a0, zero, -2048
+            (0xffff_f800, &[0x8000_0513]),              // addi a0, zero, -2048
+            (0xffff_f7ff, &[0xffff_f537, 0x7ff5_0513]), // lui a0, 0xfffff; addi a0, a0, 2047
+        ];
+        for (value, load) in cases {
+            let (len, instructions) = Primitive::Push(value).get_instructions();
+            let instructions = instructions[..len].iter().copied();
+            let expected = load.iter().chain(&STORE).copied();
+            assert!(instructions.eq(expected), "{value:#x}");
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use crate::assembler::{self, A0, RA};
+use crate::output;
+use crate::primitives;
+use crate::{CompiledWord, CompilerError};
+
+/// How the instructions at a relocation refer to their symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationKind {
+    /// `auipc ra, hi; jalr ra, lo(ra)` calling the symbol.
+    Call,
+    /// `auipc a0, hi; addi a0, a0, lo` loading the address of the symbol.
+    Address,
+    /// `lui a0, hi; addi a0, a0, lo` loading an address in the data segment, `addend` bytes
+    /// into it.
+    Data,
+}
+
+impl RelocationKind {
+    /// The instruction pair reaching `offset` bytes away from the `auipc`, or the address
+    /// `offset` for `Data`.
+    pub fn encode(self, offset: i32) -> [u32; 2] {
+        let (upper, lower) = primitives::split_immediate(offset as u32);
+        match self {
+            RelocationKind::Call => [assembler::auipc(RA, upper), assembler::jalr(RA, lower, RA)],
+            RelocationKind::Address => {
+                [assembler::auipc(A0, upper), assembler::addi(A0, A0, lower)]
+            }
+            RelocationKind::Data => [assembler::lui(A0, upper), assembler::addi(A0, A0, lower)],
+        }
+    }
+}
+
+/// A pair of instructions in the output referring to a word compiled into an earlier output, or
+/// to the data segment.
+///
+/// Everything else in the output is pc relative, so applying the relocations is all it takes to
+/// run it from any address. The data segment is at an address known while compiling, so the
+/// `Data` ones already hold it and only matter to objects, where the linker places the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation<'a> {
+    /// Offset in bytes in the output of the first instruction of the pair.
+    pub offset: usize,
+    pub kind: RelocationKind,
+    /// Name of the word referred to, empty for `Data`.
+    pub symbol: &'a str,
+    /// Compilation of the word referred to, and offset of the word in its output. They keep to
+    /// the word the reference was compiled against once a later one takes its name.
+    pub compilation: usize,
+    pub addr: usize,
+    /// Offset of the address referred to from the start of the data segment, for `Data`.
+    pub addend: u32,
+    /// The value stored to the cell at `addend` by code outside of definitions initialising it,
+    /// for objects to lay out in their data.
+    pub initial: Option<u32>,
+}
+
+impl<'a> Relocation<'a> {
+    /// A relocation of the instructions at `offset` reaching `word`.
+    pub fn new(offset: usize, kind: RelocationKind, word: &CompiledWord<'a>) -> Self {
+        Relocation {
+            offset,
+            kind,
+            symbol: word.name,
+            compilation: word.compilation,
+            addr: word.addr,
+            addend: 0,
+            initial: None,
+        }
+    }
+
+    /// A relocation of the instructions at `offset` loading the address `addend` bytes into
+    /// the data segment.
+    pub fn data(offset: usize, addend: u32) -> Self {
+        Relocation {
+            addend,
+            ..Relocation::new(offset, RelocationKind::Data, &CompiledWord::new("", "", 0))
+        }
+    }
+
+    /// Whether the relocation reaches `word`.
+    pub fn refers_to(&self, word: &CompiledWord) -> bool {
+        self.kind != RelocationKind::Data
+            && word.compilation == self.compilation
+            && word.addr == self.addr
+    }
+
+    /// Patches the instructions of `code`, loaded at `address`, to reach `target`.
+    pub fn apply(&self, code: &mut [u8], address: u32, target: u32) {
+        let pc = address.wrapping_add(self.offset as u32);
+        let [hi, lo] = self.kind.encode(target.wrapping_sub(pc) as i32);
+        output::patch(code, self.offset, hi);
+        output::patch(code, self.offset + 4, lo);
+    }
+}
+
+impl Default for Relocation<'_> {
+    fn default() -> Self {
+        Relocation::data(0, 0)
+    }
+}
+
+/// Applies `relocations` to `code` loaded at `address`, looking up the address of the word each
+/// one reaches with `resolve`. Those of the data segment are left as they are.
+pub fn relocate<'a>(
+    code: &mut [u8],
+    relocations: &[Relocation<'a>],
+    address: u32,
+    mut resolve: impl FnMut(&Relocation<'a>) -> Option<u32>,
+) -> Result<(), CompilerError> {
+    for relocation in relocations {
+        if relocation.kind == RelocationKind::Data {
+            continue;
+        }
+        let target = resolve(relocation).ok_or(CompilerError::UnresolvedSymbol)?;
+        relocation.apply(code, address, target);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompiledWord, ForthCompiler};
+
+    /// Destination register and sign extended immediate of the `auipc` or I-type instruction at
+    /// offset `at` of `code`.
+    fn decode(code: &[u8], at: usize) -> (u32, i32) {
+        let raw = u32::from_le_bytes(code[at..at + 4].try_into().unwrap());
+        let rd = raw >> 7 & 0x1f;
+        match raw & 0x7f {
+            0b0010111 => (rd, (raw & !0xfff) as i32),
+            _ => (rd, raw as i32 >> 20),
+        }
+    }
+
+    #[test]
+    fn calls_are_patched_to_reach_their_target() {
+        let mut code = [0; 16];
+        let call = Relocation::new(8, RelocationKind::Call, &CompiledWord::new("w", "", 0));
+        call.apply(&mut code, 0x1000, 0x2_0ffc);
+        let [hi, lo] = [assembler::auipc(RA, 0x20), assembler::jalr(RA, -12, RA)];
+        assert_eq!(code[8..12], hi.to_le_bytes());
+        assert_eq!(code[12..], lo.to_le_bytes());
+
+        // The lower half is sign extended, so the upper one makes up for it
+        call.apply(&mut code, 0x1000, 0x1000 + 8 + 0x1800);
+        assert_eq!(decode(&code, 8), (RA, 0x2000));
+        assert_eq!(decode(&code, 12), (RA, -0x800));
+    }
+
+    #[test]
+    fn calls_into_an_earlier_output_reach_it_where_it_was_loaded() {
+        let mut keys = [(); 4].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 256];
+        let mut relocations = [Relocation::default(); 4];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .relocations(&mut relocations)
+            .build();
+        let mut first = [0; 256];
+        let len = compiler.compile(": double 1 << ;", &mut first).unwrap();
+        compiler.relocate(&mut first[..len], 0x2000_0a00).unwrap();
+        let mut second = [0; 256];
+        let len = compiler
+            .compile(": quad double double ; ' double", &mut second)
+            .unwrap();
+
+        // Loaded below the first output, so the calls reach back up to it
+        let address = 0x1000_0000;
+        compiler.relocate(&mut second[..len], address).unwrap();
+        let (double, _) = compiler.dictionary().lookup("double").unwrap();
+        let target = double.load_address.unwrap();
+        assert_eq!(target, 0x2000_0a00 + double.addr as u32);
+        let kinds = compiler.relocations().iter().map(|r| r.kind);
+        assert!(kinds.eq([
+            RelocationKind::Call,
+            RelocationKind::Call,
+            RelocationKind::Address
+        ]));
+        for relocation in compiler.relocations() {
+            let pc = address + relocation.offset as u32;
+            let (rd, upper) = decode(&second, relocation.offset);
+            let (linked, lower) = decode(&second, relocation.offset + 4);
+            let register = match relocation.kind {
+                RelocationKind::Call => RA,
+                RelocationKind::Address => A0,
+                RelocationKind::Data => unreachable!(),
+            };
+            assert_eq!((rd, linked), (register, register));
+            let reached = pc.wrapping_add(upper as u32).wrapping_add(lower as u32);
+            assert_eq!(reached, target);
+        }
+    }
+
+    #[test]
+    fn symbols_of_outputs_not_relocated_are_unresolved() {
+        let mut keys = [(); 4].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 256];
+        let mut relocations = [Relocation::default(); 4];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .relocations(&mut relocations)
+            .build();
+        let mut output = [0; 256];
+        compiler.compile(": double 1 << ;", &mut output).unwrap();
+        let len = compiler
+            .compile(": quad double double ;", &mut output)
+            .unwrap();
+        let error = compiler.relocate(&mut output[..len], 0x1000);
+        assert_eq!(error, Err(CompilerError::UnresolvedSymbol));
+    }
+
+    #[test]
+    fn later_definitions_keep_out_of_the_references_compiled_before_them() {
+        let mut keys = [(); 4].map(|_| CompiledWord::new("", "", 0));
+        let mut memory = [0; 256];
+        let mut relocations = [Relocation::default(); 4];
+        let mut compiler = ForthCompiler::builder()
+            .storage(&mut keys, &mut memory)
+            .relocations(&mut relocations)
+            .build();
+        let mut first = [0; 256];
+        let len = compiler.compile(": w 1 2 + ;", &mut first).unwrap();
+        compiler.relocate(&mut first[..len], 0x1000).unwrap();
+        let (old, _) = compiler.dictionary().lookup("w").unwrap();
+        let target = old.load_address.unwrap();
+
+        let mut second = [0; 256];
+        let len = compiler
+            .compile(": x w ; : w 3 4 + ;", &mut second)
+            .unwrap();
+        compiler.relocate(&mut second[..len], 0x2000).unwrap();
+        let [relocation] = compiler.relocations() else {
+            panic!("{:?}", compiler.relocations());
+        };
+        let pc = 0x2000 + relocation.offset as u32;
+        let (_, upper) = decode(&second, relocation.offset);
+        let (_, lower) = decode(&second, relocation.offset + 4);
+        let reached = pc.wrapping_add(upper as u32).wrapping_add(lower as u32);
+        assert_eq!(reached, target);
+    }
+}
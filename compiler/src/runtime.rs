@@ -0,0 +1,643 @@
+use crate::assembler::*;
+use crate::control::Control;
+use crate::effect::StackEffect;
+use crate::output::Output;
+use crate::primitives::TOS;
+use crate::stack::TopOfStack;
+use crate::target::Target;
+use crate::CompilerError;
+use core::str::FromStr;
+
+/// Multiplies a0 by a1 shifting and adding, leaving the low cell of the product in a0 and the
+/// high cell in a1. Returns to t0.
+const MULTIPLY: [u32; 19] = [
+    mv(A2, A0), // multiplicand, widened into a3:a2
+    li(A3, 0),
+    li(A0, 0), // product, accumulated into a4:a0
+    li(A4, 0),
+    beq(A1, ZERO, 52), // loop: to done when no multiplier bits are left
+    andi(A5, A1, 1),
+    beq(A5, ZERO, 20), // to shift, adding the multiplicand only when the low bit is set
+    add(A0, A0, A2),
+    sltu(A5, A0, A2), // carry of the low cell
+    add(A4, A4, A3),
+    add(A4, A4, A5),
+    srli(A5, A2, 31), // shift:
+    slli(A2, A2, 1),  // multiplicand << 1
+    slli(A3, A3, 1),
+    or(A3, A3, A5),
+    srli(A1, A1, 1), // multiplier >> 1
+    j(-48),          // to loop
+    mv(A1, A4),      // done:
+    jr(T0),
+];
+
+/// Divides the unsigned double cell a1:a0 by a2 with a restoring division, leaving the quotient
+/// in a0 and the remainder in a1. The quotient must fit in a cell. Returns to t0.
+const DIVIDE: [u32; 13] = [
+    li(A3, 32),       // quotient bits left
+    srli(A4, A1, 31), // loop:
+    slli(A1, A1, 1),  // shift the next dividend bit into the remainder
+    srli(A5, A0, 31),
+    or(A1, A1, A5),
+    slli(A0, A0, 1),  // and make room for the next quotient bit
+    bne(A4, ZERO, 8), // to subtract when the remainder overflowed, so it is above the divisor
+    bltu(A1, A2, 12), // to next
+    sub(A1, A1, A2),  // subtract:
+    ori(A0, A0, 1),
+    addi(A3, A3, -1),   // next:
+    bne(A3, ZERO, -40), // to loop
+    jr(T0),
+];
+
+/// Writes the a1 bytes from address a0 to the UART at `0x1001_3000`, waiting whenever its
+/// transmit FIFO is full.
+const TYPE: [u32; 10] = [
+    lui(A2, 0x10013),  // UART txdata register
+    beq(A1, ZERO, 32), // loop: to done
+    lw(A3, 0, A2),     // wait:
+    blt(A3, ZERO, -4), // to wait while the FIFO is full, with bit 31 set
+    lbu(A4, 0, A0),
+    sw(A4, 0, A2),
+    addi(A0, A0, 1),
+    addi(A1, A1, -1),
+    j(-28), // to loop
+    ret(),  // done:
+];
+
+const STAR_OPERANDS: [u32; 3] = [
+    lw(A0, 4, SP), // load operands
+    lw(A1, 8, SP),
+    addi(SP, SP, 4), // reduce stack size by one cell
+];
+
+const STAR_RESULT: [u32; 2] = [
+    sw(A0, 4, SP), // store the low cell of the product
+    ret(),
+];
+
+const UM_STAR_OPERANDS: [u32; 2] = [
+    lw(A0, 4, SP), // load operands
+    lw(A1, 8, SP),
+];
+
+const UM_STAR_RESULT: [u32; 3] = [
+    sw(A0, 8, SP), // store the low cell
+    sw(A1, 4, SP), // and the high cell on top
+    ret(),
+];
+
+const TYPE_OPERANDS: [u32; 3] = [
+    lw(A1, 4, SP),   // load length
+    lw(A0, 8, SP),   // load address
+    addi(SP, SP, 8), // reduce stack size by two cells
+];
+
+/// Loads a single cell dividend sign extended into a1:a0 and the divisor into a2.
+const SINGLE_DIVIDEND: [u32; 3] = [
+    lw(A2, 4, SP),    // load divisor
+    lw(A0, 8, SP),    // load dividend
+    srai(A1, A0, 31), // sign extend it
+];
+
+/// Loads a double cell dividend into a1:a0 and the divisor into a2.
+const DOUBLE_DIVIDEND: [u32; 4] = [
+    lw(A2, 4, SP),   // load divisor
+    lw(A1, 8, SP),   // load dividend high cell
+    lw(A0, 12, SP),  // load dividend low cell
+    addi(SP, SP, 4), // reduce stack size by one cell
+];
+
+/// Replaces the dividend and divisor by their magnitudes, keeping their signs as masks in t1 and
+/// t2 respectively.
+const SIGNED_OPERANDS: [u32; 10] = [
+    srai(T1, A1, 31), // -1 if the dividend is negative
+    srai(T2, A2, 31), // -1 if the divisor is negative
+    xor(A2, A2, T2),  // negate the divisor if negative
+    sub(A2, A2, T2),
+    xor(A0, A0, T1), // negate the dividend if negative
+    xor(A1, A1, T1),
+    sub(A0, A0, T1),
+    seqz(A3, A0), // carry into the high cell
+    and(A3, A3, T1),
+    add(A1, A1, A3),
+];
+
+/// Gives the quotient the sign of the dividend times the divisor, and the remainder the sign of
+/// the dividend, for a division symmetric around zero.
+const SIGNED_RESULTS: [u32; 5] = [
+    xor(A3, T1, T2), // -1 if the quotient is negative
+    xor(A0, A0, A3),
+    sub(A0, A0, A3),
+    xor(A1, A1, T1),
+    sub(A1, A1, T1),
+];
+
+/// Turns a symmetric division into a floored one: a remainder whose sign differs from the
+/// divisor moves the quotient one further away from zero.
+const FLOOR: [u32; 7] = [
+    beq(A1, ZERO, 28), // past the end when there is no remainder
+    srai(A4, A1, 31),
+    beq(A4, T2, 20), // past the end when the remainder has the sign of the divisor
+    addi(A0, A0, -1),
+    xor(A2, A2, T2), // restore the sign of the divisor
+    sub(A2, A2, T2),
+    add(A1, A1, A2),
+];
+
+const QUOTIENT: [u32; 3] = [
+    addi(SP, SP, 4), // reduce stack size by one cell
+    sw(A0, 4, SP),   // store the quotient
+    ret(),
+];
+
+const REMAINDER: [u32; 3] = [
+    addi(SP, SP, 4), // reduce stack size by one cell
+    sw(A1, 4, SP),   // store the remainder
+    ret(),
+];
+
+const REMAINDER_QUOTIENT: [u32; 3] = [
+    sw(A1, 8, SP), // store the remainder
+    sw(A0, 4, SP), // and the quotient on top
+    ret(),
+];
+
+const MUL: [u32; 5] = [
+    lw(A0, 4, SP), // load operands
+    lw(A1, 8, SP),
+    addi(SP, SP, 4), // reduce stack size by one cell
+    mul(A0, A1, A0),
+    sw(A0, 4, SP), // store result to stack
+];
+
+const MULHU: [u32; 6] = [
+    lw(A0, 4, SP), // load operands
+    lw(A1, 8, SP),
+    mul(A2, A1, A0),
+    mulhu(A0, A1, A0),
+    sw(A2, 8, SP), // store the low cell
+    sw(A0, 4, SP), // and the high cell on top
+];
+
+const DIV: [u32; 5] = [
+    lw(A0, 4, SP), // load operands
+    lw(A1, 8, SP),
+    addi(SP, SP, 4), // reduce stack size by one cell
+    div(A0, A1, A0),
+    sw(A0, 4, SP), // store result to stack
+];
+
+const REM: [u32; 5] = [
+    lw(A0, 4, SP), // load operands
+    lw(A1, 8, SP),
+    addi(SP, SP, 4), // reduce stack size by one cell
+    rem(A0, A1, A0),
+    sw(A0, 4, SP), // store result to stack
+];
+
+const DIV_REM: [u32; 6] = [
+    lw(A0, 4, SP), // load operands
+    lw(A1, 8, SP),
+    rem(A2, A1, A0),
+    div(A0, A1, A0),
+    sw(A2, 8, SP), // store the remainder
+    sw(A0, 4, SP), // and the quotient on top
+];
+
+const CACHED_STAR_OPERANDS: [u32; 3] = [
+    mv(A0, TOS), // take operands
+    lw(A1, 4, SP),
+    addi(SP, SP, 4), // reduce stack size by one cell
+];
+
+const CACHED_STAR_RESULT: [u32; 2] = [
+    mv(TOS, A0), // leave the low cell of the product on top
+    ret(),
+];
+
+const CACHED_UM_STAR_OPERANDS: [u32; 2] = [
+    mv(A0, TOS), // take operands
+    lw(A1, 4, SP),
+];
+
+const CACHED_UM_STAR_RESULT: [u32; 3] = [
+    sw(A0, 4, SP), // store the low cell
+    mv(TOS, A1),   // and leave the high cell on top
+    ret(),
+];
+
+const CACHED_TYPE_OPERANDS: [u32; 4] = [
+    mv(A1, TOS),     // take length
+    lw(A0, 4, SP),   // load address
+    lw(TOS, 8, SP),  // load new top
+    addi(SP, SP, 8), // reduce stack size by two cells
+];
+
+const CACHED_SINGLE_DIVIDEND: [u32; 3] = [
+    mv(A2, TOS),      // take divisor
+    lw(A0, 4, SP),    // load dividend
+    srai(A1, A0, 31), // sign extend it
+];
+
+const CACHED_DOUBLE_DIVIDEND: [u32; 4] = [
+    mv(A2, TOS),     // take divisor
+    lw(A1, 4, SP),   // load dividend high cell
+    lw(A0, 8, SP),   // load dividend low cell
+    addi(SP, SP, 4), // reduce stack size by one cell
+];
+
+const CACHED_QUOTIENT: [u32; 3] = [
+    addi(SP, SP, 4), // reduce stack size by one cell
+    mv(TOS, A0),     // leave the quotient on top
+    ret(),
+];
+
+const CACHED_REMAINDER: [u32; 3] = [
+    addi(SP, SP, 4), // reduce stack size by one cell
+    mv(TOS, A1),     // leave the remainder on top
+    ret(),
+];
+
+const CACHED_REMAINDER_QUOTIENT: [u32; 3] = [
+    sw(A1, 4, SP), // store the remainder
+    mv(TOS, A0),   // and leave the quotient on top
+    ret(),
+];
+
+const CACHED_MUL: [u32; 3] = [
+    lw(A1, 4, SP),   // load left
+    addi(SP, SP, 4), // reduce stack size by one cell
+    mul(TOS, A1, TOS),
+];
+
+const CACHED_MULHU: [u32; 4] = [
+    lw(A1, 4, SP), // load left
+    mul(A2, A1, TOS),
+    mulhu(TOS, A1, TOS), // leave the high cell on top
+    sw(A2, 4, SP),       // store the low cell
+];
+
+const CACHED_DIV: [u32; 3] = [
+    lw(A1, 4, SP),   // load dividend
+    addi(SP, SP, 4), // reduce stack size by one cell
+    div(TOS, A1, TOS),
+];
+
+const CACHED_REM: [u32; 3] = [
+    lw(A1, 4, SP),   // load dividend
+    addi(SP, SP, 4), // reduce stack size by one cell
+    rem(TOS, A1, TOS),
+];
+
+const CACHED_DIV_REM: [u32; 4] = [
+    lw(A1, 4, SP), // load dividend
+    rem(A2, A1, TOS),
+    div(TOS, A1, TOS), // leave the quotient on top
+    sw(A2, 4, SP),     // store the remainder
+];
+
+/// The instructions moving operands and results between the data stack and the registers of
+/// the routines, which differ with where the top of the stack is.
+struct Stack {
+    star_operands: &'static [u32],
+    star_result: &'static [u32],
+    um_star_operands: &'static [u32],
+    um_star_result: &'static [u32],
+    type_operands: &'static [u32],
+    single_dividend: &'static [u32],
+    double_dividend: &'static [u32],
+    quotient: &'static [u32],
+    remainder: &'static [u32],
+    remainder_quotient: &'static [u32],
+    mul: &'static [u32],
+    mulhu: &'static [u32],
+    div: &'static [u32],
+    rem: &'static [u32],
+    div_rem: &'static [u32],
+}
+
+const MEMORY: Stack = Stack {
+    star_operands: &STAR_OPERANDS,
+    star_result: &STAR_RESULT,
+    um_star_operands: &UM_STAR_OPERANDS,
+    um_star_result: &UM_STAR_RESULT,
+    type_operands: &TYPE_OPERANDS,
+    single_dividend: &SINGLE_DIVIDEND,
+    double_dividend: &DOUBLE_DIVIDEND,
+    quotient: &QUOTIENT,
+    remainder: &REMAINDER,
+    remainder_quotient: &REMAINDER_QUOTIENT,
+    mul: &MUL,
+    mulhu: &MULHU,
+    div: &DIV,
+    rem: &REM,
+    div_rem: &DIV_REM,
+};
+
+const CACHED: Stack = Stack {
+    star_operands: &CACHED_STAR_OPERANDS,
+    star_result: &CACHED_STAR_RESULT,
+    um_star_operands: &CACHED_UM_STAR_OPERANDS,
+    um_star_result: &CACHED_UM_STAR_RESULT,
+    type_operands: &CACHED_TYPE_OPERANDS,
+    single_dividend: &CACHED_SINGLE_DIVIDEND,
+    double_dividend: &CACHED_DOUBLE_DIVIDEND,
+    quotient: &CACHED_QUOTIENT,
+    remainder: &CACHED_REMAINDER,
+    remainder_quotient: &CACHED_REMAINDER_QUOTIENT,
+    mul: &CACHED_MUL,
+    mulhu: &CACHED_MULHU,
+    div: &CACHED_DIV,
+    rem: &CACHED_REM,
+    div_rem: &CACHED_DIV_REM,
+};
+
+fn stack(top: TopOfStack) -> &'static Stack {
+    match top {
+        TopOfStack::Memory => &MEMORY,
+        TopOfStack::Register => &CACHED,
+    }
+}
+
+const ROUTINES: usize = 11;
+
+/// Words too long to be inlined at every use. They are compiled once per output, the first time
+/// they are used, and called with `jal` afterwards.
+#[derive(Clone, Copy)]
+pub enum Routine {
+    Multiply,
+    Divide,
+    Star,
+    UMStar,
+    Slash,
+    Mod,
+    SlashMod,
+    UMSlashMod,
+    SMRem,
+    FMSlashMod,
+    Type,
+}
+
+impl Routine {
+    /// Cells the routine takes from the data stack and leaves on it.
+    pub fn effect(self) -> StackEffect {
+        use Routine::*;
+        let (inputs, outputs) = match self {
+            Multiply | Divide => (0, 0),
+            Star | Slash | Mod => (2, 1),
+            UMStar | SlashMod => (2, 2),
+            UMSlashMod | SMRem | FMSlashMod => (3, 2),
+            Type => (2, 0),
+        };
+        StackEffect::new(inputs, outputs)
+    }
+
+    /// Instructions of the M extension doing the work of the routine in place, when `target`
+    /// has them. Double cell divisions are left to the routines.
+    pub fn native(self, target: Target, top: TopOfStack) -> Option<&'static [u32]> {
+        if !target.multiply() {
+            return None;
+        }
+        let stack = stack(top);
+        match self {
+            Routine::Star => Some(stack.mul),
+            Routine::UMStar => Some(stack.mulhu),
+            Routine::Slash => Some(stack.div),
+            Routine::Mod => Some(stack.rem),
+            Routine::SlashMod => Some(stack.div_rem),
+            _ => None,
+        }
+    }
+
+    /// The instructions of the routine, around a call with `jal t0` to the arithmetic routine
+    /// shared with other words.
+    fn parts(self, top: TopOfStack) -> ([&'static [u32]; 2], Option<Routine>, [&'static [u32]; 3]) {
+        use Routine::*;
+        let stack = stack(top);
+        match self {
+            Multiply => ([&MULTIPLY, &[]], None, [&[], &[], &[]]),
+            Divide => ([&DIVIDE, &[]], None, [&[], &[], &[]]),
+            Star => (
+                [stack.star_operands, &[]],
+                Some(Multiply),
+                [stack.star_result, &[], &[]],
+            ),
+            UMStar => (
+                [stack.um_star_operands, &[]],
+                Some(Multiply),
+                [stack.um_star_result, &[], &[]],
+            ),
+            Slash => (
+                [stack.single_dividend, &SIGNED_OPERANDS],
+                Some(Divide),
+                [&SIGNED_RESULTS, stack.quotient, &[]],
+            ),
+            Mod => (
+                [stack.single_dividend, &SIGNED_OPERANDS],
+                Some(Divide),
+                [&SIGNED_RESULTS, stack.remainder, &[]],
+            ),
+            SlashMod => (
+                [stack.single_dividend, &SIGNED_OPERANDS],
+                Some(Divide),
+                [&SIGNED_RESULTS, stack.remainder_quotient, &[]],
+            ),
+            UMSlashMod => (
+                [stack.double_dividend, &[]],
+                Some(Divide),
+                [stack.remainder_quotient, &[], &[]],
+            ),
+            SMRem => (
+                [stack.double_dividend, &SIGNED_OPERANDS],
+                Some(Divide),
+                [&SIGNED_RESULTS, stack.remainder_quotient, &[]],
+            ),
+            FMSlashMod => (
+                [stack.double_dividend, &SIGNED_OPERANDS],
+                Some(Divide),
+                [&SIGNED_RESULTS, &FLOOR, stack.remainder_quotient],
+            ),
+            Type => ([stack.type_operands, &TYPE], None, [&[], &[], &[]]),
+        }
+    }
+}
+
+impl FromStr for Routine {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Routine, ()> {
+        use Routine::*;
+        match s {
+            "*" => Ok(Star),
+            "UM*" => Ok(UMStar),
+            "/" => Ok(Slash),
+            "MOD" => Ok(Mod),
+            "/MOD" => Ok(SlashMod),
+            "UM/MOD" => Ok(UMSlashMod),
+            "SM/REM" => Ok(SMRem),
+            "FM/MOD" => Ok(FMSlashMod),
+            "TYPE" => Ok(Type),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Where the routines used so far were compiled in the current output.
+pub struct Routines {
+    addresses: [Option<usize>; ROUTINES],
+}
+
+impl Routines {
+    pub fn new() -> Self {
+        Routines {
+            addresses: [None; ROUTINES],
+        }
+    }
+
+    /// Offset in `output` of the first instruction of `routine`, compiling it behind a jump
+    /// that skips over it when used for the first time.
+    ///
+    /// Routines are kept in their 32 bit form since the branches within them were worked out
+    /// by hand.
+    pub fn address(
+        &mut self,
+        routine: Routine,
+        top: TopOfStack,
+        output: &mut Output,
+    ) -> Result<usize, CompilerError> {
+        if let Some(address) = self.addresses[routine as usize] {
+            return Ok(address);
+        }
+        let (before, shared, after) = routine.parts(top);
+        let shared = shared
+            .map(|shared| self.address(shared, top, output))
+            .transpose()?;
+
+        let skip = output.len();
+        output.emit_fixed(&[0])?;
+        let address = output.len();
+        for instructions in before {
+            output.emit_fixed(instructions)?;
+        }
+        if let Some(shared) = shared {
+            let offset = shared as i32 - output.len() as i32;
+            let call = jal(T0, offset).ok_or(CompilerError::BranchOutOfRange)?;
+            output.emit_fixed(&[call])?;
+        }
+        for instructions in after {
+            output.emit_fixed(instructions)?;
+        }
+        let len = output.len();
+        Control::Else(skip).resolve(output.as_mut_slice(), len)?;
+
+        self.addresses[routine as usize] = Some(address);
+        Ok(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::split_immediate;
+    use crate::Emulator;
+
+    /// Calls `routine` with a0, a1 and a2 set to `args`, returning what it leaves in a0 and a1.
+    fn call(routine: &[u32], args: [u32; 3]) -> (u32, u32) {
+        let mut code = [0; 64];
+        let mut len = 0;
+        let mut emit = |instructions: &[u32]| {
+            code[len..len + instructions.len()].copy_from_slice(instructions);
+            len += instructions.len();
+        };
+        emit(&[j(4 + 4 * routine.len() as i32)]);
+        emit(routine);
+        for (register, value) in [A0, A1, A2].into_iter().zip(args) {
+            let (upper, lower) = split_immediate(value);
+            emit(&[lui(register, upper), addi(register, register, lower)]);
+        }
+        // The routine starts right after the jump over it
+        let call = 4 * (1 + routine.len() + 2 * args.len()) as i32;
+        emit(&[jal(T0, 4 - call).unwrap()]);
+
+        let mut bytes = [0; 256];
+        for (bytes, instruction) in bytes.chunks_mut(4).zip(&code[..len]) {
+            bytes.copy_from_slice(&instruction.to_le_bytes());
+        }
+        let mut memory = [0; 1024];
+        let mut emulator = Emulator::new(&mut memory, TopOfStack::Memory);
+        emulator.load(0, &bytes[..len * 4]).unwrap();
+        emulator.run(0, len * 4).unwrap();
+        (emulator.register(A0), emulator.register(A1))
+    }
+
+    const CELLS: [u32; 9] = [
+        0,
+        1,
+        2,
+        3,
+        0x1234_5678,
+        0x7fff_ffff,
+        0x8000_0000,
+        0xdead_beef,
+        0xffff_ffff,
+    ];
+
+    #[test]
+    fn multiply_leaves_the_double_cell_product() {
+        for a in CELLS {
+            for b in CELLS {
+                let product = a as u64 * b as u64;
+                let expected = (product as u32, (product >> 32) as u32);
+                assert_eq!(call(&MULTIPLY, [a, b, 0]), expected, "{a:#x} * {b:#x}");
+            }
+        }
+    }
+
+    #[test]
+    fn divide_leaves_the_quotient_and_remainder() {
+        for high in CELLS {
+            for low in CELLS {
+                // The quotient fits in a cell as long as the high cell is below the divisor
+                for divisor in CELLS.into_iter().filter(|&divisor| divisor > high) {
+                    let dividend = (high as u64) << 32 | low as u64;
+                    let quotient = (dividend / divisor as u64) as u32;
+                    let remainder = (dividend % divisor as u64) as u32;
+                    let result = call(&DIVIDE, [low, high, divisor]);
+                    let division = format_args!("{high:#x}:{low:#x} / {divisor:#x}");
+                    assert_eq!(result, (quotient, remainder), "{division}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn routines_are_compiled_once_and_share_the_arithmetic() {
+        let mut code = [0; 1024];
+        let mut output = Output::new(&mut code, Target::RV32I, false);
+        let mut routines = Routines::new();
+        let top = TopOfStack::Memory;
+        let star = routines.address(Routine::Star, top, &mut output).unwrap();
+        let slash = routines.address(Routine::Slash, top, &mut output).unwrap();
+        let len = output.len();
+        assert_eq!(routines.address(Routine::Star, top, &mut output), Ok(star));
+        assert_eq!(output.len(), len);
+
+        // Multiply went first, behind a jump of its own
+        let multiply = routines
+            .address(Routine::Multiply, top, &mut output)
+            .unwrap();
+        let divide = routines.address(Routine::Divide, top, &mut output).unwrap();
+        assert_eq!(multiply, 4);
+        assert_eq!(output.len(), len);
+        let call = |at: usize, to: usize| {
+            let instruction = output.as_slice()[at..at + 4].try_into().unwrap();
+            let expected = jal(T0, to as i32 - at as i32);
+            assert_eq!(Some(u32::from_le_bytes(instruction)), expected);
+        };
+        call(star + STAR_OPERANDS.len() * 4, multiply);
+        call(
+            slash + (SINGLE_DIVIDEND.len() + SIGNED_OPERANDS.len()) * 4,
+            divide,
+        );
+    }
+}
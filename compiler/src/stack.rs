@@ -0,0 +1,230 @@
+use crate::primitives::{self, Primitive};
+
+/// Where the compiled code keeps the top cell of the data stack.
+///
+/// Words compiled with one convention can't be called from code compiled with the other, so a
+/// dictionary is meant to be extended with the convention it was started with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TopOfStack {
+    /// In memory at 4(sp), like the cells below it.
+    #[default]
+    Memory,
+    /// In s1, the cells below it being in memory from 4(sp) on. Words then load a single operand
+    /// and store nothing, but the first cell in memory holds whatever s1 held before the first
+    /// push.
+    Register,
+}
+
+impl TopOfStack {
+    /// The instructions of `primitive` under the convention.
+    pub fn instructions(self, primitive: &Primitive) -> (usize, [u32; 8]) {
+        match self {
+            TopOfStack::Memory => primitive.get_instructions(),
+            TopOfStack::Register => primitive.get_cached_instructions(),
+        }
+    }
+
+    /// Pops the top of the data stack into a0.
+    pub fn pop_a0(self) -> &'static [u32] {
+        match self {
+            TopOfStack::Memory => &primitives::POP_A0,
+            TopOfStack::Register => &primitives::CACHED_POP_A0,
+        }
+    }
+
+    /// Pushes a0 on the data stack.
+    pub fn push_a0(self) -> &'static [u32] {
+        match self {
+            TopOfStack::Memory => &primitives::PUSH_A0,
+            TopOfStack::Register => &primitives::CACHED_PUSH_A0,
+        }
+    }
+
+    /// Moves the limit and index of a counted loop to the return stack.
+    pub fn loop_params(self) -> &'static [u32] {
+        match self {
+            TopOfStack::Memory => &primitives::LOOP_PARAMS,
+            TopOfStack::Register => &primitives::CACHED_LOOP_PARAMS,
+        }
+    }
+
+    /// Increments the index of a counted loop by the top of the data stack.
+    pub fn plus_loop_step(self) -> &'static [u32] {
+        match self {
+            TopOfStack::Memory => &primitives::PLUS_LOOP_STEP,
+            TopOfStack::Register => &primitives::CACHED_PLUS_LOOP_STEP,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{addi, lui, ret, sw, A0, FP};
+    use crate::emulator::Emulator;
+    use crate::primitives::split_immediate;
+    use Primitive::*;
+
+    /// Where the code run by [`check`] is loaded, above the cell `!` and `@` are tried on.
+    const CODE: u32 = 0x400;
+    /// Where the word `BRANCH` is tried on is loaded, pushing 5.
+    const ROUTINE: u32 = 0x200;
+
+    struct Code {
+        bytes: [u8; 512],
+        len: usize,
+    }
+
+    impl Code {
+        fn new() -> Self {
+            Code {
+                bytes: [0; 512],
+                len: 0,
+            }
+        }
+
+        fn emit(&mut self, instructions: &[u32]) {
+            for instruction in instructions {
+                self.bytes[self.len..self.len + 4].copy_from_slice(&instruction.to_le_bytes());
+                self.len += 4;
+            }
+        }
+
+        fn load_a0(&mut self, value: u32) {
+            let (upper, lower) = split_immediate(value);
+            self.emit(&[lui(A0, upper), addi(A0, A0, lower)]);
+        }
+
+        fn as_slice(&self) -> &[u8] {
+            &self.bytes[..self.len]
+        }
+    }
+
+    /// Runs `primitives` with the data and return stacks holding `data` and `returns` from the
+    /// bottom up, under both conventions, checking that they hold `expected` afterwards.
+    fn check(primitives: &[Primitive], data: &[u32], returns: &[u32], expected: [&[u32]; 2]) {
+        for top in [TopOfStack::Memory, TopOfStack::Register] {
+            let mut routine = Code::new();
+            routine.load_a0(5);
+            routine.emit(top.push_a0());
+            routine.emit(&[ret()]);
+
+            let mut code = Code::new();
+            for cell in data {
+                code.load_a0(*cell);
+                code.emit(top.push_a0());
+            }
+            for cell in returns {
+                code.load_a0(*cell);
+                code.emit(&[sw(A0, 0, FP), addi(FP, FP, -4)]);
+            }
+            for primitive in primitives {
+                let (len, instructions) = top.instructions(primitive);
+                code.emit(&instructions[..len]);
+            }
+
+            let mut memory = [0; 0x1000];
+            let mut emulator = Emulator::new(&mut memory, top);
+            emulator.load(ROUTINE, routine.as_slice()).unwrap();
+            emulator.load(CODE, code.as_slice()).unwrap();
+            emulator.run(CODE, code.len).unwrap();
+            let [data, returns] = expected;
+            assert!(emulator.data_stack().eq(data.iter().copied()), "{top:?}");
+            assert!(
+                emulator.return_stack().eq(returns.iter().copied()),
+                "{top:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn push_leaves_its_value_on_top() {
+        for value in [0, 2047, 0x800, 0xffff_f7ff, 0xffff_ffff, 0x1234_5678] {
+            check(&[Push(value)], &[9], &[], [&[9, value], &[]]);
+        }
+    }
+
+    #[test]
+    fn arithmetic_replaces_its_operands_by_the_result() {
+        let cases: [(Primitive, u32, u32, u32); 8] = [
+            (Add, 3, 4, 7),
+            (Sub, 10, 3, 7),
+            (Sub, 3, 10, -7i32 as u32),
+            (And, 6, 3, 2),
+            (Or, 6, 3, 7),
+            (Xor, 6, 3, 5),
+            (LShift, 1, 4, 16),
+            (RShift, -16i32 as u32, 2, 0x3fff_fffc),
+        ];
+        for (primitive, a, b, result) in cases {
+            check(&[primitive], &[9, a, b], &[], [&[9, result], &[]]);
+        }
+    }
+
+    #[test]
+    fn comparisons_leave_a_flag() {
+        const TRUE: u32 = u32::MAX;
+        let cases: [(Primitive, u32, u32, u32); 8] = [
+            (Eq, 3, 3, TRUE),
+            (Eq, 3, 4, 0),
+            (Lt, 1, 2, TRUE),
+            (Lt, 2, 1, 0),
+            (Lt, -1i32 as u32, 1, TRUE),
+            (Gt, 2, 1, TRUE),
+            (Gt, 1, 2, 0),
+            (Gt, 1, -1i32 as u32, TRUE),
+        ];
+        for (primitive, a, b, flag) in cases {
+            check(&[primitive], &[9, a, b], &[], [&[9, flag], &[]]);
+        }
+    }
+
+    #[test]
+    fn memory_is_stored_to_and_fetched_from() {
+        check(&[Load], &[9, 42, 0x100], &[], [&[9], &[]]);
+        check(
+            &[Load, Push(0x100), Fetch],
+            &[9, 42, 0x100],
+            &[],
+            [&[9, 42], &[]],
+        );
+    }
+
+    #[test]
+    fn return_stack_takes_and_gives_back_cells() {
+        check(&[RTo], &[9, 5], &[1], [&[9], &[1, 5]]);
+        check(&[RFrom], &[9], &[1, 5], [&[9, 5], &[1]]);
+        // Loop parameters are pushed limit first, so the index is on top
+        check(&[I], &[9], &[10, 3], [&[9, 3], &[10, 3]]);
+        check(&[J], &[9], &[10, 2, 20, 7], [&[9, 2], &[10, 2, 20, 7]]);
+        check(&[Unloop], &[9], &[1, 10, 3], [&[9], &[1]]);
+    }
+
+    #[test]
+    fn branch_calls_the_address_on_top() {
+        check(&[Branch], &[9, ROUTINE], &[1], [&[9, 5], &[1]]);
+    }
+
+    fn stores(instruction: u32) -> bool {
+        instruction & 0x7f == 0b0100011
+    }
+
+    #[test]
+    fn caching_the_top_spares_the_stores_of_operations() {
+        for primitive in [Add, Sub, And, Or, Xor, LShift, RShift, Eq, Lt, Gt] {
+            let (memory, _) = TopOfStack::Memory.instructions(&primitive);
+            let (len, instructions) = TopOfStack::Register.instructions(&primitive);
+            assert!(len < memory);
+            assert!(!instructions[..len]
+                .iter()
+                .any(|instruction| stores(*instruction)));
+        }
+    }
+
+    #[test]
+    fn the_cached_top_moves_through_a0() {
+        // mv s1, a0 once the previous top is spilled, mv a0, s1 before the next one is loaded
+        assert_eq!(TopOfStack::Register.push_a0().last(), Some(&0x0005_0493));
+        assert_eq!(TopOfStack::Register.pop_a0().first(), Some(&0x0004_8513));
+    }
+}
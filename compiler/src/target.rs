@@ -0,0 +1,279 @@
+use crate::assembler::{
+    c_add, c_addi, c_and, c_andi, c_jr, c_li, c_lui, c_lw, c_lwsp, c_mv, c_or, c_slli, c_srai,
+    c_srli, c_sub, c_sw, c_swsp, c_xor, SP, ZERO,
+};
+use crate::disassembler::{Instruction, Operation, Width};
+
+/// The instruction set the compiled code runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Target {
+    /// Base integer instructions only, multiplication and division run in software.
+    #[default]
+    RV32I,
+    /// With the M extension, multiplying and dividing single cells with `mul` and `div`.
+    RV32IM,
+    /// With the M and C extensions, using 16 bit encodings wherever they exist.
+    RV32IMC,
+}
+
+impl Target {
+    /// Whether the target has the `mul` and `div` families of instructions.
+    pub fn multiply(self) -> bool {
+        self != Target::RV32I
+    }
+
+    /// Whether the target runs compressed instructions.
+    pub fn compressed(self) -> bool {
+        self == Target::RV32IMC
+    }
+
+    /// Size in bytes of `instructions` once emitted for the target.
+    pub fn size(self, instructions: &[u32]) -> usize {
+        instructions
+            .iter()
+            .map(|instruction| match self.compress(*instruction) {
+                Some(_) => 2,
+                None => 4,
+            })
+            .sum()
+    }
+
+    /// The 16 bit form of `instruction`, when the target has one.
+    pub fn compress(self, instruction: u32) -> Option<u16> {
+        if !self.compressed() {
+            return None;
+        }
+        compress(instruction)
+    }
+}
+
+/// Compressed form of an instruction.
+///
+/// Branches and jumps are left alone: their offsets are patched once the target is known, and
+/// relocations expect the 32 bit forms.
+fn compress(instruction: u32) -> Option<u16> {
+    use Operation::*;
+    let compressed = match Instruction::decode(instruction)? {
+        Instruction::Load {
+            width: Width::Word,
+            rd,
+            rs1: SP,
+            offset,
+        } if rd != ZERO && scaled(offset, 4, 256) => c_lwsp(rd, offset),
+        Instruction::Load {
+            width: Width::Word,
+            rd,
+            rs1,
+            offset,
+        } if prime(rs1) && prime(rd) && scaled(offset, 4, 128) => c_lw(rd, offset, rs1),
+        Instruction::Store {
+            width: Width::Word,
+            rs1: SP,
+            rs2,
+            offset,
+        } if scaled(offset, 4, 256) => c_swsp(rs2, offset),
+        Instruction::Store {
+            width: Width::Word,
+            rs1,
+            rs2,
+            offset,
+        } if prime(rs1) && prime(rs2) && scaled(offset, 4, 128) => c_sw(rs2, offset, rs1),
+        Instruction::Immediate {
+            operation: Add,
+            rd,
+            rs1: ZERO,
+            imm,
+        } if rd != ZERO && small(imm) => c_li(rd, imm),
+        Instruction::Immediate {
+            operation: Add,
+            rd,
+            rs1,
+            imm: 0,
+        } if rs1 != ZERO && rd != ZERO => c_mv(rd, rs1),
+        Instruction::Immediate {
+            operation,
+            rd,
+            rs1,
+            imm,
+        } if rd == rs1 && rd != ZERO => match operation {
+            Add if imm != 0 && small(imm) => c_addi(rd, imm),
+            Sll if imm != 0 => c_slli(rd, imm as u32),
+            Srl if imm != 0 && prime(rd) => c_srli(rd, imm as u32),
+            Sra if imm != 0 && prime(rd) => c_srai(rd, imm as u32),
+            And if prime(rd) && small(imm) => c_andi(rd, imm),
+            _ => return None,
+        },
+        // The upper 20 bits sign extended
+        Instruction::Lui { rd, imm }
+            if rd != ZERO && rd != SP && imm != 0 && small((imm << 12) as i32 >> 12) =>
+        {
+            c_lui(rd, imm)
+        }
+        // Commutative, so either source may be the destination
+        Instruction::Register {
+            operation: Add,
+            rd,
+            rs1,
+            rs2,
+        } if rd != ZERO && rs1 != ZERO && rs2 != ZERO && (rd == rs1 || rd == rs2) => {
+            c_add(rd, if rd == rs1 { rs2 } else { rs1 })
+        }
+        Instruction::Register {
+            operation: Add,
+            rd,
+            rs1: ZERO,
+            rs2,
+        } if rd != ZERO && rs2 != ZERO => c_mv(rd, rs2),
+        Instruction::Register {
+            operation,
+            rd,
+            rs1,
+            rs2,
+        } if rd == rs1 && prime(rd) && prime(rs2) => match operation {
+            Sub => c_sub(rd, rs2),
+            Xor => c_xor(rd, rs2),
+            Or => c_or(rd, rs2),
+            And => c_and(rd, rs2),
+            _ => return None,
+        },
+        Instruction::Jalr {
+            rd: ZERO,
+            rs1,
+            offset: 0,
+        } if rs1 != ZERO => c_jr(rs1),
+        _ => return None,
+    };
+    Some(compressed)
+}
+
+/// Whether `register` is one of x8 to x15, the only ones most compressed instructions reach.
+fn prime(register: u32) -> bool {
+    (8..16).contains(&register)
+}
+
+/// Whether `imm` fits the signed 6 bit immediate of the compressed instructions.
+fn small(imm: i32) -> bool {
+    (-32..32).contains(&imm)
+}
+
+/// Whether `imm` is a positive multiple of `scale` below `limit`.
+fn scaled(imm: i32, scale: i32, limit: i32) -> bool {
+    (0..limit).contains(&imm) && imm % scale == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Target;
+    use crate::assembler::*;
+    use crate::disassembler::{Instruction, Operation};
+
+    const REGISTERS: [u32; 9] = [ZERO, RA, SP, T0, FP, S1, A0, A5, 31];
+    const IMMEDIATES: [i32; 15] = [
+        -2048, -33, -32, -4, -1, 0, 1, 4, 31, 32, 64, 124, 128, 252, 2047,
+    ];
+
+    /// Every instruction the assembler builds from the operands above.
+    fn instructions() -> impl Iterator<Item = u32> {
+        let r_type = [add, sub, sll, xor, srl, or, and, mul];
+        let i_type = [addi, sltiu, ori, andi];
+        let shifts = [slli, srli, srai];
+        REGISTERS.into_iter().flat_map(move |rd| {
+            REGISTERS.into_iter().flat_map(move |rs| {
+                let registers = REGISTERS
+                    .into_iter()
+                    .flat_map(move |rs2| r_type.into_iter().map(move |op| op(rd, rs, rs2)));
+                let immediates = IMMEDIATES.into_iter().flat_map(move |imm| {
+                    let i_type = i_type.into_iter().map(move |op| op(rd, rs, imm));
+                    [lw(rd, imm, rs), sw(rd, imm, rs), jalr(rd, imm, rs)]
+                        .into_iter()
+                        .chain(i_type)
+                        .chain([lui(rd, imm as u32 & 0xfffff)])
+                });
+                let shifts = [0, 1, 31]
+                    .into_iter()
+                    .flat_map(move |shamt| shifts.into_iter().map(move |op| op(rd, rs, shamt)));
+                registers.chain(immediates).chain(shifts)
+            })
+        })
+    }
+
+    /// Writes `mv` as `add` and orders the sources of `add`, which compressed forms swap.
+    fn canonical(instruction: Instruction) -> Instruction {
+        match instruction {
+            Instruction::Immediate {
+                operation: Operation::Add,
+                rd,
+                rs1,
+                imm: 0,
+            } if rs1 != ZERO => Instruction::Register {
+                operation: Operation::Add,
+                rd,
+                rs1: ZERO,
+                rs2: rs1,
+            },
+            Instruction::Register {
+                operation: Operation::Add,
+                rd,
+                rs1,
+                rs2,
+            } => Instruction::Register {
+                operation: Operation::Add,
+                rd,
+                rs1: rs1.min(rs2),
+                rs2: rs1.max(rs2),
+            },
+            instruction => instruction,
+        }
+    }
+
+    #[test]
+    fn compressed_forms_decode_to_the_instruction_compressed() {
+        let mut compressed = 0;
+        for instruction in instructions() {
+            let Some(short) = Target::RV32IMC.compress(instruction) else {
+                continue;
+            };
+            let expected = Instruction::decode(instruction).map(canonical);
+            let decoded = Instruction::decode_compressed(short).map(canonical);
+            assert_eq!(decoded, expected, "{instruction:#010x} {short:#06x}");
+            compressed += 1;
+        }
+        assert!(compressed > 1000, "{compressed}");
+    }
+
+    #[test]
+    fn stack_traffic_is_compressed() {
+        for instruction in [
+            lw(A0, 4, SP),
+            sw(A0, 0, SP),
+            lw(A0, 4, FP),
+            sw(S1, 8, FP),
+            addi(SP, SP, -4),
+            addi(SP, SP, 4),
+            mv(A0, S1),
+            li(A0, -1),
+            add(A0, A0, A1),
+            and(A0, A0, A1),
+            jr(T0),
+            ret(),
+        ] {
+            assert!(
+                Target::RV32IMC.compress(instruction).is_some(),
+                "{instruction:#010x}"
+            );
+            assert_eq!(Target::RV32IM.compress(instruction), None);
+        }
+        assert_eq!(Target::RV32IMC.size(&[lw(A0, 4, SP), lw(A0, 4, A0)]), 4);
+        assert_eq!(
+            Target::RV32IMC.size(&[lw(A0, 256, SP), beq(A0, ZERO, 8)]),
+            8
+        );
+    }
+
+    #[test]
+    fn branches_and_jumps_keep_their_full_form() {
+        for instruction in [beq(A0, ZERO, 8), j(8), jal(RA, 8).unwrap(), auipc(RA, 0)] {
+            assert_eq!(Target::RV32IMC.compress(instruction), None);
+        }
+    }
+}
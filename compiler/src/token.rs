@@ -0,0 +1,129 @@
+use core::ops::Range;
+
+/// The words of a source, separated by ASCII whitespace, remembering where the last one read
+/// lies in the source.
+pub struct Tokens<'a> {
+    source: &'a str,
+    position: usize,
+    span: Range<usize>,
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Tokens {
+            source,
+            position: 0,
+            span: 0..0,
+        }
+    }
+
+    /// Byte range of the token read last, empty at the end of the source once they ran out.
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    /// Offset in the source the next token is looked for from.
+    pub fn offset(&self) -> usize {
+        self.position
+    }
+
+    /// Goes back, or forward, to a position returned by [`offset`](Self::offset).
+    pub fn seek(&mut self, position: usize) {
+        self.position = position;
+    }
+
+    /// Reads the next token only when it is `expected`.
+    pub fn next_if_eq(&mut self, expected: &str) -> Option<&'a str> {
+        let (span, position) = self.peek_span()?;
+        let token = &self.source[span.clone()];
+        if token != expected {
+            return None;
+        }
+        self.span = span;
+        self.position = position;
+        Some(token)
+    }
+
+    /// Reads the source following the token read last up to `delimiter`, as for comments and
+    /// strings, skipping the delimiter. The text starts past the whitespace character ending the
+    /// token, unless that is the delimiter itself. Fails when the delimiter never comes.
+    pub fn parse(&mut self, delimiter: char) -> Option<&'a str> {
+        let mut start = self.position;
+        if !self.source[start..].starts_with(delimiter) {
+            start = (start + 1).min(self.source.len());
+        }
+        let Some(len) = self.source[start..].find(delimiter) else {
+            self.position = self.source.len();
+            return None;
+        };
+        self.span = start..start + len;
+        self.position = start + len + delimiter.len_utf8();
+        Some(&self.source[self.span()])
+    }
+
+    /// Span of the next token and the position following it.
+    fn peek_span(&self) -> Option<(Range<usize>, usize)> {
+        let rest = &self.source.as_bytes()[self.position..];
+        let start = self.position + rest.iter().position(|b| !b.is_ascii_whitespace())?;
+        let len = self.source.as_bytes()[start..]
+            .iter()
+            .position(u8::is_ascii_whitespace)
+            .unwrap_or(self.source.len() - start);
+        Some((start..start + len, start + len))
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let Some((span, position)) = self.peek_span() else {
+            self.position = self.source.len();
+            self.span = self.position..self.position;
+            return None;
+        };
+        self.span = span.clone();
+        self.position = position;
+        Some(&self.source[span])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_past_the_whitespace_ending_the_token() {
+        let mut tokens = Tokens::new("S\"  two words\" next");
+        assert_eq!(tokens.next(), Some("S\""));
+        assert_eq!(tokens.parse('"'), Some(" two words"));
+        assert_eq!(tokens.span(), 3..13);
+        assert_eq!(tokens.next(), Some("next"));
+
+        // Right after the token, the text may be empty or start on the next line
+        let mut tokens = Tokens::new("( ) (\n1 2 ) 3");
+        assert_eq!(tokens.next(), Some("("));
+        assert_eq!(tokens.parse(')'), Some(""));
+        assert_eq!(tokens.next(), Some("("));
+        assert_eq!(tokens.parse(')'), Some("1 2 "));
+        assert_eq!(tokens.next(), Some("3"));
+    }
+
+    #[test]
+    fn parse_without_a_delimiter_reaches_the_end() {
+        let mut tokens = Tokens::new("1 S\" abc");
+        tokens.next();
+        assert_eq!(tokens.next(), Some("S\""));
+        assert_eq!(tokens.parse('"'), None);
+        // The token failing stays the one read last
+        assert_eq!(tokens.span(), 2..4);
+        assert_eq!(tokens.next(), None);
+        assert_eq!(tokens.span(), 8..8);
+
+        let mut tokens = Tokens::new("1 \\");
+        tokens.next();
+        tokens.next();
+        assert_eq!(tokens.parse('\n'), None);
+        assert_eq!(tokens.next(), None);
+    }
+}
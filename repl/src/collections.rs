@@ -1,7 +1,6 @@
 use core::alloc::Layout;
 use alloc::alloc::{dealloc, realloc, alloc};
 use core::hash::{Hash, Hasher};
-use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
 use crate::hash::DJB2;
 
@@ -148,18 +147,17 @@ impl<T> DerefMut for Vec<T> {
     }
 }
 
-pub struct Map<K: Hash, V> {
-    keys: Vec<usize>,
+pub struct Map<K: Hash + Eq, V> {
+    /// Hash of each key along with the key itself, as different keys may share a hash.
+    keys: Vec<(usize, K)>,
     values: Vec<V>,
-    _k: PhantomData<K>,
 }
 
-impl<K: Hash, V> Map<K, V> {
+impl<K: Hash + Eq, V> Map<K, V> {
     pub fn new() -> Self {
         Self {
             keys: Vec::new(),
             values: Vec::new(),
-            _k: PhantomData,
         }
     }
 
@@ -167,7 +165,7 @@ impl<K: Hash, V> Map<K, V> {
         let mut hasher = DJB2::new();
         key.hash(&mut hasher);
         let k = hasher.finish() as usize;
-        self.keys.push(k);
+        self.keys.push((k, key));
         self.values.push(value);
     }
 
@@ -177,7 +175,7 @@ impl<K: Hash, V> Map<K, V> {
         let hash = hasher.finish() as usize;
 
         for k in 0..self.keys.len() {
-            if self.keys[k] == hash {
+            if self.keys[k].0 == hash && self.keys[k].1 == key {
                 return self.values.get(k);
             }
         }